@@ -0,0 +1,56 @@
+//! Compares the time taken to repeatedly evaluate an expression through each
+//! of the compiled forms. Run with `cargo bench`.
+
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use geqslib::shunting::{compile_to_bytecode, compile_to_fn_of_hashmap, new_context, ContextLike};
+
+const EXPR: &str = "p * v - n * 8.314 * t + if(t > 300, sin(t / 100), cos(t / 100)) * max(p, v, 1) / hypot(p, v)";
+const ITERATIONS: u32 = 1_000_000;
+
+/// Times `ITERATIONS` calls of `f`, sweeping `t` across a range of values.
+fn time(mut f: impl FnMut(f64) -> f64) -> Duration
+{
+    let start = Instant::now();
+    for i in 0..ITERATIONS
+    {
+        black_box(f(250.0 + (i % 100) as f64));
+    }
+    start.elapsed()
+}
+
+fn main()
+{
+    let mut ctx = new_context();
+    for var in ["p", "v", "n", "t"]
+    {
+        ctx.add_var_to_ctx(var, 1.0);
+    }
+
+    let closure = compile_to_fn_of_hashmap(EXPR, &ctx).unwrap();
+    let mut inputs = HashMap::from([
+        ("p".to_owned(), 101.325),
+        ("v".to_owned(), 0.0224),
+        ("n".to_owned(), 1.0),
+        ("t".to_owned(), 0.0),
+    ]);
+    let closure_time = time(|t| {
+        inputs.insert("t".to_owned(), t);
+        closure(&inputs).unwrap()
+    });
+
+    let mut bytecode = compile_to_bytecode(EXPR, &ctx, &["p", "v", "n", "t"]).unwrap();
+    let mut slots = [101.325, 0.0224, 1.0, 0.0];
+    let bytecode_time = time(|t| {
+        slots[3] = t;
+        bytecode.eval(&slots).unwrap()
+    });
+
+    let per_call = |d: Duration| d.as_nanos() as f64 / ITERATIONS as f64;
+    println!("{EXPR}");
+    println!("compile_to_fn_of_hashmap: {:>8.1} ns/eval", per_call(closure_time));
+    println!("compile_to_bytecode:      {:>8.1} ns/eval", per_call(bytecode_time));
+    println!("speedup:                  {:>8.1}x", closure_time.as_secs_f64() / bytecode_time.as_secs_f64());
+}
@@ -0,0 +1,316 @@
+use crate::context::{ContextFn, ContextHashMap, Token};
+use crate::errors::{ExpressionCompilationError, ShuntingYardError};
+use crate::expr::{BinaryOp, Expr, UnaryOp};
+
+/// A single instruction of a `Bytecode` program. Operands are read from and
+/// written to the top of the program's stack.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Instr
+{
+    /// Pushes a number
+    Push(f64),
+    /// Pushes the value of the variable in the given slot
+    Load(usize),
+    Unary(UnaryOp),
+    Binary(BinaryOp),
+    /// Calls the function at the given index with the given number of arguments
+    Call(usize, usize),
+    /// Jumps to the given instruction
+    Jump(usize),
+    /// Pops a value and jumps to the given instruction if it is false
+    JumpIfFalse(usize),
+    /// Pops a value and jumps to the given instruction if it is true
+    JumpIfTrue(usize),
+    /// Replaces the top of the stack with `1.0` if it is true or `0.0` if it is false
+    Truthy,
+}
+
+/// An expression compiled to a flat list of instructions for fast repeated evaluation.
+///
+/// Variables are read from a slice of values by slot index instead of by name,
+/// and the stack is allocated once when the expression is compiled, so calling
+/// `eval` does not allocate.
+///
+/// `Bytecode` is `Send + Sync`. All of the state used during evaluation is 
+/// passed in, so one program can be shared between threads with 
+/// `eval_with_stack`, giving each thread its own stack.
+///
+/// # Example
+/// ```
+/// use geqslib::shunting::{Bytecode, parse_expr, new_context, ContextLike};
+///
+/// let mut ctx = new_context();
+/// ctx.add_var_to_ctx("x", 1);
+/// ctx.add_var_to_ctx("y", 1);
+///
+/// let expr = parse_expr("x^2 + max(x, y) * 2", &ctx).unwrap();
+/// let mut program = Bytecode::compile(&expr, &ctx, &["y", "x"]).unwrap();
+///
+/// // y = 4, x = 3
+/// assert_eq!(program.eval(&[4.0, 3.0]).unwrap(), 17.0);
+/// assert_eq!(program.slots(), &["y".to_owned(), "x".to_owned()]);
+/// ```
+#[derive(Clone)]
+pub struct Bytecode
+{
+    code: Vec<Instr>,
+    funcs: Vec<ContextFn>,
+    slots: Vec<String>,
+    stack: Vec<f64>,
+}
+
+impl Bytecode
+{
+    /// Compiles an expression, reading the value of each variable in `slots`
+    /// from the same position in the slice given to `eval`. Functions and
+    /// constants are looked up in `ctx` once, here.
+    ///
+    /// Every variable in `expr` must be listed in `slots`.
+    pub fn compile(expr: &Expr, ctx: &ContextHashMap, slots: &[&str]) -> anyhow::Result<Bytecode>
+    {
+        let mut compiler = Compiler
+        {
+            ctx,
+            slots,
+            code: vec![],
+            funcs: vec![],
+            func_names: vec![],
+            depth: 0,
+            max_depth: 0,
+        };
+        compiler.compile(expr)?;
+
+        Ok(Bytecode
+        {
+            code: compiler.code,
+            funcs: compiler.funcs,
+            slots: slots.iter().map(|s| s.to_string()).collect(),
+            stack: Vec::with_capacity(compiler.max_depth),
+        })
+    }
+
+    /// Returns the names of the variables read by `eval`, in slot order.
+    pub fn slots(&self) -> &[String]
+    {
+        &self.slots
+    }
+
+    /// Evaluates the program with `vars[i]` as the value of the variable in slot `i`.
+    pub fn eval(&mut self, vars: &[f64]) -> anyhow::Result<f64>
+    {
+        let mut stack = std::mem::take(&mut self.stack);
+        let res = self.eval_with_stack(vars, &mut stack);
+        self.stack = stack;
+        res
+    }
+
+    /// Evaluates the program like `eval`, but uses the given `stack` for intermediate
+    /// values instead of the program's own. This allows a single program to be 
+    /// evaluated from several threads at once. Reusing the same `stack` between 
+    /// calls avoids allocating.
+    ///
+    /// # Example
+    /// ```
+    /// use std::sync::Arc;
+    /// use geqslib::shunting::{compile_to_bytecode, new_context, ContextLike};
+    ///
+    /// let mut ctx = new_context();
+    /// ctx.add_var_to_ctx("x", 1);
+    ///
+    /// let program = Arc::new(compile_to_bytecode("x^2", &ctx, &["x"]).unwrap());
+    ///
+    /// let handles: Vec<_> = (0..4).map(|i| {
+    ///     let program = Arc::clone(&program);
+    ///     std::thread::spawn(move || program.eval_with_stack(&[i as f64], &mut vec![]).unwrap())
+    /// }).collect();
+    ///
+    /// let squares: Vec<f64> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+    /// assert_eq!(squares, [0.0, 1.0, 4.0, 9.0]);
+    /// ```
+    pub fn eval_with_stack(&self, vars: &[f64], stack: &mut Vec<f64>) -> anyhow::Result<f64>
+    {
+        if vars.len() != self.slots.len()
+        {
+            return Err(ExpressionCompilationError::WrongVarCount.into());
+        }
+
+        stack.clear();
+
+        let mut pc = 0;
+        while let Some(instr) = self.code.get(pc)
+        {
+            pc += 1;
+            match *instr
+            {
+                Instr::Push(x) => stack.push(x),
+                Instr::Load(slot) => stack.push(vars[slot]),
+                Instr::Unary(op) => {
+                    let arg = pop(stack);
+                    stack.push(op.apply(arg));
+                },
+                Instr::Binary(op) => {
+                    let rhs = pop(stack);
+                    let lhs = pop(stack);
+                    stack.push(op.apply(lhs, rhs)?);
+                },
+                Instr::Call(func, num_args) => {
+                    let args_start = stack.len() - num_args;
+                    let res = (self.funcs[func])(&stack[args_start..])?;
+                    stack.truncate(args_start);
+                    stack.push(res);
+                },
+                Instr::Jump(to) => pc = to,
+                Instr::JumpIfFalse(to) => if pop(stack) == 0.0
+                {
+                    pc = to;
+                },
+                Instr::JumpIfTrue(to) => if pop(stack) != 0.0
+                {
+                    pc = to;
+                },
+                Instr::Truthy => {
+                    let x = pop(stack);
+                    stack.push(if x != 0.0 { 1.0 } else { 0.0 });
+                },
+            }
+        }
+
+        Ok(pop(stack))
+    }
+}
+
+/// Pops a value that the compiler guarantees is on the stack.
+fn pop(stack: &mut Vec<f64>) -> f64
+{
+    stack.pop().expect("compiled programs never underflow the stack")
+}
+
+/// The state of a compilation in progress.
+struct Compiler<'a>
+{
+    ctx: &'a ContextHashMap,
+    slots: &'a [&'a str],
+    code: Vec<Instr>,
+    funcs: Vec<ContextFn>,
+    func_names: Vec<&'a str>,
+    depth: usize,
+    max_depth: usize,
+}
+
+impl<'a> Compiler<'a>
+{
+    /// Adds an instruction, tracking how it changes the depth of the stack.
+    fn emit(&mut self, instr: Instr)
+    {
+        match instr
+        {
+            Instr::Push(_) | Instr::Load(_) => self.depth += 1,
+            Instr::Binary(_) | Instr::JumpIfFalse(_) | Instr::JumpIfTrue(_) => self.depth -= 1,
+            Instr::Call(_, num_args) => self.depth = self.depth + 1 - num_args,
+            Instr::Unary(_) | Instr::Jump(_) | Instr::Truthy => (),
+        }
+        self.max_depth = self.max_depth.max(self.depth);
+        self.code.push(instr);
+    }
+
+    /// Points the jump at `at` to the next instruction to be emitted.
+    fn patch(&mut self, at: usize)
+    {
+        let to = self.code.len();
+        match &mut self.code[at]
+        {
+            Instr::Jump(target) | Instr::JumpIfFalse(target) | Instr::JumpIfTrue(target) => *target = to,
+            _ => unreachable!("only jumps are patched"),
+        }
+    }
+
+    fn compile(&mut self, expr: &'a Expr) -> anyhow::Result<()>
+    {
+        match expr
+        {
+            Expr::Num(x) | Expr::Const(_, x) => self.emit(Instr::Push(*x)),
+
+            Expr::Var(name) => match self.slots.iter().position(|s| s == name)
+            {
+                Some(slot) => self.emit(Instr::Load(slot)),
+                None => return Err(ExpressionCompilationError::VarNotFoundInContext.into()),
+            },
+
+            Expr::Unary(op, arg) => {
+                self.compile(arg)?;
+                self.emit(Instr::Unary(*op));
+            },
+
+            // Short-circuit just like `Expr::eval`
+            Expr::Binary(op @ (BinaryOp::And | BinaryOp::Or), lhs, rhs) => {
+                self.compile(lhs)?;
+                let short_circuit = self.code.len();
+                match op
+                {
+                    BinaryOp::And => self.emit(Instr::JumpIfFalse(0)),
+                    _ => self.emit(Instr::JumpIfTrue(0)),
+                }
+                let depth = self.depth;
+
+                self.compile(rhs)?;
+                self.emit(Instr::Truthy);
+                let end = self.code.len();
+                self.emit(Instr::Jump(0));
+
+                self.patch(short_circuit);
+                self.depth = depth;
+                self.emit(Instr::Push(if *op == BinaryOp::And { 0.0 } else { 1.0 }));
+                self.patch(end);
+            },
+
+            Expr::Binary(op, lhs, rhs) => {
+                self.compile(lhs)?;
+                self.compile(rhs)?;
+                self.emit(Instr::Binary(*op));
+            },
+
+            Expr::If(cond, if_true, if_false) => {
+                self.compile(cond)?;
+                let to_else = self.code.len();
+                self.emit(Instr::JumpIfFalse(0));
+                let depth = self.depth;
+
+                self.compile(if_true)?;
+                let to_end = self.code.len();
+                self.emit(Instr::Jump(0));
+
+                self.patch(to_else);
+                self.depth = depth;
+                self.compile(if_false)?;
+                self.patch(to_end);
+            },
+
+            Expr::Call(name, args) => {
+                let func = match self.ctx.get(name)
+                {
+                    Some(Token::Func(arity, f, _)) if arity.accepts(args.len()) => f,
+                    Some(Token::Func(arity, _, _)) if args.len() < arity.min_args() => return Err(ShuntingYardError::ExpectedArg.into()),
+                    Some(Token::Func(..)) => return Err(ShuntingYardError::LeftoverToken.into()),
+                    _ => return Err(ShuntingYardError::UnknownToken.into()),
+                };
+
+                let index = match self.func_names.iter().position(|n| n == name)
+                {
+                    Some(i) => i,
+                    None => {
+                        self.func_names.push(name);
+                        self.funcs.push(func.clone());
+                        self.funcs.len() - 1
+                    },
+                };
+
+                for arg in args
+                {
+                    self.compile(arg)?;
+                }
+                self.emit(Instr::Call(index, args.len()));
+            },
+        }
+        Ok(())
+    }
+}
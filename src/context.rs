@@ -1,6 +1,8 @@
 use std::collections::HashMap;
-use std::f64::consts::{PI, E};
+use std::f64::consts::{PI, E, LN_10};
+use std::fmt;
 use std::rc::Rc;
+use std::sync::Arc;
 use std::cell::RefCell;
 
 use crate::variable::Variable;
@@ -10,9 +12,75 @@ use crate::variable::Variable;
 /// expressions and equations.
 pub type ContextHashMap = HashMap<String, Token>;
 
+/// A function that can be called from an expression. It receives the values 
+/// of its arguments and may fail, e.g. when a lookup table is out of range.
+/// 
+/// Functions are `Send + Sync` so that compiled expressions and systems 
+/// can be moved to or shared between threads.
+pub type ContextFn = Arc<dyn Fn(&[f64]) -> anyhow::Result<f64> + Send + Sync>;
+
+/// The partial derivatives of a `ContextFn`. `derivative(args, i)` gives the
+/// partial derivative of the function with respect to `args[i]`.
+pub type ContextFnDerivative = Arc<dyn Fn(&[f64], usize) -> anyhow::Result<f64> + Send + Sync>;
+
+/// The number of arguments a function in a `ContextHashMap` accepts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Arity
+{
+    /// The function takes exactly this many arguments.
+    Exactly(usize),
+    /// The function is variadic and takes at least this many arguments, 
+    /// like `max(a, b, ...)`.
+    AtLeast(usize),
+}
+
+impl Arity
+{
+    /// Indicates whether a call with `num_args` arguments is allowed.
+    /// 
+    /// # Example
+    /// ```
+    /// use geqslib::shunting::Arity;
+    /// 
+    /// assert!(Arity::Exactly(2).accepts(2));
+    /// assert!(!Arity::Exactly(2).accepts(3));
+    /// assert!(Arity::AtLeast(1).accepts(3));
+    /// assert!(!Arity::AtLeast(1).accepts(0));
+    /// ```
+    pub fn accepts(&self, num_args: usize) -> bool
+    {
+        match self
+        {
+            Arity::Exactly(n) => num_args == *n,
+            Arity::AtLeast(n) => num_args >= *n,
+        }
+    }
+
+    /// Returns the fewest arguments the function can be called with.
+    pub fn min_args(&self) -> usize
+    {
+        match self
+        {
+            Arity::Exactly(n) | Arity::AtLeast(n) => *n,
+        }
+    }
+}
+
+impl fmt::Display for Arity
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result 
+    {
+        let (prefix, n) = match self
+        {
+            Arity::Exactly(n) => ("", n),
+            Arity::AtLeast(n) => ("at least ", n),
+        };
+        let plural = if *n == 1 { "" } else { "s" };
+        write!(f, "{prefix}{n} argument{plural}")
+    }
+}
+
 #[derive(Clone)]
-#[derive(Debug)]
-#[derive(PartialEq)]
 pub enum Token {
     LeftParenthesis,
     Comma,
@@ -23,7 +91,86 @@ pub enum Token {
     Minus,
     Num(f64),
     Var(Rc<RefCell<Variable>>),
-    Func(usize, fn(&[f64]) -> f64),  
+    Func(Arity, ContextFn, Option<ContextFnDerivative>),  
+}
+
+impl fmt::Debug for Token
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result 
+    {
+        match self
+        {
+            Token::LeftParenthesis => write!(f, "LeftParenthesis"),
+            Token::Comma => write!(f, "Comma"),
+            Token::Exp => write!(f, "Exp"),
+            Token::Mul => write!(f, "Mul"),
+            Token::Div => write!(f, "Div"),
+            Token::Plus => write!(f, "Plus"),
+            Token::Minus => write!(f, "Minus"),
+            Token::Num(x) => f.debug_tuple("Num").field(x).finish(),
+            Token::Var(v) => f.debug_tuple("Var").field(v).finish(),
+            // Closures can't be printed, so only show whether a derivative was given
+            Token::Func(n, _, d) => f.debug_tuple("Func")
+                .field(n)
+                .field(&format_args!("<fn>"))
+                .field(&d.as_ref().map(|_| format_args!("<fn>")))
+                .finish(),
+        }
+    }
+}
+
+impl PartialEq for Token
+{
+    fn eq(&self, other: &Self) -> bool 
+    {
+        match (self, other)
+        {
+            (Token::Num(a), Token::Num(b)) => a == b,
+            (Token::Var(a), Token::Var(b)) => a == b,
+            (Token::Func(n1, f1, d1), Token::Func(n2, f2, d2)) => {
+                let same_derivative = match (d1, d2)
+                {
+                    (Some(d1), Some(d2)) => Arc::ptr_eq(d1, d2),
+                    (None, None) => true,
+                    _ => false,
+                };
+                n1 == n2 && Arc::ptr_eq(f1, f2) && same_derivative
+            },
+            _ => std::mem::discriminant(self) == std::mem::discriminant(other),
+        }
+    }
+}
+
+/// A `Send + Sync` counterpart to `ContextHashMap`. Variables hold their 
+/// values directly instead of in shared cells, so each thread that evaluates 
+/// with it works on its own copy of that state.
+/// 
+/// Convert to and from a `ContextHashMap` with `to_sync_context` and `from_sync_context`.
+pub type SyncContextHashMap = HashMap<String, SyncToken>;
+
+/// An entry in a `SyncContextHashMap`.
+#[derive(Clone)]
+pub enum SyncToken {
+    Num(f64),
+    Var(Variable),
+    Func(Arity, ContextFn, Option<ContextFnDerivative>),
+}
+
+impl fmt::Debug for SyncToken
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result 
+    {
+        match self
+        {
+            SyncToken::Num(x) => f.debug_tuple("Num").field(x).finish(),
+            SyncToken::Var(v) => f.debug_tuple("Var").field(v).finish(),
+            SyncToken::Func(n, _, d) => f.debug_tuple("Func")
+                .field(n)
+                .field(&format_args!("<fn>"))
+                .field(&d.as_ref().map(|_| format_args!("<fn>")))
+                .finish(),
+        }
+    }
 }
 
 fn sin(x:  &[f64]) -> f64 {
@@ -59,6 +206,7 @@ fn ln(x: &[f64]) -> f64 {
 fn log10(x: &[f64]) -> f64 {
     x[0].log10()
 }
+/// `log(x, base)`
 fn log(x: &[f64]) -> f64 {
     x[0].log(x[1])
 }
@@ -66,12 +214,91 @@ fn abs(x: &[f64]) -> f64 {
     x[0].abs()
 }
 
+// Partial derivatives of the functions above with respect to `x[i]`
+fn d_sin(x: &[f64], _: usize) -> f64 {
+    x[0].cos()
+}
+fn d_cos(x: &[f64], _: usize) -> f64 {
+    -x[0].sin()
+}
+fn d_tan(x: &[f64], _: usize) -> f64 {
+    1.0 / x[0].cos().powi(2)
+}
+fn d_arcsin(x: &[f64], _: usize) -> f64 {
+    1.0 / (1.0 - x[0] * x[0]).sqrt()
+}
+fn d_arccos(x: &[f64], _: usize) -> f64 {
+    -1.0 / (1.0 - x[0] * x[0]).sqrt()
+}
+fn d_arctan(x: &[f64], _: usize) -> f64 {
+    1.0 / (1.0 + x[0] * x[0])
+}
+fn d_sinh(x: &[f64], _: usize) -> f64 {
+    x[0].cosh()
+}
+fn d_cosh(x: &[f64], _: usize) -> f64 {
+    x[0].sinh()
+}
+fn d_tanh(x: &[f64], _: usize) -> f64 {
+    1.0 / x[0].cosh().powi(2)
+}
+fn d_ln(x: &[f64], _: usize) -> f64 {
+    1.0 / x[0]
+}
+fn d_log10(x: &[f64], _: usize) -> f64 {
+    1.0 / (x[0] * LN_10)
+}
+fn d_log(x: &[f64], i: usize) -> f64 {
+    match i {
+    0 => 1.0 / (x[0] * x[1].ln()),
+    _ => -x[0].ln() / (x[1] * x[1].ln().powi(2)),
+    }
+}
+fn d_abs(x: &[f64], _: usize) -> f64 {
+    x[0].signum()
+}
+
+fn min(x: &[f64]) -> f64 {
+    x.iter().copied().fold(f64::INFINITY, f64::min)
+}
+fn max(x: &[f64]) -> f64 {
+    x.iter().copied().fold(f64::NEG_INFINITY, f64::max)
+}
+fn sum(x: &[f64]) -> f64 {
+    x.iter().sum()
+}
+fn mean(x: &[f64]) -> f64 {
+    sum(x) / x.len() as f64
+}
+fn hypot(x: &[f64]) -> f64 {
+    x.iter().map(|a| a * a).sum::<f64>().sqrt()
+}
+
+fn d_min(x: &[f64], i: usize) -> f64 {
+    // Only the first argument holding the minimum moves the result
+    let picked = x.iter().position(|a| *a == min(x));
+    if picked == Some(i) { 1.0 } else { 0.0 }
+}
+fn d_max(x: &[f64], i: usize) -> f64 {
+    let picked = x.iter().position(|a| *a == max(x));
+    if picked == Some(i) { 1.0 } else { 0.0 }
+}
+fn d_sum(_: &[f64], _: usize) -> f64 {
+    1.0
+}
+fn d_mean(x: &[f64], _: usize) -> f64 {
+    1.0 / x.len() as f64
+}
+fn d_hypot(x: &[f64], i: usize) -> f64 {
+    x[i] / hypot(x)
+}
+
 fn conditional(args: &[f64]) -> f64 {
-    let a              = args[4];
-    let op             = args[3];
+    let a              = args[0];
+    let op             = args[1];
     let b              = args[2];
-    let if_true_return = args[1];
-    let else_return    = args[0];
+    let if_true_return = args[3];
+    let else_return    = args[4];
     
     let decision = |predicate| {
     if predicate {
@@ -91,6 +318,21 @@ fn conditional(args: &[f64]) -> f64 {
     }
 }
 
+fn d_conditional(args: &[f64], i: usize) -> f64 {
+    // The result is one of the last two arguments, so it only 
+    // changes with whichever one the condition picks.
+    let mut picks = args.to_vec();
+    picks[3] = 0.0;
+    picks[4] = 0.0;
+    match i {
+    3 | 4 => {
+        picks[i] = 1.0;
+        conditional(&picks)
+    },
+    _ => 0.0,
+    }
+}
+
 /// A module for sealing the `ContextLike` trait.
 pub (crate) mod private
 {
@@ -102,16 +344,71 @@ pub (crate) mod private
 /// Provides extra methods for `ContextHashMap`.
 pub trait ContextLike: private::Sealed
 {
+    /// Adds a named function that takes exactly `num_args` arguments. The function 
+    /// receives its arguments in the order they are written at the call site. 
+    /// Use `add_closure_to_ctx` for variadic functions, functions that capture 
+    /// state or may fail, and functions with a derivative.
+    /// 
+    /// # Example
+    /// ```
+    /// use geqslib::shunting::{new_context, eval_str_with_context, ContextLike};
+    /// 
+    /// fn minus(x: &[f64]) -> f64 { x[0] - x[1] }
+    /// 
+    /// let mut ctx = new_context();
+    /// ctx.add_func_to_ctx("minus", minus, 2);
+    /// 
+    /// assert_eq!(eval_str_with_context("minus(5, 2)", &ctx).unwrap(), 3.0);
+    /// ```
     fn add_func_to_ctx(&mut self, name: &str, func: fn(&[f64]) -> f64, num_args: usize);
 
+    /// Adds a named closure that accepts the numbers of arguments allowed by `arity`.
+    /// The closure may capture state (e.g. a table loaded at runtime) and may fail. 
+    /// An error returned by the closure is returned by whatever evaluates it, 
+    /// including the equation and system solvers.
+    /// 
+    /// The derivative of the closure can be given with `FuncEntry::with_derivative`
+    /// on the returned entry. Functions added without one are differentiated 
+    /// numerically during automatic differentiation.
+    /// 
+    /// # Example
+    /// ```
+    /// use geqslib::shunting::{new_context, eval_str_with_context, Arity, ContextLike};
+    /// 
+    /// // a table of (x, y) points to interpolate between
+    /// let table = vec![(0.0, 0.0), (1.0, 10.0), (2.0, 40.0)];
+    /// 
+    /// let mut ctx = new_context();
+    /// ctx.add_closure_to_ctx("lookup", Arity::Exactly(1), move |x| {
+    ///     let i = table.windows(2)
+    ///         .position(|w| w[0].0 <= x[0] && x[0] <= w[1].0)
+    ///         .ok_or_else(|| anyhow::anyhow!("{} is outside of the table", x[0]))?;
+    ///     let ((x0, y0), (x1, y1)) = (table[i], table[i + 1]);
+    ///     Ok(y0 + (x[0] - x0) * (y1 - y0) / (x1 - x0))
+    /// });
+    /// 
+    /// // variadic functions receive however many arguments were given
+    /// ctx.add_closure_to_ctx("product", Arity::AtLeast(1), |x| Ok(x.iter().product()));
+    /// 
+    /// assert_eq!(eval_str_with_context("lookup(1.5)", &ctx).unwrap(), 25.0);
+    /// assert!(eval_str_with_context("lookup(3)", &ctx).is_err());
+    /// assert_eq!(eval_str_with_context("product(2, 3, 4)", &ctx).unwrap(), 24.0);
+    /// ```
+    fn add_closure_to_ctx<F>(&mut self, name: &str, arity: Arity, func: F) -> FuncEntry<'_>
+    where
+        F: Fn(&[f64]) -> anyhow::Result<f64> + Send + Sync + 'static;
+
+    /// Adds a named constant value.
     fn add_const_to_ctx<T>(&mut self, name: &str, val: T)
     where
         T: Into<f64> + Copy;
 
+    /// Adds a named variable with an infinite domain.
     fn add_var_to_ctx<T>(&mut self, name: &str, val: T)
     where 
         T: Into<f64> + Copy;
 
+    /// Adds a named variable with a specified domain.
     fn add_var_with_domain_to_ctx<T>(&mut self, name: &str, val: T, min: T, max: T)
     where
         T: Into<f64> + Copy;
@@ -120,12 +417,18 @@ pub trait ContextLike: private::Sealed
 /// Provides extra methods for the `ContextHashMap` type.
 impl ContextLike for ContextHashMap 
 {
-    /// Adds a named function to the `ContextHashMap`. 
     fn add_func_to_ctx(&mut self, name: &str, func: fn(&[f64]) -> f64, num_args: usize) {
-        self.insert(name.to_owned(), Token::Func(num_args, func));
+        self.add_closure_to_ctx(name, Arity::Exactly(num_args), move |x| Ok(func(x)));
+    }
+
+    fn add_closure_to_ctx<F>(&mut self, name: &str, arity: Arity, func: F) -> FuncEntry<'_>
+    where
+        F: Fn(&[f64]) -> anyhow::Result<f64> + Send + Sync + 'static
+    {
+        self.insert(name.to_owned(), Token::Func(arity, Arc::new(func), None));
+        FuncEntry(self.get_mut(name).expect("the function was just inserted"))
     }
     
-    /// Adds a named constant value to the `ContextHashMap`.
     fn add_const_to_ctx<T>(&mut self, name: &str, val: T) 
     where
         T: Into<f64> + Copy 
@@ -133,7 +436,6 @@ impl ContextLike for ContextHashMap
         self.insert(name.to_owned(), Token::Num(val.into()));
     }
     
-    /// Adds a variable to the `ContextHashMap` with an infinite domain.
     fn add_var_to_ctx<T>(&mut self, name: &str, val: T)
     where 
         T: Into<f64> + Copy 
@@ -141,7 +443,6 @@ impl ContextLike for ContextHashMap
         self.add_var_with_domain_to_ctx(name, val.into(), f64::NEG_INFINITY, f64::INFINITY);
     }
 
-    /// Adds a named variable to the `ContextHashMap` with a specified domain.
     fn add_var_with_domain_to_ctx<T>(&mut self, name: &str, val: T, min: T, max: T) 
     where
         T: Into<f64> + Copy
@@ -150,9 +451,56 @@ impl ContextLike for ContextHashMap
     }
 }
 
+/// A function that was just added to a `ContextHashMap` by `ContextLike::add_closure_to_ctx`.
+pub struct FuncEntry<'a>(&'a mut Token);
+
+impl FuncEntry<'_>
+{
+    /// Gives the function its derivative. `derivative(args, i)` must return the 
+    /// partial derivative of the function with respect to `args[i]`, where `args` 
+    /// is the same slice that the function receives.
+    /// 
+    /// # Example
+    /// ```
+    /// use geqslib::shunting::{new_context, compile_to_dual_fn, Arity, ContextLike};
+    /// 
+    /// let mut ctx = new_context();
+    /// ctx.add_closure_to_ctx("cube", Arity::Exactly(1), |x| Ok(x[0].powi(3)))
+    ///     .with_derivative(|x, _| Ok(3.0 * x[0].powi(2)));
+    /// ctx.add_var_to_ctx("x", 1);
+    /// 
+    /// let f = compile_to_dual_fn("cube(x)", &ctx).unwrap();
+    /// 
+    /// assert_eq!(f(2.0).unwrap().deriv, 12.0);
+    /// ```
+    pub fn with_derivative<D>(self, derivative: D)
+    where
+        D: Fn(&[f64], usize) -> anyhow::Result<f64> + Send + Sync + 'static
+    {
+        if let Token::Func(_, _, d) = self.0
+        {
+            *d = Some(Arc::new(derivative));
+        }
+    }
+}
+
+/// Adds one of the built-in functions and its derivative to `ctx`.
+fn add_builtin(ctx: &mut ContextHashMap, name: &str, arity: Arity, func: fn(&[f64]) -> f64, derivative: fn(&[f64], usize) -> f64)
+{
+    ctx.add_closure_to_ctx(name, arity, move |x| Ok(func(x)))
+        .with_derivative(move |x, i| Ok(derivative(x, i)));
+}
+
 /// Initializes a new `ContextHashMap` with basic trig, log, conditional, and absolute value
 /// functions as well as pre-defined constants for pi and Euler's number.
 /// 
+/// `log(x, base)` gives the base-`base` logarithm of `x`. The variadic functions 
+/// `min`, `max`, `sum`, `mean` and `hypot` accept one or more arguments.
+/// 
+/// The five-argument `if(a, op_code, b, if_true, if_false)` function is kept for
+/// backwards compatibility. New expressions should prefer the `if(condition, if_true, if_false)`
+/// form built into the parser along with the comparison and logical operators.
+/// 
 /// # Example
 /// ```
 /// use geqslib::shunting::{new_context, Token};
@@ -167,28 +515,90 @@ impl ContextLike for ContextHashMap
 /// ```
 pub fn new_context() -> ContextHashMap {
     let mut ctx = HashMap::new();
-    ctx.add_func_to_ctx("if",     conditional, 5);
+    add_builtin(&mut ctx, "if",     Arity::Exactly(5), conditional, d_conditional);
+    
+    add_builtin(&mut ctx, "sin",    Arity::Exactly(1), sin,         d_sin);
+    add_builtin(&mut ctx, "cos",    Arity::Exactly(1), cos,         d_cos);
+    add_builtin(&mut ctx, "tan",    Arity::Exactly(1), tan,         d_tan);
     
-    ctx.add_func_to_ctx("sin",    sin,         1);
-    ctx.add_func_to_ctx("cos",    cos,         1);
-    ctx.add_func_to_ctx("tan",    tan,         1);
+    add_builtin(&mut ctx, "arcsin", Arity::Exactly(1), arcsin,      d_arcsin);
+    add_builtin(&mut ctx, "arccos", Arity::Exactly(1), arccos,      d_arccos);
+    add_builtin(&mut ctx, "arctan", Arity::Exactly(1), arctan,      d_arctan);
     
-    ctx.add_func_to_ctx("arcsin", arcsin,      1);
-    ctx.add_func_to_ctx("arccos", arccos,      1);
-    ctx.add_func_to_ctx("arctan", arctan,      1);
+    add_builtin(&mut ctx, "sinh",   Arity::Exactly(1), sinh,        d_sinh);
+    add_builtin(&mut ctx, "cosh",   Arity::Exactly(1), cosh,        d_cosh);
+    add_builtin(&mut ctx, "tanh",   Arity::Exactly(1), tanh,        d_tanh);
     
-    ctx.add_func_to_ctx("sinh",   sinh,        1);
-    ctx.add_func_to_ctx("cosh",   cosh,        1);
-    ctx.add_func_to_ctx("tanh",   tanh,        1);
+    add_builtin(&mut ctx, "ln",     Arity::Exactly(1), ln,          d_ln);
+    add_builtin(&mut ctx, "log10",  Arity::Exactly(1), log10,       d_log10);
+    add_builtin(&mut ctx, "log",    Arity::Exactly(2), log,         d_log);
     
-    ctx.add_func_to_ctx("ln",     ln,          1);
-    ctx.add_func_to_ctx("log10",  log10,       1);
-    ctx.add_func_to_ctx("log",    log,         2);
+    add_builtin(&mut ctx, "abs",    Arity::Exactly(1), abs,         d_abs);
     
-    ctx.add_func_to_ctx("abs",    abs,         1);
+    add_builtin(&mut ctx, "min",    Arity::AtLeast(1), min,         d_min);
+    add_builtin(&mut ctx, "max",    Arity::AtLeast(1), max,         d_max);
+    add_builtin(&mut ctx, "sum",    Arity::AtLeast(1), sum,         d_sum);
+    add_builtin(&mut ctx, "mean",   Arity::AtLeast(1), mean,        d_mean);
+    add_builtin(&mut ctx, "hypot",  Arity::AtLeast(1), hypot,       d_hypot);
     
     ctx.add_const_to_ctx("pi",                PI);
     ctx.add_const_to_ctx("e",                  E);
     
     ctx
-}
\ No newline at end of file
+}
+
+/// Copies a `ContextHashMap` to a `SyncContextHashMap`, taking a snapshot of the 
+/// current value and domain of each variable. Functions are shared, not copied.
+/// 
+/// # Example
+/// ```
+/// use geqslib::shunting::{new_context, to_sync_context, ContextLike, SyncToken};
+/// 
+/// let mut ctx = new_context();
+/// ctx.add_var_to_ctx("x", 2);
+/// 
+/// let sync_ctx = to_sync_context(&ctx);
+/// 
+/// // the snapshot can be sent to another thread
+/// let x = std::thread::spawn(move || match &sync_ctx["x"]
+/// {
+///     SyncToken::Var(x) => f64::from(*x),
+///     _ => unreachable!(),
+/// }).join().unwrap();
+/// 
+/// assert_eq!(x, 2.0);
+/// ```
+pub fn to_sync_context(ctx: &ContextHashMap) -> SyncContextHashMap
+{
+    let mut sync_ctx = HashMap::new();
+    for (name, token) in ctx
+    {
+        let sync_token = match token
+        {
+            Token::Num(x) => SyncToken::Num(*x),
+            Token::Var(v) => SyncToken::Var(*v.borrow()),
+            Token::Func(arity, f, d) => SyncToken::Func(*arity, f.clone(), d.clone()),
+            _ => continue,
+        };
+        sync_ctx.insert(name.clone(), sync_token);
+    }
+    sync_ctx
+}
+
+/// Builds a `ContextHashMap` from a `SyncContextHashMap`. Each variable gets a 
+/// new cell of its own, so the result does not share state with any other context.
+pub fn from_sync_context(sync_ctx: &SyncContextHashMap) -> ContextHashMap
+{
+    let mut ctx = HashMap::new();
+    for (name, token) in sync_ctx
+    {
+        let token = match token
+        {
+            SyncToken::Num(x) => Token::Num(*x),
+            SyncToken::Var(v) => Token::Var(Rc::new(RefCell::new(*v))),
+            SyncToken::Func(arity, f, d) => Token::Func(*arity, f.clone(), d.clone()),
+        };
+        ctx.insert(name.clone(), token);
+    }
+    ctx
+}
@@ -0,0 +1,139 @@
+use std::ops::{Add, Sub, Mul, Div, Neg};
+
+/// A dual number `value + deriv * ε` where `ε² = 0`.
+///
+/// Evaluating a function with dual numbers carries the derivative of
+/// each intermediate result along with its value, giving the exact
+/// derivative of the function without any symbolic manipulation.
+///
+/// # Example
+/// ```
+/// use geqslib::dual::Dual;
+///
+/// // d/dx (x * x + 3) at x = 2
+/// let x = Dual::variable(2.0);
+/// let y = x * x + Dual::constant(3.0);
+///
+/// assert_eq!(y.value, 7.0);
+/// assert_eq!(y.deriv, 4.0);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Dual
+{
+    pub value: f64,
+    pub deriv: f64,
+}
+
+impl Dual
+{
+    pub fn new(value: f64, deriv: f64) -> Dual
+    {
+        Dual { value, deriv }
+    }
+
+    /// Creates a dual number for a value that does not depend on the
+    /// variable being differentiated with respect to.
+    pub fn constant(value: f64) -> Dual
+    {
+        Dual::new(value, 0.0)
+    }
+
+    /// Creates a dual number for the variable being differentiated
+    /// with respect to.
+    pub fn variable(value: f64) -> Dual
+    {
+        Dual::new(value, 1.0)
+    }
+
+    /// Raises `self` to the power of `exp`.
+    pub fn powf(self, exp: Dual) -> Dual
+    {
+        let value = self.value.powf(exp.value);
+
+        // A constant raised to a constant is constant, even where the formulas 
+        // below would give 0 * inf, e.g. for sqrt(0)
+        if self.deriv == 0.0 && exp.deriv == 0.0
+        {
+            return Dual::new(value, 0.0);
+        }
+
+        // Avoid taking the log of the base unless the exponent actually varies
+        let deriv = if exp.deriv == 0.0
+        {
+            exp.value * self.value.powf(exp.value - 1.0) * self.deriv
+        }
+        else if self.deriv == 0.0
+        {
+            value * self.value.ln() * exp.deriv
+        }
+        else
+        {
+            value * (exp.deriv * self.value.ln() + exp.value * self.deriv / self.value)
+        };
+
+        Dual::new(value, deriv)
+    }
+}
+
+impl Add for Dual
+{
+    type Output = Dual;
+
+    fn add(self, rhs: Dual) -> Dual
+    {
+        Dual::new(self.value + rhs.value, self.deriv + rhs.deriv)
+    }
+}
+
+impl Sub for Dual
+{
+    type Output = Dual;
+
+    fn sub(self, rhs: Dual) -> Dual
+    {
+        Dual::new(self.value - rhs.value, self.deriv - rhs.deriv)
+    }
+}
+
+impl Mul for Dual
+{
+    type Output = Dual;
+
+    fn mul(self, rhs: Dual) -> Dual
+    {
+        Dual::new(self.value * rhs.value, self.deriv * rhs.value + self.value * rhs.deriv)
+    }
+}
+
+impl Div for Dual
+{
+    type Output = Dual;
+
+    fn div(self, rhs: Dual) -> Dual
+    {
+        Dual::new(
+            self.value / rhs.value,
+            (self.deriv * rhs.value - self.value * rhs.deriv) / (rhs.value * rhs.value)
+        )
+    }
+}
+
+impl Neg for Dual
+{
+    type Output = Dual;
+
+    fn neg(self) -> Dual
+    {
+        Dual::new(-self.value, -self.deriv)
+    }
+}
+
+#[test]
+fn test_constant_powers_have_no_derivative()
+{
+    let root = Dual::constant(0.0).powf(Dual::constant(0.5));
+    assert_eq!(root, Dual::new(0.0, 0.0));
+
+    let root = Dual::variable(4.0).powf(Dual::constant(0.5));
+    assert_eq!(root, Dual::new(2.0, 0.25));
+}
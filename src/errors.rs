@@ -1,6 +1,10 @@
 use std::error::Error;
 use std::fmt;
 use std::fmt::Display;
+use std::ops::Range;
+
+use crate::context::Arity;
+use crate::newton::Iteration;
 
 /// More concise syntax for implementing `Error` and `Display` for both structs and enums
 macro_rules! impl_err {
@@ -45,6 +49,88 @@ impl_err! {
     ShuntingYardError::NoTokens, "expected to find one token in postfix evaluation stack but found none"
 }
 
+/// A `ShuntingYardError` found at a specific location in an expression or equation.
+#[derive(Debug)]
+pub struct ParseError {
+    /// What went wrong
+    pub kind: ShuntingYardError,
+    /// The text of the offending token, or an empty string if the input ended unexpectedly
+    pub token: String,
+    /// The byte offsets of the offending token in the source string
+    pub span: Range<usize>,
+    /// The line of the source string containing the token, with a caret underneath the token 
+    pub diagnostic: String,
+}
+impl ParseError {
+    pub fn new(kind: ShuntingYardError, source: &str, span: Range<usize>) -> ParseError {
+        ParseError {
+            kind,
+            token: source[span.clone()].to_owned(),
+            diagnostic: render_diagnostic(source, &span),
+            span,
+        }
+    }
+}
+
+impl Error for ParseError {}
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.token.is_empty() {
+            write!(f, "{} at the end of input (byte {})\n{}", self.kind, self.span.start, self.diagnostic)
+        } else {
+            write!(f, "{} at '{}' (bytes {}..{})\n{}", self.kind, self.token, self.span.start, self.span.end, self.diagnostic)
+        }
+    }
+}
+
+/// Renders the line of `source` containing `span` with carets underneath the span.
+fn render_diagnostic(source: &str, span: &Range<usize>) -> String {
+    let line_start = source[..span.start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[span.start..].find('\n').map_or(source.len(), |i| span.start + i);
+    let line = &source[line_start..line_end];
+
+    // Carets go under the characters of the token, not its bytes
+    let indent = source[line_start..span.start].chars().count();
+    let width = source[span.start..span.end.min(line_end)].chars().count().max(1);
+
+    format!("{line}\n{}{}", " ".repeat(indent), "^".repeat(width))
+}
+
+/// A call to a function with a number of arguments its `Arity` does not allow.
+#[derive(Debug)]
+pub struct ArityError {
+    /// The name of the function
+    pub func: String,
+    /// The number of arguments the function accepts
+    pub expected: Arity,
+    /// The number of arguments given at the call site
+    pub found: usize,
+    /// The byte offsets of the whole call in the source string
+    pub span: Range<usize>,
+    /// The line of the source string containing the call, with carets underneath the call
+    pub diagnostic: String,
+}
+impl ArityError {
+    pub fn new(func: &str, expected: Arity, found: usize, source: &str, span: Range<usize>) -> ArityError {
+        ArityError {
+            func: func.to_owned(),
+            expected,
+            found,
+            diagnostic: render_diagnostic(source, &span),
+            span,
+        }
+    }
+}
+impl Error for ArityError {}
+impl Display for ArityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f, "function '{}' expects {} but was called with {} (bytes {}..{})\n{}", 
+            self.func, self.expected, self.found, self.span.start, self.span.end, self.diagnostic
+        )
+    }
+}
+
 #[derive(Debug)]
 pub struct CompiledExpressionLookupError;
 impl_err!(CompiledExpressionLookupError, "failed to find given variable in the function's variable lookup table");
@@ -62,6 +148,15 @@ impl_err! {
     ExpressionCompilationError::VarNotFoundInContext, "found a legal variable in the expression that did not have a variable in the given context"
 }
 
+#[derive(Debug)]
+pub enum DifferentiationError {
+    NoKnownDerivative,
+}
+impl_err! {
+    DifferentiationError,
+    DifferentiationError::NoKnownDerivative, "found a call to a function with no known derivative"
+}
+
 #[derive(Debug)]
 pub enum NewtonRaphsonSolverError {
     NegativeMargin,
@@ -75,6 +170,102 @@ impl_err! {
     NewtonRaphsonSolverError::ImproperlyConstrainedSystem, "number of functions given did not match the number of variables"
 }
 
+#[derive(Debug)]
+pub enum SolverOptionsError {
+    NonPositiveTolerance,
+    InvalidDamping,
+    NonPositiveStep,
+    UnsupportedMethod,
+}
+impl_err! {
+    SolverOptionsError,
+    SolverOptionsError::NonPositiveTolerance, "tolerances must not be negative, and at least one residual tolerance and one step tolerance must be greater than 0",
+    SolverOptionsError::InvalidDamping, "damping must be greater than 0 and no greater than 1",
+    SolverOptionsError::NonPositiveStep, "finite difference step must be greater than 0",
+    SolverOptionsError::UnsupportedMethod, "the chosen method cannot solve this kind of problem"
+}
+
+#[derive(Debug)]
+pub enum LinearSystemError {
+    NotPositiveDefinite,
+}
+impl_err! {
+    LinearSystemError,
+    LinearSystemError::NotPositiveDefinite, "expected a symmetric positive-definite matrix"
+}
+
+/// An iterative solver that reached its iteration limit without converging.
+/// Holds the state of the solver at its last iteration to help diagnose 
+/// why it did not converge.
+#[derive(Debug)]
+pub struct NotConvergedError<T> {
+    /// The last guess evaluated
+    pub last_iterate: T,
+    /// The number of iterations performed
+    pub iterations: usize,
+    /// The size of the residual at `last_iterate`, or NaN if no iterations were performed
+    pub residual: f64,
+    /// The size of the step that was taken from `last_iterate`, or NaN if no iterations were performed
+    pub step: f64,
+    /// Every iteration performed, if the history was requested
+    pub history: Vec<Iteration<T>>,
+}
+impl<T: fmt::Debug> Error for NotConvergedError<T> {}
+impl<T> Display for NotConvergedError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f, "reached the maximum number of iterations without finding a solution ({} iterations, last residual {}, last step {})", 
+            self.iterations, self.residual, self.step
+        )
+    }
+}
+
+/// The jacobian of a system was singular, so no newton step could be found from it.
+/// This happens when some combination of the variables has no effect on the system,
+/// locally or everywhere.
+#[derive(Debug)]
+pub struct SingularJacobianError {
+    /// The rank of the jacobian
+    pub rank: usize,
+    /// The number of variables in the system
+    pub size: usize,
+    /// The variables that can change together without changing the system to first order, in sorted order
+    pub vars: Vec<String>,
+}
+impl Error for SingularJacobianError {}
+impl Display for SingularJacobianError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f, "the jacobian is singular (rank {} of {}), so no step could be found for: {}", 
+            self.rank, self.size, self.vars.join(", ")
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum LineSearchError {
+    NoDescentDirection,
+    NoSufficientDecrease,
+}
+impl_err! {
+    LineSearchError,
+    LineSearchError::NoDescentDirection, "the residual does not decrease in any direction the variables' domains allow",
+    LineSearchError::NoSufficientDecrease, "the line search could not find a step that decreases the residual enough"
+}
+
+#[derive(Debug)]
+pub enum BracketingSolverError {
+    NegativeMargin,
+    NoSignChange,
+    ReachedIterationLimit,
+}
+impl_err! {
+    BracketingSolverError,
+    BracketingSolverError::NegativeMargin, "given margin value must be greater than 0",
+    BracketingSolverError::NoSignChange, "the function has the same sign at both ends of the given interval",
+    BracketingSolverError::ReachedIterationLimit, "reached the maximum number of iterations without finding a solution"
+}
+
 #[derive(Debug)]
 pub enum EquationSolverError {
     SingleUnknownNotFound,
@@ -86,4 +277,16 @@ impl_err!{
     EquationSolverError::SingleUnknownNotFound, "found either no unknowns in given context or too many to solve a single equation",
     EquationSolverError::FoundExpression, "expected an '=' in the given equation string, found none",
     EquationSolverError::FoundMultipleEquations, "expected only '=' in the given equation string, found multiple"
-}
\ No newline at end of file
+}
+#[derive(Debug)]
+pub enum SystemStructureError {
+    NotSquare,
+    StructurallySingular,
+    TooFewEquations,
+}
+impl_err!{
+    SystemStructureError,
+    SystemStructureError::NotSquare, "the number of equations is not equal to the number of unknowns",
+    SystemStructureError::StructurallySingular, "the equations cannot each be matched to a different unknown that appears in them",
+    SystemStructureError::TooFewEquations, "there are fewer equations than unknowns"
+}
@@ -0,0 +1,798 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::context::{ContextHashMap, ContextFn, ContextFnDerivative, Token};
+use crate::dual::Dual;
+use crate::errors::{ShuntingYardError, ExpressionCompilationError, DifferentiationError};
+
+/// Relative step size for differentiating functions that have no known derivative
+const _DX_: f64 = 1e-6;
+
+/// The binary operators understood by the expression parser.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BinaryOp
+{
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+    And,
+    Or,
+}
+
+impl BinaryOp
+{
+    /// Returns the symbol used for this operator in an expression string.
+    pub fn symbol(&self) -> &'static str
+    {
+        match self
+        {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Pow => "^",
+            BinaryOp::Less => "<",
+            BinaryOp::LessEqual => "<=",
+            BinaryOp::Greater => ">",
+            BinaryOp::GreaterEqual => ">=",
+            BinaryOp::Equal => "==",
+            BinaryOp::NotEqual => "!=",
+            BinaryOp::And => "&&",
+            BinaryOp::Or => "||",
+        }
+    }
+
+    /// Returns the binding power of the operator. Higher values bind more tightly.
+    /// 
+    /// From loosest to tightest, operators bind in the same order as in C: 
+    /// `||`, `&&`, `== !=`, `< <= > >=`, `+ -`, `* /`, then `^`.
+    pub fn precedence(&self) -> u8
+    {
+        match self
+        {
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            BinaryOp::Equal | BinaryOp::NotEqual => 3,
+            BinaryOp::Less | BinaryOp::LessEqual | BinaryOp::Greater | BinaryOp::GreaterEqual => 4,
+            BinaryOp::Add | BinaryOp::Sub => 5,
+            BinaryOp::Mul | BinaryOp::Div => 6,
+            BinaryOp::Pow => 7,
+        }
+    }
+
+    /// Indicates whether the operator always gives `1.0` (true) or `0.0` (false).
+    pub fn is_logical(&self) -> bool
+    {
+        !matches!(self, BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Pow)
+    }
+
+    /// Indicates whether a chain of this operator groups from the right (e.g. `2^3^2 == 2^(3^2)`).
+    pub fn is_right_associative(&self) -> bool
+    {
+        matches!(self, BinaryOp::Pow)
+    }
+
+    /// Applies the operator to two values. Comparisons and logical operators 
+    /// give `1.0` for true and `0.0` for false, and treat any value other
+    /// than `0.0` as true.
+    pub fn apply(&self, lhs: f64, rhs: f64) -> anyhow::Result<f64>
+    {
+        let val = match self
+        {
+            BinaryOp::Add => lhs + rhs,
+            BinaryOp::Sub => lhs - rhs,
+            BinaryOp::Mul => lhs * rhs,
+            BinaryOp::Div => {
+                if rhs == 0.0
+                {
+                    return Err(ShuntingYardError::DivisionByZero.into());
+                }
+                lhs / rhs
+            },
+            BinaryOp::Pow => lhs.powf(rhs),
+            BinaryOp::Less => from_bool(lhs < rhs),
+            BinaryOp::LessEqual => from_bool(lhs <= rhs),
+            BinaryOp::Greater => from_bool(lhs > rhs),
+            BinaryOp::GreaterEqual => from_bool(lhs >= rhs),
+            BinaryOp::Equal => from_bool(lhs == rhs),
+            BinaryOp::NotEqual => from_bool(lhs != rhs),
+            BinaryOp::And => from_bool(is_true(lhs) && is_true(rhs)),
+            BinaryOp::Or => from_bool(is_true(lhs) || is_true(rhs)),
+        };
+        Ok(val)
+    }
+}
+
+/// Converts a boolean to the value used for it in expressions.
+fn from_bool(b: bool) -> f64
+{
+    if b { 1.0 } else { 0.0 }
+}
+
+/// Interprets a value as a boolean. Anything other than `0.0` is true.
+fn is_true(x: f64) -> bool
+{
+    x != 0.0
+}
+
+/// The prefix operators understood by the expression parser.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum UnaryOp
+{
+    Neg,
+    Not,
+}
+
+impl UnaryOp
+{
+    /// Returns the symbol used for this operator in an expression string.
+    pub fn symbol(&self) -> &'static str
+    {
+        match self
+        {
+            UnaryOp::Neg => "-",
+            UnaryOp::Not => "!",
+        }
+    }
+
+    /// Returns the binding power of the operator. Unary operators bind as
+    /// tightly as multiplication, so `-x^2` is read as `-(x^2)`.
+    pub fn precedence(&self) -> u8
+    {
+        BinaryOp::Mul.precedence()
+    }
+
+    /// Applies the operator to a value.
+    pub fn apply(&self, arg: f64) -> f64
+    {
+        match self
+        {
+            UnaryOp::Neg => -arg,
+            UnaryOp::Not => from_bool(!is_true(arg)),
+        }
+    }
+}
+
+/// A parsed mathematical expression.
+///
+/// Names are kept in the tree so that an expression can be inspected,
+/// transformed or printed without the `ContextHashMap` it was parsed with.
+/// The context is only needed again to evaluate it.
+///
+/// # Example
+/// ```
+/// use geqslib::shunting::{parse_expr, new_context, ContextLike, Expr, BinaryOp};
+///
+/// let mut ctx = new_context();
+/// ctx.add_var_to_ctx("x", 2);
+///
+/// let expr = parse_expr("3 * x + sin(pi)", &ctx).unwrap();
+///
+/// assert!(matches!(expr, Expr::Binary(BinaryOp::Add, _, _)));
+/// assert_eq!(expr.to_string(), "3 * x + sin(pi)");
+/// assert!((expr.eval(&ctx).unwrap() - 6.0).abs() < 0.0001);
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr
+{
+    /// A numeric literal.
+    Num(f64),
+    /// A named constant and the value it had in the context at parse time.
+    Const(String, f64),
+    /// A named variable whose value is read from the context on evaluation.
+    Var(String),
+    /// A prefix operator applied to an expression.
+    Unary(UnaryOp, Box<Expr>),
+    /// A binary operator applied to a left and right expression.
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    /// A call to a named function in the context with its arguments in call order.
+    Call(String, Vec<Expr>),
+    /// A conditional `if(condition, if_true, if_false)`. Only the branch 
+    /// picked by the condition is evaluated.
+    If(Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+impl Expr
+{
+    /// Returns the binding power of the expression's outermost operator for
+    /// the purpose of printing. Atoms bind more tightly than any operator.
+    fn precedence(&self) -> u8
+    {
+        match self
+        {
+            Expr::Num(x) if x.is_sign_negative() => UnaryOp::Neg.precedence(),
+            Expr::Unary(op, _) => op.precedence(),
+            Expr::Binary(op, _, _) => op.precedence(),
+            _ => u8::MAX,
+        }
+    }
+
+    /// Evaluates the expression, reading variable values and functions from
+    /// the given `ContextHashMap`.
+    ///
+    /// # Example
+    /// ```
+    /// use geqslib::shunting::{parse_expr, new_context, ContextLike};
+    ///
+    /// let mut ctx = new_context();
+    /// ctx.add_var_to_ctx("x", 3);
+    ///
+    /// let expr = parse_expr("x^2 - 1", &ctx).unwrap();
+    ///
+    /// assert_eq!(expr.eval(&ctx).unwrap(), 8.0);
+    /// ```
+    pub fn eval(&self, ctx: &ContextHashMap) -> anyhow::Result<f64>
+    {
+        match self
+        {
+            Expr::Num(x) | Expr::Const(_, x) => Ok(*x),
+
+            Expr::Var(name) => match ctx.get(name)
+            {
+                Some(Token::Var(v)) => Ok((*v.borrow()).into()),
+                _ => Err(ExpressionCompilationError::VarNotFoundInContext.into()),
+            },
+
+            Expr::Unary(op, arg) => Ok(op.apply(arg.eval(ctx)?)),
+
+            // `&&` and `||` short-circuit, so `x != 0 && 1 / x > 2` is safe at `x = 0`
+            Expr::Binary(BinaryOp::And, lhs, rhs) => Ok(from_bool(is_true(lhs.eval(ctx)?) && is_true(rhs.eval(ctx)?))),
+            Expr::Binary(BinaryOp::Or, lhs, rhs) => Ok(from_bool(is_true(lhs.eval(ctx)?) || is_true(rhs.eval(ctx)?))),
+
+            Expr::Binary(op, lhs, rhs) => op.apply(lhs.eval(ctx)?, rhs.eval(ctx)?),
+
+            Expr::If(cond, if_true, if_false) => if is_true(cond.eval(ctx)?)
+            {
+                if_true.eval(ctx)
+            }
+            else
+            {
+                if_false.eval(ctx)
+            },
+
+            Expr::Call(name, args) => {
+                let (func, _) = lookup_func(name, args.len(), ctx)?;
+
+                // Functions receive their arguments in call order
+                let mut arguments = Vec::with_capacity(args.len());
+                for arg in args
+                {
+                    arguments.push(arg.eval(ctx)?);
+                }
+                func(&arguments)
+            },
+        }
+    }
+
+    /// Evaluates the expression with dual numbers, giving both its value 
+    /// and its exact derivative with respect to the variable `var`.
+    /// 
+    /// Functions registered with a derivative (including every function 
+    /// from `new_context`) are differentiated exactly. Functions registered
+    /// without one are differentiated with a central difference on just 
+    /// that call.
+    ///
+    /// # Example
+    /// ```
+    /// use geqslib::shunting::{parse_expr, new_context, ContextLike};
+    ///
+    /// let mut ctx = new_context();
+    /// ctx.add_var_to_ctx("x", 2);
+    /// ctx.add_var_to_ctx("y", 3);
+    ///
+    /// let expr = parse_expr("x^2 * y", &ctx).unwrap();
+    /// let d_dx = expr.eval_dual(&ctx, "x").unwrap();
+    ///
+    /// assert_eq!(d_dx.value, 12.0);
+    /// assert_eq!(d_dx.deriv, 12.0);
+    /// ```
+    pub fn eval_dual(&self, ctx: &ContextHashMap, var: &str) -> anyhow::Result<Dual>
+    {
+        match self
+        {
+            Expr::Num(x) | Expr::Const(_, x) => Ok(Dual::constant(*x)),
+
+            Expr::Var(name) => {
+                let value = self.eval(ctx)?;
+                if name == var
+                {
+                    Ok(Dual::variable(value))
+                }
+                else
+                {
+                    Ok(Dual::constant(value))
+                }
+            },
+
+            Expr::Unary(UnaryOp::Neg, arg) => Ok(-arg.eval_dual(ctx, var)?),
+
+            // Comparisons and logic are piecewise constant
+            Expr::Unary(UnaryOp::Not, _) => Ok(Dual::constant(self.eval(ctx)?)),
+            Expr::Binary(op, _, _) if op.is_logical() => Ok(Dual::constant(self.eval(ctx)?)),
+
+            Expr::If(cond, if_true, if_false) => if is_true(cond.eval(ctx)?)
+            {
+                if_true.eval_dual(ctx, var)
+            }
+            else
+            {
+                if_false.eval_dual(ctx, var)
+            },
+
+            Expr::Binary(op, lhs, rhs) => {
+                let (u, v) = (lhs.eval_dual(ctx, var)?, rhs.eval_dual(ctx, var)?);
+                let res = match op
+                {
+                    BinaryOp::Add => u + v,
+                    BinaryOp::Sub => u - v,
+                    BinaryOp::Mul => u * v,
+                    BinaryOp::Div => {
+                        if v.value == 0.0
+                        {
+                            return Err(ShuntingYardError::DivisionByZero.into());
+                        }
+                        u / v
+                    },
+                    BinaryOp::Pow => u.powf(v),
+                    _ => unreachable!("logical operators are handled above"),
+                };
+                Ok(res)
+            },
+
+            Expr::Call(name, args) => {
+                let (func, derivative) = lookup_func(name, args.len(), ctx)?;
+
+                let mut arguments = Vec::with_capacity(args.len());
+                let mut derivs = Vec::with_capacity(args.len());
+                for arg in args
+                {
+                    let d = arg.eval_dual(ctx, var)?;
+                    arguments.push(d.value);
+                    derivs.push(d.deriv);
+                }
+
+                // Chain rule: sum the partial derivative of each argument times its derivative
+                let mut deriv = 0.0;
+                for (i, arg_deriv) in derivs.iter().enumerate()
+                {
+                    if *arg_deriv == 0.0
+                    {
+                        continue;
+                    }
+
+                    let partial = match derivative
+                    {
+                        Some(d) => d(&arguments, i)?,
+                        None => {
+                            let mut shifted = arguments.clone();
+                            let h = _DX_ * arguments[i].abs().max(1.0);
+                            shifted[i] = arguments[i] + h;
+                            let forward = func(&shifted)?;
+                            shifted[i] = arguments[i] - h;
+                            let backward = func(&shifted)?;
+                            (forward - backward) / (2.0 * h)
+                        },
+                    };
+                    deriv += partial * arg_deriv;
+                }
+
+                Ok(Dual::new(func(&arguments)?, deriv))
+            },
+        }
+    }
+
+    /// Returns references to the direct sub-expressions of this expression.
+    pub fn children(&self) -> Vec<&Expr>
+    {
+        match self
+        {
+            Expr::Num(_) | Expr::Const(_, _) | Expr::Var(_) => vec![],
+            Expr::Unary(_, arg) => vec![arg],
+            Expr::Binary(_, lhs, rhs) => vec![lhs, rhs],
+            Expr::Call(_, args) => args.iter().collect(),
+            Expr::If(cond, if_true, if_false) => vec![cond, if_true, if_false],
+        }
+    }
+
+    /// Visits this expression and every sub-expression in pre-order,
+    /// i.e. each node is visited before its children.
+    ///
+    /// # Example
+    /// ```
+    /// use geqslib::shunting::{parse_expr, new_context, ContextLike, Expr};
+    ///
+    /// let mut ctx = new_context();
+    /// ctx.add_var_to_ctx("x", 1);
+    ///
+    /// let expr = parse_expr("sin(x) + cos(2 * x)", &ctx).unwrap();
+    ///
+    /// let mut calls = vec![];
+    /// expr.walk(&mut |e| if let Expr::Call(name, _) = e { calls.push(name.clone()) });
+    ///
+    /// assert_eq!(calls, vec!["sin", "cos"]);
+    /// ```
+    pub fn walk<F>(&self, f: &mut F)
+    where F: FnMut(&Expr)
+    {
+        f(self);
+        for child in self.children()
+        {
+            child.walk(f);
+        }
+    }
+
+    /// Returns the names of all variables that appear in the expression.
+    ///
+    /// # Example
+    /// ```
+    /// use geqslib::shunting::{parse_expr, new_context, ContextLike};
+    ///
+    /// let mut ctx = new_context();
+    /// ctx.add_var_to_ctx("x", 1);
+    /// ctx.add_var_to_ctx("y", 1);
+    ///
+    /// let expr = parse_expr("x * y + pi * x", &ctx).unwrap();
+    /// let vars = expr.variables();
+    ///
+    /// assert_eq!(vars.len(), 2);
+    /// assert!(vars.contains("x"));
+    /// assert!(vars.contains("y"));
+    /// ```
+    pub fn variables(&self) -> HashSet<&str>
+    {
+        let mut vars = HashSet::new();
+        self.collect_variables(&mut vars);
+        vars
+    }
+
+    fn collect_variables<'a>(&'a self, vars: &mut HashSet<&'a str>)
+    {
+        if let Expr::Var(name) = self
+        {
+            vars.insert(name);
+        }
+        for child in self.children()
+        {
+            child.collect_variables(vars);
+        }
+    }
+
+    /// Indicates whether the variable `var` appears anywhere in the expression.
+    pub fn depends_on(&self, var: &str) -> bool
+    {
+        match self
+        {
+            Expr::Var(name) => name == var,
+            _ => self.children().iter().any(|child| child.depends_on(var)),
+        }
+    }
+
+    /// Returns the exact derivative of the expression with respect to `var`.
+    ///
+    /// Derivatives are known for `+ - * / ^`, every function registered by 
+    /// `new_context` and the conditional `if` function. Calls to any other 
+    /// function are only allowed if their arguments do not depend on `var`.
+    /// 
+    /// The returned expression may call `ln`, `sin`, `cos`, `cosh`, `sinh`
+    /// or `abs`, so it should be evaluated with a context that defines them
+    /// in the same way that `new_context` does.
+    ///
+    /// # Example
+    /// ```
+    /// use geqslib::shunting::{parse_expr, new_context, ContextLike};
+    ///
+    /// let mut ctx = new_context();
+    /// ctx.add_var_to_ctx("x", 2);
+    ///
+    /// let expr = parse_expr("x^3 + sin(x)", &ctx).unwrap();
+    /// let d_dx = expr.derivative("x").unwrap();
+    ///
+    /// assert_eq!(d_dx.to_string(), "3 * x^2 + cos(x)");
+    /// assert!((d_dx.eval(&ctx).unwrap() - (12.0 + 2f64.cos())).abs() < 1e-12);
+    /// ```
+    pub fn derivative(&self, var: &str) -> anyhow::Result<Expr>
+    {
+        if !self.depends_on(var)
+        {
+            return Ok(Expr::Num(0.0));
+        }
+
+        let d = match self
+        {
+            Expr::Num(_) | Expr::Const(_, _) => Expr::Num(0.0),
+
+            Expr::Var(_) => Expr::Num(1.0), // must be `var` if we depend on it
+
+            Expr::Unary(UnaryOp::Neg, u) => neg(u.derivative(var)?),
+
+            // Comparisons and logic are piecewise constant
+            Expr::Unary(UnaryOp::Not, _) => Expr::Num(0.0),
+            Expr::Binary(op, _, _) if op.is_logical() => Expr::Num(0.0),
+
+            Expr::If(cond, if_true, if_false) => Expr::If(
+                cond.clone(), 
+                Box::new(if_true.derivative(var)?), 
+                Box::new(if_false.derivative(var)?)
+            ),
+
+            Expr::Binary(op, u, v) => {
+                let (u, v) = (u.as_ref(), v.as_ref());
+                match op
+                {
+                    BinaryOp::Add => add(u.derivative(var)?, v.derivative(var)?),
+                    BinaryOp::Sub => sub(u.derivative(var)?, v.derivative(var)?),
+                    BinaryOp::Mul => add(
+                        mul(u.derivative(var)?, v.clone()), 
+                        mul(u.clone(), v.derivative(var)?)
+                    ),
+                    BinaryOp::Div => div(
+                        sub(mul(u.derivative(var)?, v.clone()), mul(u.clone(), v.derivative(var)?)), 
+                        pow(v.clone(), Expr::Num(2.0))
+                    ),
+                    BinaryOp::Pow if !v.depends_on(var) => mul(
+                        mul(v.clone(), pow(u.clone(), sub(v.clone(), Expr::Num(1.0)))), 
+                        u.derivative(var)?
+                    ),
+                    BinaryOp::Pow if !u.depends_on(var) => mul(
+                        mul(self.clone(), call("ln", vec![u.clone()])), 
+                        v.derivative(var)?
+                    ),
+                    BinaryOp::Pow => mul(
+                        self.clone(), 
+                        add(
+                            mul(v.derivative(var)?, call("ln", vec![u.clone()])),
+                            div(mul(v.clone(), u.derivative(var)?), u.clone())
+                        )
+                    ),
+                    _ => unreachable!("logical operators are handled above"),
+                }
+            },
+
+            Expr::Call(name, args) => return call_derivative(name, args, var),
+        };
+
+        Ok(d)
+    }
+}
+
+/// Finds a function in the context and checks that it accepts `num_args` arguments.
+fn lookup_func<'a>(name: &str, num_args: usize, ctx: &'a ContextHashMap) -> anyhow::Result<(&'a ContextFn, Option<&'a ContextFnDerivative>)>
+{
+    let (arity, func, derivative) = match ctx.get(name)
+    {
+        Some(Token::Func(arity, f, d)) => (arity, f, d.as_ref()),
+        _ => return Err(ShuntingYardError::UnknownToken.into()),
+    };
+
+    if num_args < arity.min_args()
+    {
+        return Err(ShuntingYardError::ExpectedArg.into());
+    }
+    else if !arity.accepts(num_args)
+    {
+        return Err(ShuntingYardError::LeftoverToken.into());
+    }
+
+    Ok((func, derivative))
+}
+
+/// Differentiates a call to one of the functions defined by `new_context`.
+fn call_derivative(name: &str, args: &[Expr], var: &str) -> anyhow::Result<Expr>
+{
+    if name == "log" && args.len() == 2
+    {
+        // `log(x, b)` gives the base-`b` logarithm of `x`
+        let as_ln = div(call("ln", vec![args[0].clone()]), call("ln", vec![args[1].clone()]));
+        return as_ln.derivative(var);
+    }
+
+    if name == "if" && args.len() == 5
+    {
+        // The derivative of a piecewise function is the piecewise derivative
+        let mut d_args = args[..3].to_vec();
+        d_args.push(args[3].derivative(var)?);
+        d_args.push(args[4].derivative(var)?);
+        return Ok(call("if", d_args));
+    }
+
+    match name
+    {
+        "sum" | "mean" => {
+            let mut d = Expr::Num(0.0);
+            for arg in args
+            {
+                d = add(d, arg.derivative(var)?);
+            }
+            return Ok(if name == "mean" { div(d, Expr::Num(args.len() as f64)) } else { d });
+        },
+        "hypot" => {
+            let mut d = Expr::Num(0.0);
+            for arg in args
+            {
+                d = add(d, mul(arg.clone(), arg.derivative(var)?));
+            }
+            return Ok(div(d, call("hypot", args.to_vec())));
+        },
+        "min" | "max" if !args.is_empty() => {
+            // Follows whichever argument is picked, checking them in order
+            let picked = call(name, args.to_vec());
+            let (last, rest) = args.split_last().unwrap();
+            let mut d = last.derivative(var)?;
+            for arg in rest.iter().rev()
+            {
+                let cond = Expr::Binary(BinaryOp::Equal, Box::new(arg.clone()), Box::new(picked.clone()));
+                d = Expr::If(Box::new(cond), Box::new(arg.derivative(var)?), Box::new(d));
+            }
+            return Ok(d);
+        },
+        _ => (),
+    }
+
+    if args.len() != 1
+    {
+        return Err(DifferentiationError::NoKnownDerivative.into());
+    }
+
+    let u = &args[0];
+    let one_minus_u_squared = sub(Expr::Num(1.0), pow(u.clone(), Expr::Num(2.0)));
+    let d_outer = match name
+    {
+        "sin"    => call("cos", vec![u.clone()]),
+        "cos"    => neg(call("sin", vec![u.clone()])),
+        "tan"    => div(Expr::Num(1.0), pow(call("cos", vec![u.clone()]), Expr::Num(2.0))),
+        "arcsin" => div(Expr::Num(1.0), pow(one_minus_u_squared, Expr::Num(0.5))),
+        "arccos" => neg(div(Expr::Num(1.0), pow(one_minus_u_squared, Expr::Num(0.5)))),
+        "arctan" => div(Expr::Num(1.0), add(Expr::Num(1.0), pow(u.clone(), Expr::Num(2.0)))),
+        "sinh"   => call("cosh", vec![u.clone()]),
+        "cosh"   => call("sinh", vec![u.clone()]),
+        "tanh"   => div(Expr::Num(1.0), pow(call("cosh", vec![u.clone()]), Expr::Num(2.0))),
+        "ln"     => div(Expr::Num(1.0), u.clone()),
+        "log10"  => div(Expr::Num(1.0), mul(u.clone(), Expr::Num(std::f64::consts::LN_10))),
+        "abs"    => div(u.clone(), call("abs", vec![u.clone()])),
+        _ => return Err(DifferentiationError::NoKnownDerivative.into()),
+    };
+
+    Ok(mul(d_outer, u.derivative(var)?))
+}
+
+/// Builds `u + v`, folding away zeros and numeric literals.
+fn add(u: Expr, v: Expr) -> Expr
+{
+    match (u, v)
+    {
+        (Expr::Num(a), Expr::Num(b)) => Expr::Num(a + b),
+        (Expr::Num(0.0), other) | (other, Expr::Num(0.0)) => other,
+        (u, Expr::Unary(UnaryOp::Neg, v)) => sub(u, *v),
+        (u, v) => Expr::Binary(BinaryOp::Add, Box::new(u), Box::new(v)),
+    }
+}
+
+/// Builds `u - v`, folding away zeros and numeric literals.
+fn sub(u: Expr, v: Expr) -> Expr
+{
+    match (u, v)
+    {
+        (Expr::Num(a), Expr::Num(b)) => Expr::Num(a - b),
+        (u, Expr::Num(0.0)) => u,
+        (Expr::Num(0.0), v) => neg(v),
+        (u, v) => Expr::Binary(BinaryOp::Sub, Box::new(u), Box::new(v)),
+    }
+}
+
+/// Builds `u * v`, folding away zeros, ones and numeric literals.
+fn mul(u: Expr, v: Expr) -> Expr
+{
+    match (u, v)
+    {
+        (Expr::Num(a), Expr::Num(b)) => Expr::Num(a * b),
+        (Expr::Num(0.0), _) | (_, Expr::Num(0.0)) => Expr::Num(0.0),
+        (Expr::Num(1.0), other) | (other, Expr::Num(1.0)) => other,
+        (Expr::Num(-1.0), other) | (other, Expr::Num(-1.0)) => neg(other),
+        (u, Expr::Num(a)) => Expr::Binary(BinaryOp::Mul, Box::new(Expr::Num(a)), Box::new(u)),
+        (u, v) => Expr::Binary(BinaryOp::Mul, Box::new(u), Box::new(v)),
+    }
+}
+
+/// Builds `u / v`, folding away zeros and ones.
+fn div(u: Expr, v: Expr) -> Expr
+{
+    match (u, v)
+    {
+        (Expr::Num(0.0), _) => Expr::Num(0.0),
+        (u, Expr::Num(1.0)) => u,
+        (u, v) => Expr::Binary(BinaryOp::Div, Box::new(u), Box::new(v)),
+    }
+}
+
+/// Builds `u ^ v`, folding away trivial exponents.
+fn pow(u: Expr, v: Expr) -> Expr
+{
+    match (u, v)
+    {
+        (_, Expr::Num(0.0)) => Expr::Num(1.0),
+        (u, Expr::Num(1.0)) => u,
+        (u, v) => Expr::Binary(BinaryOp::Pow, Box::new(u), Box::new(v)),
+    }
+}
+
+/// Builds `-u`, cancelling double negatives.
+fn neg(u: Expr) -> Expr
+{
+    match u
+    {
+        Expr::Num(a) => Expr::Num(-a),
+        Expr::Unary(UnaryOp::Neg, inner) => *inner,
+        u => Expr::Unary(UnaryOp::Neg, Box::new(u)),
+    }
+}
+
+/// Builds a call to a named function.
+fn call(name: &str, args: Vec<Expr>) -> Expr
+{
+    Expr::Call(name.to_owned(), args)
+}
+
+impl fmt::Display for Expr
+{
+    /// Prints the expression with the minimum number of parentheses
+    /// required for it to parse back to the same tree.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            Expr::Num(x) => write!(f, "{x}"),
+
+            Expr::Const(name, _) | Expr::Var(name) => write!(f, "{name}"),
+
+            Expr::Unary(op, arg) => {
+                // Parenthesize anything that would grab the unary operator's operand
+                if arg.precedence() <= op.precedence()
+                {
+                    write!(f, "{}({arg})", op.symbol())
+                }
+                else
+                {
+                    write!(f, "{}{arg}", op.symbol())
+                }
+            },
+
+            Expr::Binary(op, lhs, rhs) => {
+                let wrap_lhs = lhs.precedence() < op.precedence()
+                    || (lhs.precedence() == op.precedence() && op.is_right_associative());
+                let wrap_rhs = rhs.precedence() < op.precedence()
+                    || (rhs.precedence() == op.precedence() && !op.is_right_associative());
+
+                if wrap_lhs { write!(f, "({lhs})")? } else { write!(f, "{lhs}")? }
+                match op
+                {
+                    BinaryOp::Pow => write!(f, "{}", op.symbol())?,
+                    _ => write!(f, " {} ", op.symbol())?,
+                }
+                if wrap_rhs { write!(f, "({rhs})") } else { write!(f, "{rhs}") }
+            },
+
+            Expr::Call(name, args) => {
+                write!(f, "{name}(")?;
+                for (i, arg) in args.iter().enumerate()
+                {
+                    if i > 0
+                    {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{arg}")?;
+                }
+                write!(f, ")")
+            },
+
+            Expr::If(cond, if_true, if_false) => write!(f, "if({cond}, {if_true}, {if_false})"),
+        }
+    }
+}
@@ -2,6 +2,7 @@ use std::ffi::{c_char, c_int, c_void, c_double, c_uint, CStr, CString};
 use std::panic::catch_unwind;
 use std::ptr::{null, copy_nonoverlapping};
 
+use crate::options::{FiniteDifference, Method, Norm, SolverOptions};
 use crate::shunting::{ContextHashMap, new_context, ContextLike};
 use crate::solve_equation_with_context;
 use crate::system::{System, SystemBuilder, ConstrainResult};
@@ -28,6 +29,9 @@ unsafe fn destroy_object<T>(p_obj: *mut T)
 }
 
 /// Creates a new empty `ContextHashMap` and returns a C-compatible `void *` to it.
+/// 
+/// # Safety
+/// The returned pointer must be freed with `free_context_hash_map`.
 #[no_mangle]
 pub unsafe extern "C" fn new_context_hash_map() -> *mut c_void
 {
@@ -35,6 +39,9 @@ pub unsafe extern "C" fn new_context_hash_map() -> *mut c_void
 }
 
 /// Creates a new `ContextHashMap` created via `new_context` and returns a C-compatible `void *` to it.
+/// 
+/// # Safety
+/// The returned pointer must be freed with `free_context_hash_map`.
 #[no_mangle]
 pub unsafe extern "C" fn new_default_context_hash_map() -> *mut c_void
 {
@@ -42,6 +49,10 @@ pub unsafe extern "C" fn new_default_context_hash_map() -> *mut c_void
 }
 
 /// Adds a constant value to the `ContextHashMap` at the given pointer.
+/// 
+/// # Safety
+/// `context` must point to a live `ContextHashMap` created by this library and 
+/// `name` must be a valid nul-terminated string.
 #[no_mangle]
 pub unsafe extern "C" fn add_const_to_ctx(context: *mut c_void, name: *const c_char, val: c_double)
 {
@@ -51,8 +62,12 @@ pub unsafe extern "C" fn add_const_to_ctx(context: *mut c_void, name: *const c_c
 
 /// Solves a single-unknown equation for a single unknown variable, returning the solution as a
 /// nul-terminated C `char *` on success or `NULL` on failure.
+/// 
+/// # Safety
+/// `equation` must be a valid nul-terminated string and `context` must point to 
+/// a live `ContextHashMap` created by this library.
 #[no_mangle]
-pub extern "C" fn solve_equation(equation: *const c_char, context: *const c_void, guess: c_double, min: c_double, max: c_double, margin: c_double, limit: c_uint) -> *const c_char
+pub unsafe extern "C" fn solve_equation(equation: *const c_char, context: *const c_void, guess: c_double, min: c_double, max: c_double, margin: c_double, limit: c_uint) -> *const c_char
 {
     let res = catch_unwind(|| {
         let equation_str = unsafe { new_owned_string(equation) };
@@ -81,8 +96,12 @@ pub extern "C" fn solve_equation(equation: *const c_char, context: *const c_void
 }
 
 /// Allocates a new `SystemBuilder` object on the Rust side of the FFI and returns a raw pointer to it.
+/// 
+/// # Safety
+/// `equation` must be a valid nul-terminated string and `context` must point to 
+/// a live `ContextHashMap` created by this library.
 #[no_mangle]
-pub extern "C" fn new_system_builder(equation: *const c_char, context: *const c_void) -> *const c_void
+pub unsafe extern "C" fn new_system_builder(equation: *const c_char, context: *const c_void) -> *const c_void
 {
     let res = catch_unwind(|| {
         let equation_str = unsafe { new_owned_string(equation) };
@@ -112,8 +131,12 @@ pub extern "C" fn new_system_builder(equation: *const c_char, context: *const c_
 /// - `1`: The equation further constrained the system and was added successfully
 /// - `2`: The equation will over-constrain the system and was not added
 /// - `-1`: An error occurred while trying to constrain the system
+/// 
+/// # Safety
+/// `p_builder` must point to a live `SystemBuilder` created by this library and 
+/// `equation` must be a valid nul-terminated string.
 #[no_mangle]
-pub extern "C" fn try_constrain_with(p_builder: *mut c_void, equation: *const c_char) -> c_int
+pub unsafe extern "C" fn try_constrain_with(p_builder: *mut c_void, equation: *const c_char) -> c_int
 {
     let res = catch_unwind(|| {
         let builder = p_builder as *mut SystemBuilder;
@@ -175,6 +198,9 @@ pub extern "C" fn build_system(p_builder: *mut c_void) -> *const c_void
 }
 
 /// Prints information about a `SystemBuilder` for debugging purposes.
+/// 
+/// # Safety
+/// `p_builder` must point to a live `SystemBuilder` created by this library.
 #[no_mangle]
 pub unsafe extern "C" fn debug_system_builder(p_builder: *const c_void)
 {
@@ -186,8 +212,12 @@ pub unsafe extern "C" fn debug_system_builder(p_builder: *const c_void)
 /// The returned C `int` value indicates the following:
 /// - `1`: The values were specified successfully
 /// - `-1`: An error occurred while specifying the domain or guess value 
+/// 
+/// # Safety
+/// `p_system` must point to a live `System` created by this library and 
+/// `var` must be a valid nul-terminated string.
 #[no_mangle]
-pub extern "C" fn specify_variable(p_system: *mut c_void, var: *const c_char, guess: c_double, min: c_double, max: c_double) -> c_int
+pub unsafe extern "C" fn specify_variable(p_system: *mut c_void, var: *const c_char, guess: c_double, min: c_double, max: c_double) -> c_int
 {
     let res = catch_unwind(|| {
         unsafe
@@ -209,11 +239,30 @@ pub extern "C" fn specify_variable(p_system: *mut c_void, var: *const c_char, gu
 /// solution to the system or `NULL` if the solution failed.
 #[no_mangle]
 pub extern "C" fn solve_system(p_system: *mut c_void, margin: c_double, limit: c_uint) -> *const c_char
+{
+    solve_system_impl(p_system, &SolverOptions::with_margin(margin, limit as usize))
+}
+
+/// Identical to `solve_system`, but configured by the `SolverOptions` at `p_options`
+/// instead of a margin and iteration limit.
+/// 
+/// # Safety
+/// `p_system` must point to a live `System` created by this library, which is consumed,
+/// and `p_options` must point to a live `SolverOptions` created by this library.
+#[no_mangle]
+pub unsafe extern "C" fn solve_system_with_options(p_system: *mut c_void, p_options: *const c_void) -> *const c_char
+{
+    let options = (*(p_options as *const SolverOptions)).clone();
+    solve_system_impl(p_system, &options)
+}
+
+/// Solves the `System` at the given pointer, consuming it, and formats the solution as a C `char *`.
+fn solve_system_impl(p_system: *mut c_void, options: &SolverOptions) -> *const c_char
 {
     let res = catch_unwind(|| {
         let system = unsafe { Box::from_raw(p_system as *mut System) };
 
-        let soln = match system.solve(margin, limit as usize)
+        let soln = match system.solve_with_options(options)
         {
             Ok(s) => s,
             Err(_) => return null() as *const c_char,
@@ -237,7 +286,145 @@ pub extern "C" fn solve_system(p_system: *mut c_void, margin: c_double, limit: c
     }
 }
 
+/// Creates a new `SolverOptions` with its default values and returns a C-compatible `void *` to it.
+/// 
+/// # Safety
+/// The returned pointer must be freed with `free_solver_options`.
+#[no_mangle]
+pub unsafe extern "C" fn new_solver_options() -> *mut c_void
+{
+    leak_object(SolverOptions::new()) as *mut c_void
+}
+
+/// Sets the absolute and relative residual tolerances and the step tolerance 
+/// of the `SolverOptions` at the given pointer.
+/// 
+/// # Safety
+/// `p_options` must point to a live `SolverOptions` created by this library.
+#[no_mangle]
+pub unsafe extern "C" fn set_solver_tolerances(p_options: *mut c_void, abs_tol: c_double, rel_tol: c_double, step_tol: c_double)
+{
+    let options = &mut *(p_options as *mut SolverOptions);
+    *options = options.clone()
+        .abs_tol(abs_tol)
+        .rel_tol(rel_tol)
+        .step_tol(step_tol);
+}
+
+/// Sets the relative step tolerance of the `SolverOptions` at the given pointer.
+/// 
+/// # Safety
+/// `p_options` must point to a live `SolverOptions` created by this library.
+#[no_mangle]
+pub unsafe extern "C" fn set_solver_rel_step_tol(p_options: *mut c_void, rel_step_tol: c_double)
+{
+    let options = &mut *(p_options as *mut SolverOptions);
+    *options = options.clone().rel_step_tol(rel_step_tol);
+}
+
+/// Sets the norm of the `SolverOptions` at the given pointer. The given C `int` 
+/// value selects the norm as follows:
+/// 
+/// - `0`: `Norm::L1`
+/// - `1`: `Norm::L2`
+/// - `2`: `Norm::LInf`
+/// 
+/// The returned C `int` value is `1` if the norm was set or `-1` if the value was not recognized.
+/// 
+/// # Safety
+/// `p_options` must point to a live `SolverOptions` created by this library.
+#[no_mangle]
+pub unsafe extern "C" fn set_solver_norm(p_options: *mut c_void, norm: c_int) -> c_int
+{
+    let norm = match norm
+    {
+        0 => Norm::L1,
+        1 => Norm::L2,
+        2 => Norm::LInf,
+        _ => return -1,
+    };
+
+    let options = &mut *(p_options as *mut SolverOptions);
+    *options = options.clone().norm(norm);
+    1
+}
+
+/// Sets the maximum number of iterations of the `SolverOptions` at the given pointer.
+/// 
+/// # Safety
+/// `p_options` must point to a live `SolverOptions` created by this library.
+#[no_mangle]
+pub unsafe extern "C" fn set_solver_max_iterations(p_options: *mut c_void, limit: c_uint)
+{
+    let options = &mut *(p_options as *mut SolverOptions);
+    *options = options.clone().max_iterations(limit as usize);
+}
+
+/// Sets the finite difference step of the `SolverOptions` at the given pointer.
+/// The step is relative to the magnitude of each variable if `relative` is 
+/// non-zero and absolute otherwise.
+/// 
+/// # Safety
+/// `p_options` must point to a live `SolverOptions` created by this library.
+#[no_mangle]
+pub unsafe extern "C" fn set_solver_finite_difference(p_options: *mut c_void, step: c_double, relative: c_int)
+{
+    let finite_difference = if relative != 0 { FiniteDifference::Relative(step) } else { FiniteDifference::Absolute(step) };
+    let options = &mut *(p_options as *mut SolverOptions);
+    *options = options.clone().finite_difference(finite_difference);
+}
+
+/// Sets the damping of the `SolverOptions` at the given pointer.
+/// 
+/// # Safety
+/// `p_options` must point to a live `SolverOptions` created by this library.
+#[no_mangle]
+pub unsafe extern "C" fn set_solver_damping(p_options: *mut c_void, damping: c_double)
+{
+    let options = &mut *(p_options as *mut SolverOptions);
+    *options = options.clone().damping(damping);
+}
+
+/// Sets the method of the `SolverOptions` at the given pointer. The given C `int` 
+/// value selects the method as follows:
+/// 
+/// - `0`: `Method::Auto`
+/// - `1`: `Method::Newton`
+/// - `2`: `Method::Bisection`
+/// - `3`: `Method::Illinois`
+/// - `4`: `Method::Brent`
+/// - `5`: `Method::LevenbergMarquardt`
+/// - `6`: `Method::Broyden`
+/// 
+/// The returned C `int` value is `1` if the method was set or `-1` if the value was not recognized.
+/// 
+/// # Safety
+/// `p_options` must point to a live `SolverOptions` created by this library.
+#[no_mangle]
+pub unsafe extern "C" fn set_solver_method(p_options: *mut c_void, method: c_int) -> c_int
+{
+    let method = match method
+    {
+        0 => Method::Auto,
+        1 => Method::Newton,
+        2 => Method::Bisection,
+        3 => Method::Illinois,
+        4 => Method::Brent,
+        5 => Method::LevenbergMarquardt,
+        6 => Method::Broyden,
+        _ => return -1,
+    };
+
+    let options = &mut *(p_options as *mut SolverOptions);
+    *options = options.clone().method(method);
+    1
+}
+
 /// Frees a `ContextHashMap` object at the given pointer
+/// 
+/// # Safety
+/// `p_context` must point to a live `ContextHashMap` created by this library.
+/// The pointer must not be used after this call.
 #[no_mangle]
 pub unsafe extern "C" fn free_context_hash_map(p_context: *mut c_void)
 {
@@ -245,6 +432,10 @@ pub unsafe extern "C" fn free_context_hash_map(p_context: *mut c_void)
 }
 
 /// Frees a `SystemBuilder` object at the given pointer
+/// 
+/// # Safety
+/// `p_builder` must point to a live `SystemBuilder` created by this library.
+/// The pointer must not be used after this call.
 #[no_mangle]
 pub unsafe extern "C" fn free_system_builder(p_builder: *mut c_void)
 {
@@ -252,13 +443,32 @@ pub unsafe extern "C" fn free_system_builder(p_builder: *mut c_void)
 }
 
 /// Frees a `System` object at the given pointer
+/// 
+/// # Safety
+/// `p_system` must point to a live `System` created by this library.
+/// The pointer must not be used after this call.
 #[no_mangle]
 pub unsafe extern "C" fn free_system(p_system: *mut c_void)
 {
     destroy_object(p_system as *mut System);
 }
 
+/// Frees a `SolverOptions` object at the given pointer
+/// 
+/// # Safety
+/// `p_options` must point to a live `SolverOptions` created by this library.
+/// The pointer must not be used after this call.
+#[no_mangle]
+pub unsafe extern "C" fn free_solver_options(p_options: *mut c_void)
+{
+    destroy_object(p_options as *mut SolverOptions);
+}
+
 /// Frees the nul-terminated `char *` given
+/// 
+/// # Safety
+/// `soln_str` must be a solution string returned by this library.
+/// The pointer must not be used after this call.
 #[no_mangle]
 pub unsafe extern "C" fn free_solution_string(soln_str: *mut c_char)
 {
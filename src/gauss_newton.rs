@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use gmatlib::Matrix;
+use crate::errors::{NewtonRaphsonSolverError, NotConvergedError};
+use crate::linalg::{cholesky_solve, normal_equations};
+use crate::newton::{eval_system, exact_jacobian_eval, finite_difference_eval, line_search, take_step, Bounds, Iteration, SystemEval};
+use crate::options::SolverOptions;
+
+/// The result of fitting a system of equations by least squares.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LeastSquaresReport
+{
+    /// The best-fit value of each variable
+    pub solution: HashMap<String, f64>,
+    /// The value of each function at `solution`, in the order the functions were given
+    pub residuals: Vec<f64>,
+    /// The variables in the order used by `covariance`, which is sorted
+    pub vars: Vec<String>,
+    /// The estimated covariance of the variables at `solution`, `s²(JᵀJ)⁻¹`, where
+    /// `s² = ‖F‖² / (m - n)` estimates the variance of the `m` residuals from the
+    /// `n` variables. Every element is NaN if `m = n`, as there is nothing to estimate it from.
+    pub covariance: Vec<Vec<f64>>,
+    /// The number of iterations performed
+    pub iterations: usize,
+}
+
+/// Fits the variables of a system of equations to minimize the sum of the
+/// squares of the functions with the Gauss-Newton method. There may be more
+/// functions than variables, as when fitting parameters to measurements. The
+/// jacobian is approximated with the finite difference given in `options`,
+/// and `guess` is updated with the best fit.
+///
+/// Each step solves the normal equations `JᵀJ * step = -JᵀF`. Because the
+/// residual at a best fit is generally not zero, only the step tolerances
+/// are used to decide when the fit has converged. The `damping` and
+/// `line_search` options apply as they do to Newton-Raphson.
+///
+/// # Example
+/// ```
+/// use std::io::Error;
+/// use std::collections::HashMap;
+/// use geqslib::gauss_newton::gauss_newton;
+/// use geqslib::options::SolverOptions;
+///
+/// // Fit y = m * x + b to (0, 1), (1, 3), (2, 4), (3, 7)
+/// let points = [(0.0, 1.0), (1.0, 3.0), (2.0, 4.0), (3.0, 7.0)];
+/// let f: Vec<_> = points.iter()
+///     .map(|&(x, y)| move |p: &HashMap<String, f64>| Ok::<f64, Error>(p["m"] * x + p["b"] - y))
+///     .collect();
+///
+/// let mut guess = HashMap::from([
+///     ("m".to_string(), 1.0),
+///     ("b".to_string(), 1.0),
+/// ]);
+///
+/// let report = gauss_newton(f, &mut guess, &SolverOptions::new()).unwrap();
+///
+/// assert!((report.solution["m"] - 1.9).abs() < 0.001);
+/// assert!((report.solution["b"] - 0.9).abs() < 0.001);
+/// assert_eq!(report.residuals.len(), 4);
+/// ```
+pub fn gauss_newton<E>(f: Vec<impl Fn(&HashMap<String, f64>) -> Result<f64, E>>, guess: &mut HashMap<String, f64>, options: &SolverOptions) -> anyhow::Result<LeastSquaresReport>
+where anyhow::Error: From<E>
+{
+    if f.len() < guess.len()
+    {
+        return Err(NewtonRaphsonSolverError::ImproperlyConstrainedSystem.into());
+    }
+    gauss_newton_impl(&finite_difference_eval(&f, options), &|guess| eval_system(&f, guess), guess, &Bounds::new(), options)
+}
+
+/// Identical to `gauss_newton`, but uses the given partial derivatives instead
+/// of approximating the jacobian with finite differences. `jacobian` is given
+/// like it is to `multivariate_newton_raphson_with_jacobian`.
+pub fn gauss_newton_with_jacobian<E1, E2>(f: Vec<impl Fn(&HashMap<String, f64>) -> Result<f64, E1>>, jacobian: Vec<HashMap<String, impl Fn(&HashMap<String, f64>) -> Result<f64, E2>>>, guess: &mut HashMap<String, f64>, options: &SolverOptions) -> anyhow::Result<LeastSquaresReport>
+where anyhow::Error: From<E1> + From<E2>
+{
+    bounded_gauss_newton_with_jacobian(f, jacobian, guess, &Bounds::new(), options)
+}
+
+/// Identical to `gauss_newton_with_jacobian`, but keeps each variable within its `bounds`.
+pub (in crate) fn bounded_gauss_newton_with_jacobian<E1, E2>(f: Vec<impl Fn(&HashMap<String, f64>) -> Result<f64, E1>>, jacobian: Vec<HashMap<String, impl Fn(&HashMap<String, f64>) -> Result<f64, E2>>>, guess: &mut HashMap<String, f64>, bounds: &Bounds, options: &SolverOptions) -> anyhow::Result<LeastSquaresReport>
+where anyhow::Error: From<E1> + From<E2>
+{
+    if f.len() < guess.len() || jacobian.len() != f.len()
+    {
+        return Err(NewtonRaphsonSolverError::ImproperlyConstrainedSystem.into());
+    }
+    gauss_newton_impl(&exact_jacobian_eval(&f, &jacobian), &|guess| eval_system(&f, guess), guess, bounds, options)
+}
+
+/// The Gauss-Newton iteration shared by the public solvers. `eval` and
+/// `residual` are the same as for the multivariate Newton-Raphson solvers.
+fn gauss_newton_impl(eval: &impl Fn(&mut HashMap<String, f64>, &[String]) -> SystemEval, residual: &impl Fn(&HashMap<String, f64>) -> anyhow::Result<Vec<f64>>, guess: &mut HashMap<String, f64>, bounds: &Bounds, options: &SolverOptions) -> anyhow::Result<LeastSquaresReport>
+{
+    options.validate()?;
+
+    let mut vars = Vec::from_iter(guess.keys().map(|x| x.to_string()));
+    vars.sort();
+
+    let mut last = Iteration { guess: guess.clone(), residual: f64::NAN, step: f64::NAN };
+    let mut history = vec![];
+
+    for iterations in 1..=options.max_iterations
+    {
+        // Solve JᵀJ * step = -JᵀF
+        let (y, jacobian) = eval(guess, &vars)?;
+        let (jtj, jty) = normal_equations(&jacobian, &y);
+        let neg_jty: Vec<f64> = jty.iter().map(|g| -g).collect();
+        let step = cholesky_solve(&jtj, &neg_jty)?;
+
+        let start: Vec<f64> = vars.iter().map(|var| guess[var]).collect();
+        let error = options.norm.of(&y);
+        let change = options.norm.of(&step);
+
+        last = Iteration { guess: guess.clone(), residual: error, step: change };
+        if options.record_history
+        {
+            history.push(last.clone());
+        }
+
+        if change <= options.step_tol_at(options.norm.of(&start))
+        {
+            let covariance = covariance(&jtj, &y)?;
+            return Ok(LeastSquaresReport { solution: last.guess, residuals: y, vars, covariance, iterations });
+        }
+
+        let step: Vec<f64> = step.iter()
+            .map(|step_i| options.damping * step_i)
+            .collect();
+
+        if options.line_search
+        {
+            line_search(residual, guess, &vars, &y, &jacobian, step, bounds)?;
+        }
+        else
+        {
+            take_step(guess, &vars, &start, step, bounds);
+        }
+    }
+
+    Err(NotConvergedError { last_iterate: last.guess, iterations: options.max_iterations, residual: last.residual, step: last.step, history }.into())
+}
+
+/// Estimates the covariance of the variables from JᵀJ and the residuals at a best fit.
+fn covariance(jtj: &Matrix<f64>, y: &[f64]) -> anyhow::Result<Vec<Vec<f64>>>
+{
+    let (m, n) = (y.len(), jtj.get_rows());
+    let variance = if m > n
+    {
+        y.iter().map(|y_i| y_i * y_i).sum::<f64>() / (m - n) as f64
+    }
+    else
+    {
+        f64::NAN
+    };
+
+    // Each column of (JᵀJ)⁻¹ solves JᵀJ * column = eᵢ
+    let mut covariance = vec![vec![0.0; n]; n];
+    for j in 0..n
+    {
+        let mut unit = vec![0.0; n];
+        unit[j] = 1.0;
+        let column = cholesky_solve(jtj, &unit)?;
+        for i in 0..n
+        {
+            covariance[i][j] = variance * column[i];
+        }
+    }
+    Ok(covariance)
+}
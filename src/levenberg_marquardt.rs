@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use crate::errors::{NewtonRaphsonSolverError, NotConvergedError};
+use crate::linalg::{cholesky_solve, normal_equations};
+use crate::newton::{eval_system, exact_jacobian_eval, finite_difference_eval, fit_step_to_bounds, Bounds, Iteration, SolverReport, SystemEval};
+use crate::options::{Method, SolverOptions};
+
+/// The initial damping as a fraction of the largest diagonal element of JᵀJ
+const INITIAL_DAMPING: f64 = 1e-3;
+
+/// Solves a system of equations with the Levenberg-Marquardt method. The
+/// jacobian is approximated with the finite difference given in `options`,
+/// and `guess` is updated with the solution.
+///
+/// Each step solves `(JᵀJ + μI) * step = -JᵀF`. The damping `μ` shrinks while
+/// steps reduce ‖F‖² as well as predicted, which makes the method behave like
+/// Newton-Raphson near a solution, and grows when they don't, which makes it
+/// take short steps down the gradient of ‖F‖². Because `JᵀJ + μI` can always
+/// be solved, this is robust where the jacobian is singular, which
+/// Newton-Raphson can't handle.
+///
+/// The `damping` and `line_search` options do not apply to this method, and 
+/// only `Method::Auto` and `Method::LevenbergMarquardt` can be chosen.
+///
+/// # Example
+/// ```
+/// use std::io::Error;
+/// use std::collections::HashMap;
+/// use geqslib::levenberg_marquardt::levenberg_marquardt;
+/// use geqslib::options::SolverOptions;
+///
+/// fn f1(x: &HashMap<String, f64>) -> Result<f64, Error>
+/// {
+///     Ok(x["x"] * x["y"] - 2.0)
+/// }
+///
+/// fn f2(x: &HashMap<String, f64>) -> Result<f64, Error>
+/// {
+///     Ok(x["y"] - 1.0)
+/// }
+///
+/// // x has no effect on the system while y = 0, so the jacobian is singular here
+/// let mut guess = HashMap::from([
+///     ("x".to_string(), 1.0),
+///     ("y".to_string(), 0.0),
+/// ]);
+///
+/// let report = levenberg_marquardt(vec![f1, f2], &mut guess, &SolverOptions::new()).unwrap();
+///
+/// assert!((report.solution["x"] - 2.0).abs() < 0.001);
+/// assert!((report.solution["y"] - 1.0).abs() < 0.001);
+/// ```
+pub fn levenberg_marquardt<E>(f: Vec<impl Fn(&HashMap<String, f64>) -> Result<f64, E>>, guess: &mut HashMap<String, f64>, options: &SolverOptions) -> anyhow::Result<SolverReport<HashMap<String, f64>>>
+where anyhow::Error: From<E>
+{
+    options.require_method(&[Method::Auto, Method::LevenbergMarquardt])?;
+    if f.len() != guess.len()
+    {
+        return Err(NewtonRaphsonSolverError::ImproperlyConstrainedSystem.into());
+    }
+    levenberg_marquardt_impl(&finite_difference_eval(&f, options), &|guess| eval_system(&f, guess), guess, &Bounds::new(), options)
+}
+
+/// Identical to `levenberg_marquardt`, but uses the given partial derivatives
+/// instead of approximating the jacobian with finite differences. `jacobian`
+/// is given like it is to `multivariate_newton_raphson_with_jacobian`.
+pub fn levenberg_marquardt_with_jacobian<E1, E2>(f: Vec<impl Fn(&HashMap<String, f64>) -> Result<f64, E1>>, jacobian: Vec<HashMap<String, impl Fn(&HashMap<String, f64>) -> Result<f64, E2>>>, guess: &mut HashMap<String, f64>, options: &SolverOptions) -> anyhow::Result<SolverReport<HashMap<String, f64>>>
+where anyhow::Error: From<E1> + From<E2>
+{
+    options.require_method(&[Method::Auto, Method::LevenbergMarquardt])?;
+    bounded_levenberg_marquardt_with_jacobian(f, jacobian, guess, &Bounds::new(), options)
+}
+
+/// Identical to `levenberg_marquardt_with_jacobian`, but shortens steps to keep
+/// each variable within its `bounds`.
+pub (in crate) fn bounded_levenberg_marquardt_with_jacobian<E1, E2>(f: Vec<impl Fn(&HashMap<String, f64>) -> Result<f64, E1>>, jacobian: Vec<HashMap<String, impl Fn(&HashMap<String, f64>) -> Result<f64, E2>>>, guess: &mut HashMap<String, f64>, bounds: &Bounds, options: &SolverOptions) -> anyhow::Result<SolverReport<HashMap<String, f64>>>
+where anyhow::Error: From<E1> + From<E2>
+{
+    if f.len() != guess.len() || jacobian.len() != f.len()
+    {
+        return Err(NewtonRaphsonSolverError::ImproperlyConstrainedSystem.into());
+    }
+    levenberg_marquardt_impl(&exact_jacobian_eval(&f, &jacobian), &|guess| eval_system(&f, guess), guess, bounds, options)
+}
+
+/// The Levenberg-Marquardt iteration shared by the public solvers. `eval` and
+/// `residual` are the same as for the multivariate Newton-Raphson solvers.
+fn levenberg_marquardt_impl(eval: &impl Fn(&mut HashMap<String, f64>, &[String]) -> SystemEval, residual: &impl Fn(&HashMap<String, f64>) -> anyhow::Result<Vec<f64>>, guess: &mut HashMap<String, f64>, bounds: &Bounds, options: &SolverOptions) -> anyhow::Result<SolverReport<HashMap<String, f64>>>
+{
+    options.validate()?;
+
+    let vars = Vec::from_iter(guess.keys().map(|x| x.to_string()));
+
+    let (mut y, jacobian) = eval(guess, &vars)?;
+    let (mut jtj, mut jty) = normal_equations(&jacobian, &y);
+    let residual_tol = options.residual_tol(options.norm.of(&y));
+
+    // μ starts out relative to the scale of JᵀJ and grows by ν after every rejected step
+    let max_diag = (0..vars.len()).map(|i| jtj[(i, i)]).fold(0.0, f64::max);
+    let mut mu = if max_diag > 0.0 { INITIAL_DAMPING * max_diag } else { INITIAL_DAMPING };
+    let mut nu = 2.0;
+
+    let mut last = Iteration { guess: guess.clone(), residual: f64::NAN, step: f64::NAN };
+    let mut history = vec![];
+
+    for iterations in 1..=options.max_iterations
+    {
+        // Solve (JᵀJ + μI) * step = -JᵀF
+        let mut damped = jtj.clone();
+        for i in 0..vars.len()
+        {
+            damped[(i, i)] += mu;
+        }
+        let neg_jty: Vec<f64> = jty.iter().map(|g| -g).collect();
+        let mut step = cholesky_solve(&damped, &neg_jty)?;
+
+        let start: Vec<f64> = vars.iter().map(|var| guess[var]).collect();
+        let length = fit_step_to_bounds(&start, &vars, &mut step, bounds);
+        for step_i in step.iter_mut()
+        {
+            *step_i *= length;
+        }
+
+        let error = options.norm.of(&y);
+        let change = options.norm.of(&step);
+
+        last = Iteration { guess: guess.clone(), residual: error, step: change };
+        if options.record_history
+        {
+            history.push(last.clone());
+        }
+
+        if error <= residual_tol && change <= options.step_tol_at(options.norm.of(&start))
+        {
+            return Ok(SolverReport { solution: last.guess, iterations, residual: error, step: change, history });
+        }
+
+        for (i, var) in vars.iter().enumerate()
+        {
+            if let Some(guess_val) = guess.get_mut(var)
+            {
+                *guess_val = start[i] + step[i];
+            }
+        }
+        let trial_y = residual(guess)?;
+
+        // Compare the actual reduction in ½‖F‖² to the reduction predicted by the
+        // linear model of F, which is -JᵀF · step - ½ stepᵀJᵀJ step
+        let actual = 0.5 * (sum_of_squares(&y) - sum_of_squares(&trial_y));
+        let mut predicted = 0.0;
+        for i in 0..vars.len()
+        {
+            predicted -= jty[i] * step[i];
+            for j in 0..vars.len()
+            {
+                predicted -= 0.5 * step[i] * jtj[(i, j)] * step[j];
+            }
+        }
+        let gain = if predicted > 0.0 { actual / predicted } else { -1.0 };
+
+        if gain > 0.0
+        {
+            // Accept the step, trusting the linear model more the better it did
+            let (new_y, jacobian) = eval(guess, &vars)?;
+            y = new_y;
+            (jtj, jty) = normal_equations(&jacobian, &y);
+            mu *= (1.0 - (2.0 * gain - 1.0).powi(3)).max(1.0 / 3.0);
+            nu = 2.0;
+        }
+        else
+        {
+            // Reject the step and move toward steepest descent
+            for (i, var) in vars.iter().enumerate()
+            {
+                if let Some(guess_val) = guess.get_mut(var)
+                {
+                    *guess_val = start[i];
+                }
+            }
+            mu *= nu;
+            nu *= 2.0;
+        }
+    }
+
+    Err(NotConvergedError { last_iterate: last.guess, iterations: options.max_iterations, residual: last.residual, step: last.step, history }.into())
+}
+
+fn sum_of_squares(y: &[f64]) -> f64
+{
+    y.iter().map(|y_i| y_i * y_i).sum()
+}
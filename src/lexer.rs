@@ -0,0 +1,179 @@
+use std::ops::Range;
+
+use crate::errors::{ParseError, ShuntingYardError};
+
+/// The kinds of lexemes that make up an expression or equation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LexemeKind
+{
+    /// A numeric literal, including literals in scientific notation like `6.02e23`.
+    Num(f64),
+    /// A name that may refer to a constant, variable or function.
+    Ident,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Caret,
+    LeftParenthesis,
+    RightParenthesis,
+    Comma,
+    /// The `=` separating the two sides of an equation.
+    Equals,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    /// The `==` comparison, as opposed to the `=` of an equation.
+    EqualEqual,
+    NotEqual,
+    AndAnd,
+    OrOr,
+    Bang,
+}
+
+/// A single token of an expression along with its location in the source string.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lexeme<'a>
+{
+    pub kind: LexemeKind,
+    /// The text of the token as it appears in the source string.
+    pub text: &'a str,
+    /// The byte offsets of the token in the source string.
+    pub span: Range<usize>,
+}
+
+/// Splits an expression or equation into `Lexeme`s, recording the byte
+/// offsets of each one. Whitespace is skipped.
+///
+/// # Example
+/// ```
+/// use geqslib::shunting::{lex, LexemeKind};
+///
+/// let lexemes = lex("x1 + 1e-3").unwrap();
+///
+/// assert_eq!(lexemes.len(), 3);
+/// assert_eq!(lexemes[0].kind, LexemeKind::Ident);
+/// assert_eq!(lexemes[1].span, 3..4);
+/// assert_eq!(lexemes[2].kind, LexemeKind::Num(0.001));
+/// assert_eq!(lexemes[2].text, "1e-3");
+/// ```
+pub fn lex(text: &str) -> anyhow::Result<Vec<Lexeme<'_>>>
+{
+    let bytes = text.as_bytes();
+    let mut lexemes = vec![];
+    let mut i = 0;
+
+    while i < bytes.len()
+    {
+        let start = i;
+        let c = bytes[i];
+
+        let kind = match c
+        {
+            b'+' => LexemeKind::Plus,
+            b'-' => LexemeKind::Minus,
+            b'*' => LexemeKind::Star,
+            b'/' => LexemeKind::Slash,
+            b'^' => LexemeKind::Caret,
+            b'(' => LexemeKind::LeftParenthesis,
+            b')' => LexemeKind::RightParenthesis,
+            b',' => LexemeKind::Comma,
+            b'=' => two_char_operator(bytes, &mut i, b'=', LexemeKind::EqualEqual).unwrap_or(LexemeKind::Equals),
+            b'<' => two_char_operator(bytes, &mut i, b'=', LexemeKind::LessEqual).unwrap_or(LexemeKind::Less),
+            b'>' => two_char_operator(bytes, &mut i, b'=', LexemeKind::GreaterEqual).unwrap_or(LexemeKind::Greater),
+            b'!' => two_char_operator(bytes, &mut i, b'=', LexemeKind::NotEqual).unwrap_or(LexemeKind::Bang),
+            b'&' | b'|' => {
+                let kind = if c == b'&' { LexemeKind::AndAnd } else { LexemeKind::OrOr };
+                match two_char_operator(bytes, &mut i, c, kind)
+                {
+                    Some(kind) => kind,
+                    None => return Err(ParseError::new(ShuntingYardError::UnknownToken, text, start..start + 1).into()),
+                }
+            },
+
+            c if c.is_ascii_whitespace() => {
+                i += 1;
+                continue;
+            },
+
+            c if c.is_ascii_alphabetic() => {
+                while i + 1 < bytes.len() && (bytes[i + 1].is_ascii_alphanumeric() || bytes[i + 1] == b'_')
+                {
+                    i += 1;
+                }
+                LexemeKind::Ident
+            },
+
+            c if c.is_ascii_digit() || c == b'.' => {
+                i = end_of_number(bytes, start) - 1;
+                match text[start..=i].parse::<f64>()
+                {
+                    Ok(num) => LexemeKind::Num(num),
+                    Err(_) => return Err(ParseError::new(ShuntingYardError::UnknownToken, text, start..i + 1).into()),
+                }
+            },
+
+            _ => {
+                // Report the whole character, not just its first byte
+                let len = text[start..].chars().next().map_or(1, char::len_utf8);
+                return Err(ParseError::new(ShuntingYardError::UnknownToken, text, start..start + len).into());
+            },
+        };
+
+        i += 1;
+        lexemes.push(Lexeme { kind, text: &text[start..i], span: start..i });
+    }
+
+    Ok(lexemes)
+}
+
+/// Checks whether the byte after `i` is `second`, and if so, advances `i`
+/// onto it and returns `kind`.
+fn two_char_operator(bytes: &[u8], i: &mut usize, second: u8, kind: LexemeKind) -> Option<LexemeKind>
+{
+    if bytes.get(*i + 1) == Some(&second)
+    {
+        *i += 1;
+        Some(kind)
+    }
+    else
+    {
+        None
+    }
+}
+
+/// Returns the byte offset just past the numeric literal starting at `start`.
+fn end_of_number(bytes: &[u8], start: usize) -> usize
+{
+    let digits_from = |mut i: usize| {
+        while i < bytes.len() && bytes[i].is_ascii_digit()
+        {
+            i += 1;
+        }
+        i
+    };
+
+    // Mantissa
+    let mut i = digits_from(start);
+    if i < bytes.len() && bytes[i] == b'.'
+    {
+        i = digits_from(i + 1);
+    }
+
+    // Exponent, only if there are digits to go with it
+    if i < bytes.len() && (bytes[i] == b'e' || bytes[i] == b'E')
+    {
+        let mut j = i + 1;
+        if j < bytes.len() && (bytes[j] == b'+' || bytes[j] == b'-')
+        {
+            j += 1;
+        }
+        if j < bytes.len() && bytes[j].is_ascii_digit()
+        {
+            i = digits_from(j);
+        }
+    }
+
+    i
+}
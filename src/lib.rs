@@ -2,54 +2,79 @@
 pub mod system;
 /// Contains structs for passing information to the shunting yard algorithm. This is re-exported by the `shunting` module.
 mod context;
+/// Contains the `Expr` syntax tree produced by the parser. This is re-exported by the `shunting` module.
+mod expr;
+/// Contains the `Dual` number type used for automatic differentiation.
+pub mod dual;
+/// Contains the `Bytecode` form of compiled expressions. This is re-exported by the `shunting` module.
+mod bytecode;
+/// Contains the lexer that splits strings into tokens for the parser. This is re-exported by the `shunting` module.
+mod lexer;
 /// Contains error types for different errors that this crate may throw.
 pub mod errors;
 /// Contains `extern "C"` function definitions for linking this library
 /// against projects in different languages. Not intended for use in 
 /// other Rust projects.
 pub mod ffi;
+/// Contains the Gauss-Newton method for fitting systems of equations by least squares.
+pub mod gauss_newton;
+/// Contains the Levenberg-Marquardt method for solving systems of equations.
+pub mod levenberg_marquardt;
+/// Contains dense linear algebra routines used by the solvers.
+mod linalg;
+/// Contains a Newton method for the solutions of under-determined systems of equations nearest to a guess.
+pub mod minimum_norm;
 /// Contains root-finding algorithms for building equation-solving tools. 
 pub mod newton;
+/// Contains `SolverOptions` for configuring how equations and systems are solved.
+pub mod options;
+/// Contains bracketing root-finding algorithms for functions of one variable.
+pub mod roots;
 /// Contains a basic shunting yard algorithm for evaluating strings as mathematical expressions.
 pub mod shunting;
+/// Contains the structural analysis used to split systems of equations into blocks.
+mod structure;
 /// Contains the `Variable` type for numbers that exist on a user-specified domain.
 pub mod variable;
 
-use std::collections::{HashMap, HashSet};
+use std::collections::HashSet;
 
 use context::ContextLike;
 use errors::EquationSolverError;
-use newton::newton_raphson;
-use shunting::{ContextHashMap, compile_to_fn, compile_to_fn_of_hashmap, get_legal_variables_iter, new_context};
+use newton::{solve_in_domain, solve_with_margin};
+use options::SolverOptions;
+use shunting::{BinaryOp, ContextHashMap, Expr, Lexeme, LexemeKind, Token, compile_expr_to_fn, compile_expr_to_dual_fn, get_legal_variables_iter, lex, new_context, parse_lexemes};
 use system::get_equation_unknowns;
 
-/// An internal function for formatting a single-unknown equation to an expression prior to tokenization 
-pub (in crate) fn compile_equation_to_fn(equation: &str, ctx: &ContextHashMap) -> anyhow::Result<impl Fn(f64) -> anyhow::Result<f64>>
+/// An internal function for parsing an equation to an expression equal to `lhs - (rhs)`.
+/// Parsing errors point at the offending token in the equation as it was given.
+pub (in crate) fn parse_equation(equation: &str, ctx: &ContextHashMap) -> anyhow::Result<Expr>
 {
+    let lexemes = lex(equation)?;
+
     // Ensure that we're solving just one equation
-    let sides: Vec<&str> = equation.split('=').collect();
+    let sides: Vec<&[Lexeme]> = lexemes.split(|l| l.kind == LexemeKind::Equals).collect();
     match sides.len()
     {
         1 => return Err(EquationSolverError::FoundExpression.into()),
         2 => (),
         _ => return Err(EquationSolverError::FoundMultipleEquations.into()),
     }
+
+    let equals_at = lexemes.iter()
+        .position(|l| l.kind == LexemeKind::Equals)
+        .map(|i| lexemes[i].span.start)
+        .unwrap_or_default();
     
-    compile_to_fn(&format!("{} - ({})", sides[0], sides[1]), ctx)
+    let lhs = parse_lexemes(sides[0], equation, equals_at, ctx)?;
+    let rhs = parse_lexemes(sides[1], equation, equation.len(), ctx)?;
+
+    Ok(Expr::Binary(BinaryOp::Sub, Box::new(lhs), Box::new(rhs)))
 }
 
-/// An internal function for formatting an equation to an expression prior to tokenization 
-pub (in crate) fn compile_equation_to_fn_of_hashmap(equation: &str, ctx: &mut ContextHashMap) -> anyhow::Result<impl Fn(&HashMap<String, f64>) -> anyhow::Result<f64>>
+/// An internal function for parsing an equation after adding its unknowns to the context 
+pub (in crate) fn parse_equation_with_unknowns(equation: &str, ctx: &mut ContextHashMap) -> anyhow::Result<Expr>
 {
-    // Ensure that we're solving just one equation
-    let sides: Vec<&str> = equation.split('=').collect();
-    match sides.len()
-    {
-        1 => return Err(EquationSolverError::FoundExpression.into()),
-        2 => (),
-        _ => return Err(EquationSolverError::FoundMultipleEquations.into()),
-    }
-
     // Get the unknowns. Need to be owned to mutate ctx
     let unknowns: Vec<String> = get_equation_unknowns(equation, ctx)
         .map(|x| x.to_owned())
@@ -61,7 +86,22 @@ pub (in crate) fn compile_equation_to_fn_of_hashmap(equation: &str, ctx: &mut Co
         ctx.add_var_with_domain_to_ctx(&var, 1.0, f64::NEG_INFINITY, f64::INFINITY);
     }
 
-    compile_to_fn_of_hashmap(&format!("{} - ({})", sides[0], sides[1]), ctx)
+    parse_equation(equation, ctx)
+}
+
+/// An internal function for differentiating an expression, returning `None` if the
+/// derivative is unknown or calls a function that is not in the given context
+pub (in crate) fn derivative_in_context(expr: &Expr, var: &str, ctx: &ContextHashMap) -> Option<Expr>
+{
+    let derivative = expr.derivative(var).ok()?;
+
+    let mut resolves = true;
+    derivative.walk(&mut |e| if let Expr::Call(name, _) = e
+    {
+        resolves &= matches!(ctx.get(name), Some(Token::Func(..)));
+    });
+
+    if resolves { Some(derivative) } else { None }
 }
 
 /// Solves an equation given as a string for the SINGLE
@@ -71,6 +111,10 @@ pub (in crate) fn compile_equation_to_fn_of_hashmap(equation: &str, ctx: &mut Co
 /// E.g. the context for `"x + sin(y) = 9"` must define a value for `"y"` 
 /// and `"sin"`, but NO value for `"x"` if `"x"` is the variable to be solved for.
 /// 
+/// If `min` and `max` are both finite, they are used as a bracket for Brent's method,
+/// which cannot diverge. If the equation does not change sign over the bracket, 
+/// Newton-Raphson is used as it is for an unbounded domain.
+/// 
 /// # Example
 /// ```
 /// use geqslib::solve_equation_with_context;
@@ -83,9 +127,55 @@ pub (in crate) fn compile_equation_to_fn_of_hashmap(equation: &str, ctx: &mut Co
 /// 
 /// assert_eq!(var, "x");
 /// assert!((soln - 8.0).abs() < 0.001);
+/// 
+/// // Newton-Raphson diverges on this from x = 1, but the bracket is safe
+/// let mut ctx = new_context();
+/// let (_, soln) = solve_equation_with_context("arctan(x - 5) = 0", &mut ctx, 1.0, 0.0, 10.0, 0.0001, 50)
+///     .expect("failed to find a solution");
+/// 
+/// assert!((soln - 5.0).abs() < 0.001);
 /// ```
 pub fn solve_equation_with_context(equation: &str, ctx: &mut ContextHashMap, guess: f64, min: f64, max: f64, margin: f64, limit: usize) -> anyhow::Result<(String, f64)>
 {
+    solve_with_margin(margin, limit, |options| solve_equation_with_options(equation, ctx, guess, min, max, options))
+}
+
+/// Identical to `solve_equation_with_context`, but configured by `options`
+/// instead of a margin and iteration limit.
+/// 
+/// With `Method::Auto`, the method is chosen as described for 
+/// `solve_equation_with_context`. The bracketing methods require `min` and 
+/// `max` to be finite. Like Brent's method with `Method::Auto`, they narrow the 
+/// bracket down to the step tolerance, and their root is refined with 
+/// Newton-Raphson if it doesn't meet the residual tolerance.
+/// 
+/// # Example
+/// ```
+/// use geqslib::solve_equation_with_options;
+/// use geqslib::options::{Method, SolverOptions};
+/// use geqslib::shunting::new_context;
+/// 
+/// let options = SolverOptions::new()
+///     .abs_tol(1e-10)
+///     .step_tol(1e-10)
+///     .method(Method::Bisection)
+///     .max_iterations(200);
+/// 
+/// let mut ctx = new_context();
+/// let (var, soln) = solve_equation_with_options("x^2 = 2", &mut ctx, 1.0, 0.0, 2.0, &options)
+///     .expect("failed to find a solution");
+/// 
+/// assert_eq!(var, "x");
+/// assert!((soln - 2f64.sqrt()).abs() < 1e-9);
+/// 
+/// // Bisection needs a bracket
+/// let mut ctx = new_context();
+/// assert!(solve_equation_with_options("x^2 = 2", &mut ctx, 1.0, f64::NEG_INFINITY, f64::INFINITY, &options).is_err());
+/// ```
+pub fn solve_equation_with_options(equation: &str, ctx: &mut ContextHashMap, guess: f64, min: f64, max: f64, options: &SolverOptions) -> anyhow::Result<(String, f64)>
+{
+    options.validate()?;
+
     // Check constraints
     let unknowns: Vec<&str> = get_legal_variables_iter(equation)
         .filter(|&x| !ctx.contains_key(x))
@@ -100,9 +190,21 @@ pub fn solve_equation_with_context(equation: &str, ctx: &mut ContextHashMap, gue
     }
     
     ctx.add_var_with_domain_to_ctx(unknowns[0], guess, min, max);
-    let f = compile_equation_to_fn(equation, ctx)?;
+    let expr = parse_equation(equation, ctx)?;
+    // Use the exact derivative if there is one
+    let f = compile_expr_to_fn(expr.clone(), ctx)?;
+    let soln = match derivative_in_context(&expr, unknowns[0], ctx)
+    {
+        Some(derivative) => solve_in_domain(f, compile_expr_to_fn(derivative, ctx)?, guess, (min, max), options)?,
+        None => {
+            // ...otherwise fall back on automatic differentiation
+            let f_dual = compile_expr_to_dual_fn(expr, ctx)?;
+            let f_prime = move |x: f64| -> anyhow::Result<f64> { Ok(f_dual(x)?.deriv) };
+            solve_in_domain(f, f_prime, guess, (min, max), options)?
+        },
+    };
 
-    Ok((unknowns[0].to_owned(), newton_raphson(f, 1.0, margin, limit)?))
+    Ok((unknowns[0].to_owned(), soln))
 }
 
 /// Solves an equation given as a string for a SINGLE unknown variable.
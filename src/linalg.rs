@@ -0,0 +1,173 @@
+use gmatlib::Matrix;
+
+use crate::errors::LinearSystemError;
+
+/// Solves `a * x = b` for `x`, where `a` is a symmetric positive-definite matrix,
+/// by Cholesky decomposition. Only the lower triangle of `a` is read.
+pub (in crate) fn cholesky_solve(a: &Matrix<f64>, b: &[f64]) -> anyhow::Result<Vec<f64>>
+{
+    let n = b.len();
+
+    // Factor a = l * l^T
+    let mut l = Matrix::new(n, n);
+    for j in 0..n
+    {
+        let mut diag = a[(j, j)];
+        for k in 0..j
+        {
+            diag -= l[(j, k)] * l[(j, k)];
+        }
+        if diag.is_nan() || diag <= 0.0
+        {
+            return Err(LinearSystemError::NotPositiveDefinite.into());
+        }
+        l[(j, j)] = diag.sqrt();
+
+        for i in (j + 1)..n
+        {
+            let mut sum = a[(i, j)];
+            for k in 0..j
+            {
+                sum -= l[(i, k)] * l[(j, k)];
+            }
+            l[(i, j)] = sum / l[(j, j)];
+        }
+    }
+
+    // Solve l * z = b, then l^T * x = z
+    let mut x = b.to_vec();
+    for i in 0..n
+    {
+        for k in 0..i
+        {
+            x[i] -= l[(i, k)] * x[k];
+        }
+        x[i] /= l[(i, i)];
+    }
+    for i in (0..n).rev()
+    {
+        for k in (i + 1)..n
+        {
+            x[i] -= l[(k, i)] * x[k];
+        }
+        x[i] /= l[(i, i)];
+    }
+
+    Ok(x)
+}
+
+/// Returns JᵀJ and JᵀF for a jacobian `J` and residual vector `F`.
+pub (in crate) fn normal_equations(jacobian: &Matrix<f64>, y: &[f64]) -> (Matrix<f64>, Vec<f64>)
+{
+    let jt = jacobian.transpose();
+    let jtj = &jt * jacobian;
+    let jty = (jt * Matrix::from_col_vec(y.to_vec())).into();
+    (jtj, jty)
+}
+
+/// The rank of a singular matrix and a basis for its null space.
+#[derive(Debug)]
+pub (in crate) struct RankDeficiency
+{
+    pub rank: usize,
+    /// Vectors `x` for which `a * x = 0`
+    pub null_space: Vec<Vec<f64>>,
+}
+
+/// Solves `a * x = b` for `x`, where `a` is square, by LU factorization with
+/// partial pivoting. If `a` is singular, the rank deficiency is returned instead.
+pub (in crate) fn lu_solve(a: &Matrix<f64>, b: &[f64]) -> Result<Vec<f64>, RankDeficiency>
+{
+    let n = b.len();
+    let mut u = a.clone();
+    let mut x = b.to_vec();
+
+    // Pivots smaller than this are taken to be rounding error on a zero
+    let mut largest: f64 = 0.0;
+    for i in 0..n
+    {
+        for j in 0..n
+        {
+            largest = largest.max(u[(i, j)].abs());
+        }
+    }
+    let tol = n as f64 * f64::EPSILON * largest;
+
+    // Eliminate below each pivot, reducing u to row echelon form. Columns
+    // without a pivot are dependent on the columns before them.
+    let mut pivot_cols = vec![];
+    let mut free_cols = vec![];
+    for col in 0..n
+    {
+        let row = pivot_cols.len();
+        let pivot = (row..n).max_by(|&i, &j| u[(i, col)].abs().total_cmp(&u[(j, col)].abs()));
+        let pivot = match pivot
+        {
+            Some(pivot) if u[(pivot, col)].abs() > tol => pivot,
+            _ => {
+                free_cols.push(col);
+                continue;
+            },
+        };
+
+        // gmatlib's inplace_row_swap exchanges columns, so rows are swapped by hand
+        if pivot != row
+        {
+            for j in 0..n
+            {
+                let tmp = u[(pivot, j)];
+                u[(pivot, j)] = u[(row, j)];
+                u[(row, j)] = tmp;
+            }
+            x.swap(pivot, row);
+        }
+
+        for i in (row + 1)..n
+        {
+            let factor = u[(i, col)] / u[(row, col)];
+            for j in col..n
+            {
+                u[(i, j)] -= factor * u[(row, j)];
+            }
+            x[i] -= factor * x[row];
+        }
+        pivot_cols.push(col);
+    }
+
+    if free_cols.is_empty()
+    {
+        back_substitute(&u, &mut x);
+        return Ok(x);
+    }
+
+    // Each column without a pivot gives a null vector: set its variable to 1
+    // and solve for the pivot variables that cancel it out
+    let null_space = free_cols.iter()
+        .map(|&free| {
+            let mut null_vector = vec![0.0; n];
+            null_vector[free] = 1.0;
+            for (row, &col) in pivot_cols.iter().enumerate().rev()
+            {
+                let sum: f64 = ((col + 1)..n).map(|j| u[(row, j)] * null_vector[j]).sum();
+                null_vector[col] = -sum / u[(row, col)];
+            }
+            null_vector
+        })
+        .collect();
+
+    Err(RankDeficiency { rank: pivot_cols.len(), null_space })
+}
+
+/// Solves the upper triangular system left in `u` by elimination, overwriting `x` with the solution.
+fn back_substitute(u: &Matrix<f64>, x: &mut [f64])
+{
+    let n = x.len();
+    for i in (0..n).rev()
+    {
+        for k in (i + 1)..n
+        {
+            x[i] -= u[(i, k)] * x[k];
+        }
+        x[i] /= u[(i, i)];
+    }
+}
@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+use gmatlib::Matrix;
+use crate::errors::{NewtonRaphsonSolverError, NotConvergedError, SingularJacobianError};
+use crate::linalg::cholesky_solve;
+use crate::newton::{exact_jacobian_eval, finite_difference_eval, Iteration, SystemEval};
+use crate::options::SolverOptions;
+
+/// The size below which a projected direction is taken to be rounding error on zero
+const FREE_DIRECTION_TOL: f64 = 1e-8;
+
+/// The fraction of an equation's gradient below which what is left of it, once the 
+/// gradients of the equations kept before it are projected out, is taken to be rounding 
+/// error on zero
+const DEPENDENT_ROW_TOL: f64 = 1e-8;
+
+/// The result of solving an under-determined system of equations for the
+/// solution nearest to the initial guess.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MinimumNormReport
+{
+    /// The solution found
+    pub solution: HashMap<String, f64>,
+    /// The number of iterations performed
+    pub iterations: usize,
+    /// The size of the residual at `solution`
+    pub residual: f64,
+    /// The size of the last correction computed at `solution`
+    pub step: f64,
+    /// Orthonormal directions in which `solution` can move without changing
+    /// the system to first order. There is one for each variable more than
+    /// there are independent equations.
+    pub free_directions: Vec<HashMap<String, f64>>,
+}
+
+/// Solves a system of equations with fewer functions than variables for the
+/// solution nearest to `guess`. The jacobian is approximated with the finite
+/// difference given in `options`, and `guess` is updated with the solution.
+///
+/// Each step is the smallest correction that moves back toward the initial
+/// guess while solving the linearized system,
+/// `step = d - Jᵀ(JJᵀ)⁻¹(F + J * d)` where `d` is the initial guess minus the
+/// current one. Functions whose gradients are combinations of the gradients of
+/// the others are left out of `J` and `F` while finding the step, so that `JJᵀ`
+/// can be solved. Redundant functions are still solved if they agree with the
+/// others, and otherwise the solver fails to converge. If no function changes 
+/// to first order at a guess that is not a solution, the error is a 
+/// `SingularJacobianError`. The `damping` option applies as it does to 
+/// Newton-Raphson, but the `line_search` option does not.
+///
+/// # Example
+/// ```
+/// use std::io::Error;
+/// use std::collections::HashMap;
+/// use geqslib::minimum_norm::minimum_norm_newton;
+/// use geqslib::options::SolverOptions;
+///
+/// fn f(x: &HashMap<String, f64>) -> Result<f64, Error>
+/// {
+///     Ok(x["x"] + x["y"] - 4.0)
+/// }
+///
+/// let mut guess = HashMap::from([
+///     ("x".to_string(), 1.0),
+///     ("y".to_string(), 1.0),
+/// ]);
+///
+/// let report = minimum_norm_newton(vec![f], &mut guess, &SolverOptions::new()).unwrap();
+///
+/// // (2, 2) is the point on x + y = 4 nearest to (1, 1)...
+/// assert!((report.solution["x"] - 2.0).abs() < 0.001);
+/// assert!((report.solution["y"] - 2.0).abs() < 0.001);
+///
+/// // ...and x + y = 4 leaves the solution free to move along (1, -1)
+/// let free = &report.free_directions[0];
+/// assert!((free["x"] + free["y"]).abs() < 0.001);
+/// ```
+pub fn minimum_norm_newton<E>(f: Vec<impl Fn(&HashMap<String, f64>) -> Result<f64, E>>, guess: &mut HashMap<String, f64>, options: &SolverOptions) -> anyhow::Result<MinimumNormReport>
+where anyhow::Error: From<E>
+{
+    if f.len() > guess.len()
+    {
+        return Err(NewtonRaphsonSolverError::ImproperlyConstrainedSystem.into());
+    }
+    minimum_norm_impl(&finite_difference_eval(&f, options), guess, options)
+}
+
+/// Identical to `minimum_norm_newton`, but uses the given partial derivatives
+/// instead of approximating the jacobian with finite differences. `jacobian`
+/// is given like it is to `multivariate_newton_raphson_with_jacobian`.
+pub fn minimum_norm_newton_with_jacobian<E1, E2>(f: Vec<impl Fn(&HashMap<String, f64>) -> Result<f64, E1>>, jacobian: Vec<HashMap<String, impl Fn(&HashMap<String, f64>) -> Result<f64, E2>>>, guess: &mut HashMap<String, f64>, options: &SolverOptions) -> anyhow::Result<MinimumNormReport>
+where anyhow::Error: From<E1> + From<E2>
+{
+    if f.len() > guess.len() || jacobian.len() != f.len()
+    {
+        return Err(NewtonRaphsonSolverError::ImproperlyConstrainedSystem.into());
+    }
+    minimum_norm_impl(&exact_jacobian_eval(&f, &jacobian), guess, options)
+}
+
+/// The minimum-norm Newton iteration shared by the public solvers. `eval` is
+/// the same as for the multivariate Newton-Raphson solvers.
+fn minimum_norm_impl(eval: &impl Fn(&mut HashMap<String, f64>, &[String]) -> SystemEval, guess: &mut HashMap<String, f64>, options: &SolverOptions) -> anyhow::Result<MinimumNormReport>
+{
+    options.validate()?;
+
+    let mut vars = Vec::from_iter(guess.keys().map(|x| x.to_string()));
+    vars.sort();
+    let initial: Vec<f64> = vars.iter().map(|var| guess[var]).collect();
+
+    let mut last = Iteration { guess: guess.clone(), residual: f64::NAN, step: f64::NAN };
+    let mut history = vec![];
+    let mut residual_tol = options.abs_tol;
+
+    for iterations in 1..=options.max_iterations
+    {
+        let (y, jacobian) = eval(guess, &vars)?;
+        let start: Vec<f64> = vars.iter().map(|var| guess[var]).collect();
+
+        // Leave out the functions that depend on the others to first order
+        let rows = independent_rows(&jacobian);
+        let mut reduced = Matrix::new(rows.len(), vars.len());
+        for (i, &row) in rows.iter().enumerate()
+        {
+            for j in 0..vars.len()
+            {
+                reduced[(i, j)] = jacobian[(row, j)];
+            }
+        }
+
+        // Solve JJᵀ * w = F + J * d for the correction d - Jᵀw
+        let d: Vec<f64> = initial.iter().zip(&start).map(|(x0, x)| x0 - x).collect();
+        let j_d: Vec<f64> = (&reduced * Matrix::from_col_vec(d.clone())).into();
+        let rhs: Vec<f64> = rows.iter().zip(&j_d).map(|(&row, jd_i)| y[row] + jd_i).collect();
+        let jt = reduced.transpose();
+        let jjt = &reduced * jt.clone();
+        let w = cholesky_solve(&jjt, &rhs)?;
+        let jt_w: Vec<f64> = (jt * Matrix::from_col_vec(w)).into();
+        let step: Vec<f64> = d.iter().zip(&jt_w).map(|(d_i, jtw_i)| d_i - jtw_i).collect();
+
+        let error = options.norm.of(&y);
+        let change = options.norm.of(&step);
+
+        if iterations == 1
+        {
+            residual_tol = options.residual_tol(error);
+        }
+
+        // Nothing can reduce the residual where no function changes to first order
+        if rows.is_empty() && error > residual_tol
+        {
+            return Err(SingularJacobianError { rank: 0, size: vars.len(), vars }.into());
+        }
+
+        last = Iteration { guess: guess.clone(), residual: error, step: change };
+        if options.record_history
+        {
+            history.push(last.clone());
+        }
+
+        if error <= residual_tol && change <= options.step_tol_at(options.norm.of(&start))
+        {
+            let free_directions = free_directions(&reduced, &jjt, &vars)?;
+            return Ok(MinimumNormReport { solution: last.guess, iterations, residual: error, step: change, free_directions });
+        }
+
+        for (var, step_i) in vars.iter().zip(&step)
+        {
+            if let Some(guess_val) = guess.get_mut(var)
+            {
+                *guess_val += options.damping * step_i;
+            }
+        }
+    }
+
+    Err(NotConvergedError { last_iterate: last.guess, iterations: options.max_iterations, residual: last.residual, step: last.step, history }.into())
+}
+
+/// Finds the rows of `jacobian` that are independent of the rows before them, by
+/// projecting out the rows already kept from each one in turn (Gram-Schmidt).
+fn independent_rows(jacobian: &Matrix<f64>) -> Vec<usize>
+{
+    let n = jacobian.get_cols();
+    let mut rows = vec![];
+    let mut basis: Vec<Vec<f64>> = vec![];
+
+    for i in 0..jacobian.get_rows()
+    {
+        let mut row: Vec<f64> = (0..n).map(|j| jacobian[(i, j)]).collect();
+        let length = row.iter().map(|x| x * x).sum::<f64>().sqrt();
+
+        for b in &basis
+        {
+            let dot: f64 = row.iter().zip(b).map(|(x, y)| x * y).sum();
+            for (x, y) in row.iter_mut().zip(b)
+            {
+                *x -= dot * y;
+            }
+        }
+
+        let remaining = row.iter().map(|x| x * x).sum::<f64>().sqrt();
+        if remaining > DEPENDENT_ROW_TOL * length
+        {
+            basis.push(row.iter().map(|x| x / remaining).collect());
+            rows.push(i);
+        }
+    }
+
+    rows
+}
+
+/// Finds an orthonormal basis for the null space of `jacobian` by projecting
+/// each variable's axis onto it, `(I - Jᵀ(JJᵀ)⁻¹J) * eₖ`, and orthonormalizing
+/// the projections. The rows of `jacobian` must be independent.
+fn free_directions(jacobian: &Matrix<f64>, jjt: &Matrix<f64>, vars: &[String]) -> anyhow::Result<Vec<HashMap<String, f64>>>
+{
+    let (m, n) = (jacobian.get_rows(), vars.len());
+    let mut basis: Vec<Vec<f64>> = vec![];
+
+    for k in 0..n
+    {
+        if basis.len() == n - m
+        {
+            break;
+        }
+
+        let column: Vec<f64> = (0..m).map(|i| jacobian[(i, k)]).collect();
+        let w = cholesky_solve(jjt, &column)?;
+        let mut direction: Vec<f64> = (0..n)
+            .map(|i| {
+                let projected: f64 = (0..m).map(|j| jacobian[(j, i)] * w[j]).sum();
+                if i == k { 1.0 - projected } else { -projected }
+            })
+            .collect();
+
+        // Gram-Schmidt against the directions already found
+        for b in &basis
+        {
+            let dot: f64 = direction.iter().zip(b).map(|(x, y)| x * y).sum();
+            for (x, y) in direction.iter_mut().zip(b)
+            {
+                *x -= dot * y;
+            }
+        }
+
+        let length = direction.iter().map(|x| x * x).sum::<f64>().sqrt();
+        if length > FREE_DIRECTION_TOL
+        {
+            basis.push(direction.iter().map(|x| x / length).collect());
+        }
+    }
+
+    Ok(basis.into_iter()
+        .map(|direction| vars.iter().cloned().zip(direction).collect())
+        .collect())
+}
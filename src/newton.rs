@@ -1,8 +1,77 @@
 use std::collections::HashMap;
 use gmatlib::Matrix;
-use crate::errors::NewtonRaphsonSolverError;
+use crate::errors::{BracketingSolverError, LineSearchError, NewtonRaphsonSolverError, NotConvergedError, SingularJacobianError, SolverOptionsError};
+use crate::linalg::{lu_solve, normal_equations};
+use crate::options::{Method, SolverOptions};
+use crate::roots::{bisection, brent, illinois};
 
-const _DX_: f64 = 0.001; 
+/// The fraction of the decrease predicted by the slope of ‖F‖² that a line search step must achieve
+const ARMIJO_FRACTION: f64 = 1e-4;
+/// The smallest fraction of a step that a line search will try
+const MIN_STEP_LENGTH: f64 = 1e-10;
+/// The fraction of the residual that a step must remove for Broyden's method to 
+/// keep updating the jacobian instead of evaluating it again
+const BROYDEN_PROGRESS: f64 = 0.1;
+
+/// The domain of each variable of a system as `(min, max)`, by name. 
+/// Variables that are missing are unbounded.
+pub (in crate) type Bounds = HashMap<String, (f64, f64)>;
+
+/// The values of a system's functions and its jacobian at a guess.
+pub (in crate) type SystemEval = anyhow::Result<(Vec<f64>, Matrix<f64>)>;
+
+/// Runs `solve` with the options equivalent to `margin` and `limit`, keeping the errors 
+/// that the solvers taking a margin and an iteration limit have always given. A margin 
+/// that is not positive gives `NewtonRaphsonSolverError::NegativeMargin`, a limit of 0 
+/// gives `NewtonRaphsonSolverError::ReachedIterationLimit`, and a `NotConvergedError` 
+/// can be downcast to either `NotConvergedError` or `ReachedIterationLimit`.
+pub (in crate) fn solve_with_margin<T>(margin: f64, limit: usize, solve: impl FnOnce(&SolverOptions) -> anyhow::Result<T>) -> anyhow::Result<T>
+{
+    if margin <= 0.0
+    {
+        return Err(NewtonRaphsonSolverError::NegativeMargin.into());
+    }
+    if limit == 0
+    {
+        return Err(NewtonRaphsonSolverError::ReachedIterationLimit.into());
+    }
+
+    solve(&SolverOptions::with_margin(margin, limit)).map_err(|err| match err.is::<NotConvergedError<f64>>() 
+        || err.is::<NotConvergedError<HashMap<String, f64>>>()
+    {
+        true => err.context(NewtonRaphsonSolverError::ReachedIterationLimit),
+        false => err,
+    })
+}
+
+/// The state of a solver at one iteration, as recorded in a `SolverReport`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Iteration<T>
+{
+    /// The guess evaluated in this iteration
+    pub guess: T,
+    /// The size of the residual at `guess`
+    pub residual: f64,
+    /// The size of the step taken from `guess`
+    pub step: f64,
+}
+
+/// Describes how a solver reached its solution.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SolverReport<T>
+{
+    /// The solution found
+    pub solution: T,
+    /// The number of iterations performed, counting the one that found `solution`
+    pub iterations: usize,
+    /// The size of the residual at `solution`
+    pub residual: f64,
+    /// The size of the step that would have been taken from `solution`
+    pub step: f64,
+    /// Every iteration performed, in order. This is only recorded on request 
+    /// and is empty otherwise.
+    pub history: Vec<Iteration<T>>,
+}
 
 /// A basic implementation of the 1-D newton-raphson method.
 /// This function allows the caller to choose an initial guess value,
@@ -30,32 +99,188 @@ const _DX_: f64 = 0.001;
 pub fn newton_raphson<E>(f: impl Fn(f64) -> Result<f64, E>, guess: f64, margin: f64, limit: usize) -> anyhow::Result<f64>
 where anyhow::Error: From<E>
 {
-    // Catch illegal margin of error
-    if margin <= 0.0
+    solve_with_margin(margin, limit, |options| newton_raphson_report(f, guess, options)).map(|report| report.solution)
+}
+
+/// Identical to `newton_raphson`, but configured by `options` and returning a 
+/// `SolverReport` describing how the solution was found. The derivative is 
+/// approximated with the finite difference given in `options`.
+/// 
+/// If no solution is found within the iteration limit, the error is a 
+/// `NotConvergedError<f64>` holding the last guess evaluated. Only 
+/// `Method::Auto` and `Method::Newton` can be chosen.
+/// 
+/// # Example
+/// ```
+/// use std::io::Error;
+/// use geqslib::errors::NotConvergedError;
+/// use geqslib::newton::newton_raphson_report;
+/// use geqslib::options::SolverOptions;
+/// 
+/// fn f(x: f64) -> Result<f64, Error>
+/// {
+///     Ok(x * x - 2.0)
+/// }
+/// 
+/// let options = SolverOptions::new().record_history(true);
+/// let report = newton_raphson_report(f, 1.0, &options).unwrap();
+/// 
+/// assert!((report.solution - 2f64.sqrt()).abs() < 0.0001);
+/// assert!(report.residual <= 0.0001);
+/// assert_eq!(report.history.len(), report.iterations);
+/// assert_eq!(report.history[0].guess, 1.0);
+/// 
+/// // x^2 + 1 has no real roots
+/// let err = newton_raphson_report(|x: f64| Ok::<f64, Error>(x * x + 1.0), 1.0, &SolverOptions::new().max_iterations(10)).unwrap_err();
+/// let err = err.downcast_ref::<NotConvergedError<f64>>().unwrap();
+/// 
+/// assert_eq!(err.iterations, 10);
+/// assert!(err.residual >= 1.0);
+/// ```
+pub fn newton_raphson_report<E>(f: impl Fn(f64) -> Result<f64, E>, guess: f64, options: &SolverOptions) -> anyhow::Result<SolverReport<f64>>
+where anyhow::Error: From<E>
+{
+    options.require_method(&[Method::Auto, Method::Newton])?;
+
+    let eval = |x: f64| -> anyhow::Result<(f64, f64)> {
+        let y = f(x)?;
+        let dx = options.finite_difference.step_at(x);
+        let y_prime = (f(x + dx)? - y) / dx;
+        Ok((y, y_prime))
+    };
+    newton_raphson_impl(&eval, guess, (f64::NEG_INFINITY, f64::INFINITY), options)
+}
+
+/// Identical to `newton_raphson`, but uses the given derivative `f_prime`
+/// instead of approximating it with a finite difference.
+/// 
+/// # Example
+/// ```
+/// use std::io::Error;
+/// use geqslib::newton::newton_raphson_with_derivative;
+/// 
+/// fn f(x: f64) -> Result<f64, Error>
+/// {
+///     Ok(x * x - 2.0)
+/// }
+/// 
+/// fn f_prime(x: f64) -> Result<f64, Error>
+/// {
+///     Ok(2.0 * x)
+/// }
+/// 
+/// let x = newton_raphson_with_derivative(f, f_prime, 1.0, 0.0001, 100).unwrap();
+/// 
+/// assert!((x - 2f64.sqrt()).abs() < 0.0001);
+/// ```
+pub fn newton_raphson_with_derivative<E1, E2>(f: impl Fn(f64) -> Result<f64, E1>, f_prime: impl Fn(f64) -> Result<f64, E2>, guess: f64, margin: f64, limit: usize) -> anyhow::Result<f64>
+where anyhow::Error: From<E1> + From<E2>
+{
+    solve_with_margin(margin, limit, |options| newton_raphson_with_derivative_report(f, f_prime, guess, options)).map(|report| report.solution)
+}
+
+/// Identical to `newton_raphson_with_derivative`, but returns a `SolverReport`.
+/// See `newton_raphson_report`.
+pub fn newton_raphson_with_derivative_report<E1, E2>(f: impl Fn(f64) -> Result<f64, E1>, f_prime: impl Fn(f64) -> Result<f64, E2>, guess: f64, options: &SolverOptions) -> anyhow::Result<SolverReport<f64>>
+where anyhow::Error: From<E1> + From<E2>
+{
+    options.require_method(&[Method::Auto, Method::Newton])?;
+    bounded_newton_raphson_with_derivative_report(f, f_prime, guess, (f64::NEG_INFINITY, f64::INFINITY), options)
+}
+
+/// Identical to `newton_raphson_with_derivative_report`, but keeps `x` within 
+/// `domain`, given as `(min, max)`, by shortening any step that would leave it.
+pub (in crate) fn bounded_newton_raphson_with_derivative_report<E1, E2>(f: impl Fn(f64) -> Result<f64, E1>, f_prime: impl Fn(f64) -> Result<f64, E2>, guess: f64, domain: (f64, f64), options: &SolverOptions) -> anyhow::Result<SolverReport<f64>>
+where anyhow::Error: From<E1> + From<E2>
+{
+    let eval = |x: f64| -> anyhow::Result<(f64, f64)> {
+        Ok((f(x)?, f_prime(x)?))
+    };
+    newton_raphson_impl(&eval, guess, domain, options)
+}
+
+/// Solves `f(x) = 0` for an `x` within `domain`, given as `(min, max)`, starting from `guess`.
+/// This is how single equations are solved, on their own or as blocks of a system.
+///
+/// With `Method::Auto`, a finite domain is a bracket to search for the root in with Brent's
+/// method, and `Method::Bisection`, `Method::Illinois` and `Method::Brent` need one. These
+/// methods narrow the bracket down to the step tolerance, measured relative to the width of
+/// the domain, so their root still has to meet the residual tolerance, or else Newton-Raphson
+/// refines it. With `Method::Auto`, Newton-Raphson is also used if `f` does not change sign 
+/// over the domain, or the domain is not finite.
+pub (in crate) fn solve_in_domain<E1, E2>(f: impl Fn(f64) -> Result<f64, E1>, f_prime: impl Fn(f64) -> Result<f64, E2>, guess: f64, (min, max): (f64, f64), options: &SolverOptions) -> anyhow::Result<f64>
+where anyhow::Error: From<E1> + From<E2>
+{
+    let bracketed = min.is_finite() && max.is_finite();
+    match options.method
     {
-        return Err(NewtonRaphsonSolverError::NegativeMargin.into());
+        Method::Bisection | Method::Illinois | Method::Brent if !bracketed => return Err(SolverOptionsError::UnsupportedMethod.into()),
+        Method::Broyden | Method::LevenbergMarquardt => return Err(SolverOptionsError::UnsupportedMethod.into()),
+        _ => (),
     }
 
-    // Allow user to manually prevent stack overflow
-    if limit == 0
+    let mut start = guess;
+    if bracketed && options.method != Method::Newton
     {
-        return Err(NewtonRaphsonSolverError::ReachedIterationLimit.into());
+        // Valid options only give a step tolerance of 0 for a domain of no width
+        let margin = options.step_tol_at((max - min).abs()).max(f64::MIN_POSITIVE);
+        let root = match options.method
+        {
+            Method::Bisection => bisection(&f, min, max, margin, options.max_iterations),
+            Method::Illinois => illinois(&f, min, max, margin, options.max_iterations),
+            _ => brent(&f, min, max, margin, options.max_iterations),
+        };
+
+        match root
+        {
+            Ok(root) if f(root)?.abs() <= options.residual_tol(f(guess)?.abs()) => return Ok(root),
+            Ok(root) => start = root,
+            Err(e) if options.method == Method::Auto && e.downcast_ref::<BracketingSolverError>().is_some_and(|e| matches!(e, BracketingSolverError::NoSignChange)) => (),
+            Err(e) => return Err(e),
+        }
     }
 
-    let y = f(guess)?;
-    let y_prime = (f(guess + _DX_)? - y) / _DX_;
-    let delta = y / y_prime;
+    Ok(bounded_newton_raphson_with_derivative_report(f, f_prime, start, (min, max), options)?.solution)
+}
+
+/// The 1-D newton-raphson iteration shared by the public solvers. 
+/// `eval` returns both `f(x)` and `f'(x)` for a given `x`.
+fn newton_raphson_impl(eval: &impl Fn(f64) -> anyhow::Result<(f64, f64)>, guess: f64, (min, max): (f64, f64), options: &SolverOptions) -> anyhow::Result<SolverReport<f64>>
+{
+    options.validate()?;
+
+    let mut last = Iteration { guess, residual: f64::NAN, step: f64::NAN };
+    let mut history = vec![];
+    let mut guess = guess;
+    let mut residual_tol = options.abs_tol;
 
-    // Check if we are sufficiently close to the solution:
-    if y.abs() <= margin && delta <= margin // ...in both the y AND x directions...
+    for iterations in 1..=options.max_iterations
     {
-        return Ok(guess); // ...if so, exit early
-    }
+        let (y, y_prime) = eval(guess)?;
+        let delta = y / y_prime;
 
-    // ...if not, calculate next iteration
-    let next_guess = guess - delta;
+        if iterations == 1
+        {
+            residual_tol = options.residual_tol(y.abs());
+        }
+
+        last = Iteration { guess, residual: y.abs(), step: delta.abs() };
+        if options.record_history
+        {
+            history.push(last.clone());
+        }
 
-    newton_raphson(f, next_guess, margin, limit - 1)
+        // Check if we are sufficiently close to the solution:
+        if y.abs() <= residual_tol && delta.abs() <= options.step_tol_at(guess.abs()) // ...in both the y AND x directions...
+        {
+            return Ok(SolverReport { solution: guess, iterations, residual: last.residual, step: last.step, history });
+        }
+
+        // ...if not, calculate next iteration, stopping short at the edge of the domain
+        guess = (guess - options.damping * delta).max(min).min(max);
+    }
+
+    Err(NotConvergedError { last_iterate: last.guess, iterations: options.max_iterations, residual: last.residual, step: last.step, history }.into())
 }
 
 /// A basic implementation of the Newton-Raphson method for multivariate
@@ -101,85 +326,473 @@ where anyhow::Error: From<E>
 pub fn multivariate_newton_raphson<E>(f: Vec<impl Fn(&HashMap<String, f64>) -> Result<f64, E>>, guess: &mut HashMap<String, f64>, margin: f64, limit: usize) -> anyhow::Result<&mut HashMap<String, f64>>
 where anyhow::Error: From<E>
 {
-    // Catch illegal margin of error
-    if margin <= 0.0
-    {
-        return Err(NewtonRaphsonSolverError::NegativeMargin.into());
-    }
+    solve_with_margin(margin, limit, |options| multivariate_newton_raphson_report(f, guess, options))?;
+    Ok(guess)
+}
 
-    // Allow user to manually prevent stack overflow
-    if limit == 0
+/// Identical to `multivariate_newton_raphson`, but configured by `options` and 
+/// returning a `SolverReport` describing how the solution was found. `guess` is 
+/// still updated with the solution. The jacobian is approximated with the finite
+/// difference given in `options`, or only approximated when progress stalls and 
+/// updated in between if `options` uses `Method::Broyden`.
+/// 
+/// If no solution is found within the iteration limit, the error is a 
+/// `NotConvergedError<HashMap<String, f64>>` holding the last guess evaluated. 
+/// Only `Method::Auto`, `Method::Newton` and `Method::Broyden` can be chosen.
+/// 
+/// # Example
+/// ```
+/// use std::io::Error;
+/// use std::collections::HashMap;
+/// use geqslib::newton::multivariate_newton_raphson_report;
+/// use geqslib::options::SolverOptions;
+/// 
+/// fn f1(x: &HashMap<String, f64>) -> Result<f64, Error>
+/// {
+///     Ok(x["x"] + x["y"] - 9.0)
+/// }
+/// 
+/// fn f2(x: &HashMap<String, f64>) -> Result<f64, Error>
+/// {
+///     Ok(x["x"] - x["y"] - 4.0)
+/// }
+/// 
+/// let mut guess = HashMap::from([
+///     ("x".to_string(), 7.0),
+///     ("y".to_string(), 2.0),
+/// ]);
+/// 
+/// let report = multivariate_newton_raphson_report(vec![f1, f2], &mut guess, &SolverOptions::new()).unwrap();
+/// 
+/// assert!((report.solution["x"] - 6.5).abs() < 0.0001);
+/// assert!(report.iterations <= 3);
+/// assert!(report.history.is_empty());
+/// ```
+pub fn multivariate_newton_raphson_report<E>(f: Vec<impl Fn(&HashMap<String, f64>) -> Result<f64, E>>, guess: &mut HashMap<String, f64>, options: &SolverOptions) -> anyhow::Result<SolverReport<HashMap<String, f64>>>
+where anyhow::Error: From<E>
+{
+    options.require_method(&[Method::Auto, Method::Newton, Method::Broyden])?;
+    if f.len() != guess.len()
     {
-        return Err(NewtonRaphsonSolverError::ReachedIterationLimit.into());
+        return Err(NewtonRaphsonSolverError::ImproperlyConstrainedSystem.into());
     }
+    multivariate_newton_raphson_impl(&finite_difference_eval(&f, options), &|guess| eval_system(&f, guess), guess, &Bounds::new(), options)
+}
 
-    // Establish system size
-    let n = f.len();
-    if guess.len() != n
+/// Identical to `multivariate_newton_raphson`, but uses the given partial 
+/// derivatives instead of approximating the jacobian with finite differences.
+/// 
+/// `jacobian[i]` maps the name of each variable to the partial derivative 
+/// of `f[i]` with respect to that variable. Any variable missing from 
+/// `jacobian[i]` is assumed to have no effect on `f[i]`.
+/// 
+/// # Example
+/// ```
+/// use std::io::Error;
+/// use std::collections::HashMap;
+/// use geqslib::newton::multivariate_newton_raphson_with_jacobian;
+/// 
+/// let f: Vec<Box<dyn Fn(&HashMap<String, f64>) -> Result<f64, Error>>> = vec![
+///     Box::new(|x| Ok(x["x"] * x["y"] - 6.0)),
+///     Box::new(|x| Ok(x["x"] - x["y"] - 1.0)),
+/// ];
+/// 
+/// let jacobian: Vec<HashMap<String, Box<dyn Fn(&HashMap<String, f64>) -> Result<f64, Error>>>> = vec![
+///     HashMap::from([
+///         ("x".to_string(), Box::new(|x: &HashMap<String, f64>| Ok(x["y"])) as Box<_>),
+///         ("y".to_string(), Box::new(|x: &HashMap<String, f64>| Ok(x["x"])) as Box<_>),
+///     ]),
+///     HashMap::from([
+///         ("x".to_string(), Box::new(|_: &HashMap<String, f64>| Ok(1.0)) as Box<_>),
+///         ("y".to_string(), Box::new(|_: &HashMap<String, f64>| Ok(-1.0)) as Box<_>),
+///     ]),
+/// ];
+/// 
+/// let mut guess = HashMap::from([
+///     ("x".to_string(), 4.0),
+///     ("y".to_string(), 1.0),
+/// ]);
+/// 
+/// let soln = multivariate_newton_raphson_with_jacobian(f, jacobian, &mut guess, 0.0001, 50).unwrap();
+/// 
+/// assert!((soln["x"] - 3.0).abs() < 0.0001);
+/// assert!((soln["y"] - 2.0).abs() < 0.0001);
+/// ```
+pub fn multivariate_newton_raphson_with_jacobian<E1, E2>(f: Vec<impl Fn(&HashMap<String, f64>) -> Result<f64, E1>>, jacobian: Vec<HashMap<String, impl Fn(&HashMap<String, f64>) -> Result<f64, E2>>>, guess: &mut HashMap<String, f64>, margin: f64, limit: usize) -> anyhow::Result<&mut HashMap<String, f64>>
+where anyhow::Error: From<E1> + From<E2>
+{
+    solve_with_margin(margin, limit, |options| multivariate_newton_raphson_with_jacobian_report(f, jacobian, guess, options))?;
+    Ok(guess)
+}
+
+/// Identical to `multivariate_newton_raphson_with_jacobian`, but returns a 
+/// `SolverReport`. See `multivariate_newton_raphson_report`.
+pub fn multivariate_newton_raphson_with_jacobian_report<E1, E2>(f: Vec<impl Fn(&HashMap<String, f64>) -> Result<f64, E1>>, jacobian: Vec<HashMap<String, impl Fn(&HashMap<String, f64>) -> Result<f64, E2>>>, guess: &mut HashMap<String, f64>, options: &SolverOptions) -> anyhow::Result<SolverReport<HashMap<String, f64>>>
+where anyhow::Error: From<E1> + From<E2>
+{
+    options.require_method(&[Method::Auto, Method::Newton, Method::Broyden])?;
+    bounded_multivariate_newton_raphson_with_jacobian_report(f, jacobian, guess, &Bounds::new(), options)
+}
+
+/// Identical to `multivariate_newton_raphson_with_jacobian_report`, but keeps 
+/// each variable within its `bounds` by shortening any step that would leave them.
+pub (in crate) fn bounded_multivariate_newton_raphson_with_jacobian_report<E1, E2>(f: Vec<impl Fn(&HashMap<String, f64>) -> Result<f64, E1>>, jacobian: Vec<HashMap<String, impl Fn(&HashMap<String, f64>) -> Result<f64, E2>>>, guess: &mut HashMap<String, f64>, bounds: &Bounds, options: &SolverOptions) -> anyhow::Result<SolverReport<HashMap<String, f64>>>
+where anyhow::Error: From<E1> + From<E2>
+{
+    if f.len() != guess.len() || jacobian.len() != f.len()
     {
         return Err(NewtonRaphsonSolverError::ImproperlyConstrainedSystem.into());
     }
+    multivariate_newton_raphson_impl(&exact_jacobian_eval(&f, &jacobian), &|guess| eval_system(&f, guess), guess, bounds, options)
+}
+
+/// Returns a function that evaluates a system and approximates its jacobian with
+/// the finite difference given in `options`, for use by the multivariate solvers.
+/// The jacobian has a row for each function and a column for each variable.
+pub (in crate) fn finite_difference_eval<'a, E>(f: &'a [impl Fn(&HashMap<String, f64>) -> Result<f64, E>], options: &'a SolverOptions) -> impl Fn(&mut HashMap<String, f64>, &[String]) -> SystemEval + 'a
+where anyhow::Error: From<E>
+{
+    move |guess: &mut HashMap<String, f64>, vars: &[String]| -> SystemEval {
+        let n = vars.len();
+
+        // Calculate current error
+        let y = eval_system(f, guess)?;
+
+        // Build jacobian w/ F(X) values... we will mutate them to F'(X) later
+        let mut elements = vec![];
+        for y_i in &y
+        {
+            let row = &mut vec![*y_i; n];
+            elements.append(row);
+        }
+        let mut jacobian = Matrix::from_vec(n, elements)?; // <- should this be a panic on failure?
+
+        // Correct jacobian values
+        for j in 0..n
+        {
+            let x_j = guess[&vars[j]];
+            let dx = options.finite_difference.step_at(x_j);
+            if let Some(v) = guess.get_mut(&vars[j])
+            {
+                *v = x_j + dx;
+            } 
+            for i in 0..f.len()
+            {
+                // mutate values to partial derivatives
+                jacobian[(i, j)] = (f[i](guess)? - jacobian[(i, j)]) / dx;
+            }
+            if let Some(v) = guess.get_mut(&vars[j])
+            {
+                *v = x_j;
+            } 
+        }
+
+        Ok((y, jacobian))
+    }
+}
+
+/// Returns a function that evaluates a system and its given `jacobian`, for use 
+/// by the multivariate solvers. The jacobian has a row for each function and a 
+/// column for each variable.
+pub (in crate) fn exact_jacobian_eval<'a, E1, E2>(f: &'a [impl Fn(&HashMap<String, f64>) -> Result<f64, E1>], jacobian: &'a [HashMap<String, impl Fn(&HashMap<String, f64>) -> Result<f64, E2>>]) -> impl Fn(&mut HashMap<String, f64>, &[String]) -> SystemEval + 'a
+where anyhow::Error: From<E1> + From<E2>
+{
+    move |guess: &mut HashMap<String, f64>, vars: &[String]| -> SystemEval {
+        let n = vars.len();
+
+        let y = eval_system(f, guess)?;
+        let mut jacobian_values = Matrix::new(f.len(), n);
+        for i in 0..f.len()
+        {
+            for j in 0..n
+            {
+                if let Some(df_dx) = jacobian[i].get(&vars[j])
+                {
+                    jacobian_values[(i, j)] = df_dx(guess)?;
+                }
+            }
+        }
+
+        Ok((y, jacobian_values))
+    }
+}
 
-    // Build jacobian w/ F(X) values... we will mutate them to F'(X) later
-    let mut elements = vec![];
-    for func in &f 
+/// Evaluates every function of a system at `guess`.
+pub (in crate) fn eval_system<E>(f: &[impl Fn(&HashMap<String, f64>) -> Result<f64, E>], guess: &HashMap<String, f64>) -> anyhow::Result<Vec<f64>>
+where anyhow::Error: From<E>
+{
+    let mut y = Vec::with_capacity(f.len());
+    for f_i in f
     {
-        let row = &mut vec![func(guess)?; n];
-        elements.append(row);
+        y.push(f_i(guess)?);
     }
-    let mut jacobian = Matrix::from_vec(n, elements)?; // <- should this be a panic on failure?
+    Ok(y)
+}
+
+/// The multivariate newton-raphson iteration shared by the public solvers. 
+/// `eval` returns both F(X) and the jacobian of F at X for a given guess 
+/// vector X, with columns ordered like the given variable names. `residual`
+/// returns just F(X), for use by the line search and Broyden's method.
+/// 
+/// With `Method::Broyden`, the jacobian is only evaluated on the first iteration
+/// and whenever a step fails to make progress. Otherwise, it is corrected with a
+/// rank-one update from the change in F(X) over the last step.
+fn multivariate_newton_raphson_impl(eval: &impl Fn(&mut HashMap<String, f64>, &[String]) -> SystemEval, residual: &impl Fn(&HashMap<String, f64>) -> anyhow::Result<Vec<f64>>, guess: &mut HashMap<String, f64>, bounds: &Bounds, options: &SolverOptions) -> anyhow::Result<SolverReport<HashMap<String, f64>>>
+{
+    options.validate()?;
+
+    // Establish system size
     let vars = Vec::from_iter(guess.keys().map(|x| x.to_string()));
 
-    // Correct jacobian values and invert
-    for j in 0..n
+    let mut last = Iteration { guess: guess.clone(), residual: f64::NAN, step: f64::NAN };
+    let mut history = vec![];
+    let mut residual_tol = options.abs_tol;
+    let mut updated: Option<(Vec<f64>, Matrix<f64>)> = None;
+
+    for iterations in 1..=options.max_iterations
     {
-        if let Some(v) = guess.get_mut(&vars[j])
+        // Evaluate system and solve for the newton step, unless Broyden's method 
+        // gave an updated jacobian. An update can make the jacobian singular, so 
+        // it falls back on evaluating the jacobian then too.
+        let (y, jacobian, deltas, from_update) = match updated.take().map(|(y, jacobian)| (newton_step(&jacobian, &y, &vars), y, jacobian))
         {
-            *v += _DX_;
-        } 
-        for i in 0..n
+            Some((Ok(deltas), y, jacobian)) => (y, jacobian, deltas, true),
+            _ => {
+                let (y, jacobian) = eval(guess, &vars)?;
+                let deltas = newton_step(&jacobian, &y, &vars)?;
+                (y, jacobian, deltas, false)
+            },
+        };
+
+        // Calculate current error and the magnitude of the step
+        let start: Vec<f64> = vars.iter().map(|var| guess[var]).collect();
+        let error = options.norm.of(&y);
+        let change = options.norm.of(&deltas);
+
+        if iterations == 1
         {
-            // mutate values to partial derivatives
-            jacobian[(i, j)] = (f[i](guess)? - jacobian[(i, j)]) / _DX_;
+            residual_tol = options.residual_tol(error);
         }
-        if let Some(v) = guess.get_mut(&vars[j])
+
+        last = Iteration { guess: guess.clone(), residual: error, step: change };
+        if options.record_history
+        {
+            history.push(last.clone());
+        }
+
+        if error <= residual_tol && change <= options.step_tol_at(options.norm.of(&start))
+        {
+            return Ok(SolverReport { solution: last.guess, iterations, residual: error, step: change, history });
+        }
+
+        // Build next guess vector
+        let step: Vec<f64> = deltas.iter()
+            .map(|delta| -options.damping * delta)
+            .collect();
+
+        if options.line_search
         {
-            *v -= _DX_;
-        } 
+            match line_search(residual, guess, &vars, &y, &jacobian, step, bounds)
+            {
+                // An updated jacobian can be too far off to find a decrease with, 
+                // so evaluate it again before giving up
+                Err(err) if from_update && err.is::<LineSearchError>() => continue,
+                searched => searched?,
+            }
+        }
+        else
+        {
+            take_step(guess, &vars, &start, step, bounds);
+        }
+
+        if options.method == Method::Broyden
+        {
+            let next_y = residual(guess)?;
+            if options.norm.of(&next_y) <= (1.0 - BROYDEN_PROGRESS) * error
+            {
+                let taken: Vec<f64> = vars.iter().zip(&start).map(|(var, x)| guess[var] - x).collect();
+                updated = broyden_update(jacobian, &taken, &y, &next_y).map(|jacobian| (next_y, jacobian));
+            }
+        }
     }
-    jacobian.try_inplace_invert()?;
 
-    // Calculate current error
-    let mut y = vec![0.0; n];
-    for i in 0..n
+    Err(NotConvergedError { last_iterate: last.guess, iterations: options.max_iterations, residual: last.residual, step: last.step, history }.into())
+}
+
+/// Solves `jacobian * deltas = y` for the newton step by LU factorization. If the 
+/// jacobian is singular, the error names the variables that can change together
+/// without changing the linearized system.
+fn newton_step(jacobian: &Matrix<f64>, y: &[f64], vars: &[String]) -> anyhow::Result<Vec<f64>>
+{
+    lu_solve(jacobian, y).map_err(|deficiency| {
+        let mut involved: Vec<String> = vars.iter()
+            .enumerate()
+            .filter(|&(i, _)| deficiency.null_space.iter().any(|v| v[i] != 0.0))
+            .map(|(_, var)| var.to_string())
+            .collect();
+        involved.sort();
+
+        SingularJacobianError { rank: deficiency.rank, size: vars.len(), vars: involved }.into()
+    })
+}
+
+/// Applies Broyden's ("good") rank-one update to `jacobian` after a step `step` 
+/// changed F(X) from `y` to `next_y`, so that the updated jacobian maps the 
+/// step to that change exactly: J + ((ΔF - J * step) * stepᵀ) / (stepᵀ * step).
+/// Returns `None` if the step was too small to update from.
+fn broyden_update(mut jacobian: Matrix<f64>, step: &[f64], y: &[f64], next_y: &[f64]) -> Option<Matrix<f64>>
+{
+    let step_squared: f64 = step.iter().map(|s| s * s).sum();
+    if step_squared == 0.0 || !step_squared.is_finite()
     {
-        y[i] = f[i](guess)?;
+        return None;
     }
-    let error = y.iter()
-        .map(|v| v.abs())
-        .sum::<f64>();
 
-    // Calculate change vector and its magnitude
-    let deltas: Vec<f64> = (jacobian * Matrix::from_col_vec(y)).into();
-    let change = deltas.iter()
-        .map(|d| d.abs())
-        .sum::<f64>()
-        .sqrt();
+    let j_step: Vec<f64> = (&jacobian * Matrix::from_col_vec(step.to_vec())).into();
+    for i in 0..y.len()
+    {
+        let correction = (next_y[i] - y[i] - j_step[i]) / step_squared;
+        for (j, step_j) in step.iter().enumerate()
+        {
+            jacobian[(i, j)] += correction * step_j;
+        }
+    }
+    Some(jacobian)
+}
 
-    if error <= margin && change <= margin
+/// Moves `guess` from `start` along `step`, shortened so that no variable leaves its `bounds`.
+pub (in crate) fn take_step(guess: &mut HashMap<String, f64>, vars: &[String], start: &[f64], mut step: Vec<f64>, bounds: &Bounds)
+{
+    let length = fit_step_to_bounds(start, vars, &mut step, bounds);
+    for ((var, x), step_i) in vars.iter().zip(start).zip(&step)
     {
-        return Ok(guess);
+        if let Some(guess_val) = guess.get_mut(var)
+        {
+            *guess_val = x + length * step_i;
+        }
     }
+}
 
-    // Build next guess vector
-    for (i, var) in vars.iter().enumerate().take(n)
+/// Moves `guess` along `step` with an Armijo backtracking line search on ‖F‖². 
+/// The step is shortened so that no variable leaves its `bounds`, and any part 
+/// of the step pushing a variable further into a bound it is already at is dropped.
+/// 
+/// If what is left of the step does not point downhill, the search is made along 
+/// the steepest descent direction `-JᵀF` instead. If no step decreases ‖F‖² enough, 
+/// `guess` is left where it was and a `LineSearchError` is returned.
+pub (in crate) fn line_search(residual: &impl Fn(&HashMap<String, f64>) -> anyhow::Result<Vec<f64>>, guess: &mut HashMap<String, f64>, vars: &[String], y: &[f64], jacobian: &Matrix<f64>, mut step: Vec<f64>, bounds: &Bounds) -> anyhow::Result<()>
+{
+    let start: Vec<f64> = vars.iter().map(|var| guess[var]).collect();
+    let start_norm = y.iter().map(|y_i| y_i * y_i).sum::<f64>();
+    let step_size = step.iter().map(|s| s * s).sum::<f64>().sqrt();
+
+    let mut max_length = fit_step_to_bounds(&start, vars, &mut step, bounds);
+    let mut slope = descent_slope(y, jacobian, &step);
+    if slope >= 0.0
     {
-        if let (Some(guess_val), Some(delta)) = (guess.get_mut(var), deltas.get(i))
+        // -JᵀF is the direction ‖F‖² falls fastest in, taken as far as the original step
+        let (_, jty) = normal_equations(jacobian, y);
+        let gradient_size = jty.iter().map(|g| g * g).sum::<f64>().sqrt();
+        step = jty.iter().map(|g| -g * step_size / gradient_size).collect();
+
+        max_length = fit_step_to_bounds(&start, vars, &mut step, bounds);
+        slope = descent_slope(y, jacobian, &step);
+        if slope >= 0.0 || slope.is_nan()
         {
-            *guess_val -= delta;
+            return Err(LineSearchError::NoDescentDirection.into());
         }
     }
 
-    // COMPUTER, ENHANCE!
-    multivariate_newton_raphson(f, guess, margin, limit - 1)
-}
\ No newline at end of file
+    let mut length = max_length;
+    while length >= MIN_STEP_LENGTH
+    {
+        for (i, var) in vars.iter().enumerate()
+        {
+            if let Some(guess_val) = guess.get_mut(var)
+            {
+                *guess_val = start[i] + length * step[i];
+            }
+        }
+
+        let norm = residual(guess)?.iter().map(|y_i| y_i * y_i).sum::<f64>();
+        if norm <= start_norm + ARMIJO_FRACTION * length * slope
+        {
+            return Ok(());
+        }
+        length *= 0.5;
+    }
+
+    for (var, x) in vars.iter().zip(&start)
+    {
+        if let Some(guess_val) = guess.get_mut(var)
+        {
+            *guess_val = *x;
+        }
+    }
+    Err(LineSearchError::NoSufficientDecrease.into())
+}
+
+/// Returns the slope of ‖F‖² along `step`, which is 2 * F · (J * step).
+fn descent_slope(y: &[f64], jacobian: &Matrix<f64>, step: &[f64]) -> f64
+{
+    let j_step: Vec<f64> = (jacobian * Matrix::from_col_vec(step.to_vec())).into();
+    2.0 * y.iter().zip(&j_step).map(|(y_i, d_i)| y_i * d_i).sum::<f64>()
+}
+
+/// Returns the longest fraction of `step` from `start` that keeps every variable 
+/// within its `bounds`. Any part of the step pushing a variable further into a 
+/// bound it is already at is dropped from `step` first.
+pub (in crate) fn fit_step_to_bounds(start: &[f64], vars: &[String], step: &mut [f64], bounds: &Bounds) -> f64
+{
+    let mut max_length: f64 = 1.0;
+    for (i, var) in vars.iter().enumerate()
+    {
+        let (min, max) = match bounds.get(var)
+        {
+            Some(&bound) => bound,
+            None => continue,
+        };
+
+        if (start[i] <= min && step[i] < 0.0) || (start[i] >= max && step[i] > 0.0)
+        {
+            step[i] = 0.0;
+        }
+        else if start[i] + step[i] > max
+        {
+            max_length = max_length.min((max - start[i]) / step[i]);
+        }
+        else if start[i] + step[i] < min
+        {
+            max_length = max_length.min((min - start[i]) / step[i]);
+        }
+    }
+    max_length
+}
+
+#[test]
+fn test_line_search_only_takes_steps_that_decrease_the_residual()
+{
+    // F(x) = -x - 1, so ‖F‖² falls toward x = -1
+    let residual = |guess: &HashMap<String, f64>| Ok(vec![-guess["x"] - 1.0]);
+    let vars = vec!["x".to_string()];
+    let y = [-1.0];
+
+    // a step uphill is turned around to follow the steepest descent
+    let mut guess = HashMap::from([("x".to_string(), 0.0)]);
+    let jacobian = Matrix::from_col_vec(vec![-1.0]);
+    line_search(&residual, &mut guess, &vars, &y, &jacobian, vec![1.0], &Bounds::new()).unwrap();
+    assert!((guess["x"] + 1.0).abs() < 1e-9);
+
+    // a jacobian with the wrong sign predicts a decrease that never comes
+    let mut guess = HashMap::from([("x".to_string(), 0.0)]);
+    let jacobian = Matrix::from_col_vec(vec![1.0]);
+    let err = line_search(&residual, &mut guess, &vars, &y, &jacobian, vec![1.0], &Bounds::new()).unwrap_err();
+    assert!(matches!(err.downcast_ref::<LineSearchError>(), Some(LineSearchError::NoSufficientDecrease)));
+    assert_eq!(guess["x"], 0.0);
+
+    // no direction downhill is left once the domain blocks it
+    let bounds = Bounds::from([("x".to_string(), (0.0, f64::INFINITY))]);
+    let jacobian = Matrix::from_col_vec(vec![-1.0]);
+    let err = line_search(&residual, &mut guess, &vars, &y, &jacobian, vec![-1.0], &bounds).unwrap_err();
+    assert!(matches!(err.downcast_ref::<LineSearchError>(), Some(LineSearchError::NoDescentDirection)));
+}
@@ -0,0 +1,335 @@
+use crate::errors::SolverOptionsError;
+
+/// How the step for a finite-difference derivative is chosen.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FiniteDifference
+{
+    /// Always steps by the given amount.
+    Absolute(f64),
+    /// Steps by the given fraction of the magnitude of the variable, or by
+    /// the given amount if the variable's magnitude is less than 1. This
+    /// keeps the step meaningful for very large values.
+    Relative(f64),
+}
+
+impl FiniteDifference
+{
+    /// Returns the step to take from `x` when approximating a derivative at `x`.
+    ///
+    /// # Example
+    /// ```
+    /// use geqslib::options::FiniteDifference;
+    ///
+    /// assert_eq!(FiniteDifference::Absolute(0.001).step_at(5000.0), 0.001);
+    /// assert_eq!(FiniteDifference::Relative(0.001).step_at(5000.0), 5.0);
+    /// assert_eq!(FiniteDifference::Relative(0.001).step_at(0.0), 0.001);
+    /// ```
+    pub fn step_at(&self, x: f64) -> f64
+    {
+        match *self
+        {
+            FiniteDifference::Absolute(h) => h,
+            FiniteDifference::Relative(h) => h * x.abs().max(1.0),
+        }
+    }
+
+    fn size(&self) -> f64
+    {
+        match *self
+        {
+            FiniteDifference::Absolute(h) | FiniteDifference::Relative(h) => h,
+        }
+    }
+}
+
+/// How the size of a vector of residuals or steps is measured when checking for convergence.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Norm
+{
+    /// The sum of the absolute values of the elements.
+    L1,
+    /// The euclidean length of the vector.
+    #[default]
+    L2,
+    /// The largest absolute value of the elements.
+    LInf,
+}
+
+impl Norm
+{
+    /// Returns the size of `v` in this norm.
+    ///
+    /// # Example
+    /// ```
+    /// use geqslib::options::Norm;
+    ///
+    /// let v = [3.0, -4.0];
+    ///
+    /// assert_eq!(Norm::L1.of(&v), 7.0);
+    /// assert_eq!(Norm::L2.of(&v), 5.0);
+    /// assert_eq!(Norm::LInf.of(&v), 4.0);
+    /// ```
+    pub fn of(&self, v: &[f64]) -> f64
+    {
+        match *self
+        {
+            Norm::L1 => v.iter().map(|x| x.abs()).sum(),
+            Norm::L2 => v.iter().map(|x| x * x).sum::<f64>().sqrt(),
+            Norm::LInf => v.iter().fold(0.0, |max, x| max.max(x.abs())),
+        }
+    }
+}
+
+/// The algorithm used to find a solution.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Method
+{
+    /// Lets the solver choose. Single equations use Brent's method when their
+    /// unknown has a finite domain that brackets a root and Newton-Raphson
+    /// otherwise. Systems are solved block by block: blocks of one equation are
+    /// solved like single equations, and larger blocks use Newton-Raphson.
+    #[default]
+    Auto,
+    /// Newton-Raphson, for single equations and systems.
+    Newton,
+    /// Broyden's quasi-Newton method, for systems. The jacobian is updated from 
+    /// the values of the system between iterations instead of being evaluated 
+    /// again, which saves evaluating every equation once per variable on each 
+    /// iteration. It is only evaluated again when an iteration fails to make progress.
+    Broyden,
+    /// Bisection, for single equations with a finite domain.
+    Bisection,
+    /// The Illinois variant of regula falsi, for single equations with a finite domain.
+    Illinois,
+    /// Brent's method, for single equations with a finite domain.
+    Brent,
+    /// The Levenberg-Marquardt method, for systems. This is more robust than
+    /// Newton-Raphson where the jacobian is singular or nearly so.
+    LevenbergMarquardt,
+}
+
+/// Configures how an equation or system of equations is solved.
+///
+/// Options are built up from the defaults given by `SolverOptions::new`,
+/// and the same options are accepted by every solver in this crate.
+///
+/// A solver stops at a guess `x` once both of these hold, where `F` is the 
+/// vector of residuals, `F0` is `F` at the initial guess, `step` is the full 
+/// step the solver would take from `x`, and `‖·‖` is the chosen `norm`:
+///
+/// - `‖F(x)‖ <= max(abs_tol, rel_tol * ‖F0‖)`
+/// - `‖step‖ <= max(step_tol, rel_step_tol * ‖x‖)`
+///
+/// For a single equation every norm is the absolute value.
+///
+/// # Example
+/// ```
+/// use geqslib::options::{FiniteDifference, Method, SolverOptions};
+///
+/// let options = SolverOptions::new()
+///     .abs_tol(1e-9)
+///     .step_tol(1e-9)
+///     .max_iterations(200)
+///     .finite_difference(FiniteDifference::Relative(1e-6))
+///     .damping(0.5)
+///     .method(Method::Newton);
+///
+/// assert!(options.validate().is_ok());
+/// assert!(SolverOptions::new().damping(0.0).validate().is_err());
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct SolverOptions
+{
+    pub (in crate) abs_tol: f64,
+    pub (in crate) rel_tol: f64,
+    pub (in crate) step_tol: f64,
+    pub (in crate) rel_step_tol: f64,
+    pub (in crate) norm: Norm,
+    pub (in crate) max_iterations: usize,
+    pub (in crate) finite_difference: FiniteDifference,
+    pub (in crate) damping: f64,
+    pub (in crate) method: Method,
+    pub (in crate) line_search: bool,
+    pub (in crate) record_history: bool,
+}
+
+impl Default for SolverOptions
+{
+    fn default() -> SolverOptions
+    {
+        SolverOptions
+        {
+            abs_tol: 0.0001,
+            rel_tol: 0.0,
+            step_tol: 0.0001,
+            rel_step_tol: 0.0,
+            norm: Norm::L2,
+            max_iterations: 100,
+            finite_difference: FiniteDifference::Absolute(0.001),
+            damping: 1.0,
+            method: Method::Auto,
+            line_search: false,
+            record_history: false,
+        }
+    }
+}
+
+impl SolverOptions
+{
+    /// Creates the default options:
+    ///
+    /// - `abs_tol`: `0.0001`
+    /// - `rel_tol`: `0.0`
+    /// - `step_tol`: `0.0001`
+    /// - `rel_step_tol`: `0.0`
+    /// - `norm`: `Norm::L2`
+    /// - `max_iterations`: `100`
+    /// - `finite_difference`: `FiniteDifference::Absolute(0.001)`
+    /// - `damping`: `1.0`
+    /// - `method`: `Method::Auto`
+    /// - `line_search`: `false`
+    /// - `record_history`: `false`
+    pub fn new() -> SolverOptions
+    {
+        SolverOptions::default()
+    }
+
+    /// Creates the options equivalent to passing `margin` and `limit` to one of
+    /// the solvers that takes them as arguments. `margin` is used as both the
+    /// absolute residual tolerance and the step tolerance.
+    pub fn with_margin(margin: f64, limit: usize) -> SolverOptions
+    {
+        SolverOptions::new()
+            .abs_tol(margin)
+            .step_tol(margin)
+            .max_iterations(limit)
+    }
+
+    /// Sets the largest residual that is accepted as a solution.
+    pub fn abs_tol(mut self, tol: f64) -> SolverOptions
+    {
+        self.abs_tol = tol;
+        self
+    }
+
+    /// Sets the largest residual that is accepted as a solution, as a fraction of
+    /// the residual at the initial guess. The larger of this and `abs_tol` is used.
+    pub fn rel_tol(mut self, tol: f64) -> SolverOptions
+    {
+        self.rel_tol = tol;
+        self
+    }
+
+    /// Sets the largest step that is accepted at a solution. Solvers stop once
+    /// the residual is small enough AND the step they would take is no larger than this.
+    pub fn step_tol(mut self, tol: f64) -> SolverOptions
+    {
+        self.step_tol = tol;
+        self
+    }
+
+    /// Sets the largest step that is accepted at a solution, as a fraction of the
+    /// size of the guess it is taken from. The larger of this and `step_tol` is used.
+    pub fn rel_step_tol(mut self, tol: f64) -> SolverOptions
+    {
+        self.rel_step_tol = tol;
+        self
+    }
+
+    /// Sets the norm used to measure residuals and steps.
+    pub fn norm(mut self, norm: Norm) -> SolverOptions
+    {
+        self.norm = norm;
+        self
+    }
+
+    /// Sets the number of iterations to try before giving up.
+    pub fn max_iterations(mut self, limit: usize) -> SolverOptions
+    {
+        self.max_iterations = limit;
+        self
+    }
+
+    /// Sets how the step is chosen where derivatives are approximated by finite differences.
+    pub fn finite_difference(mut self, finite_difference: FiniteDifference) -> SolverOptions
+    {
+        self.finite_difference = finite_difference;
+        self
+    }
+
+    /// Sets the fraction of each Newton step that is taken. Must be in `(0, 1]`.
+    /// Damping below `1.0` converges more slowly but is less likely to overshoot.
+    pub fn damping(mut self, damping: f64) -> SolverOptions
+    {
+        self.damping = damping;
+        self
+    }
+
+    /// Sets the algorithm used to find a solution.
+    pub fn method(mut self, method: Method) -> SolverOptions
+    {
+        self.method = method;
+        self
+    }
+
+    /// Sets whether systems are solved with a backtracking line search. Each Newton 
+    /// step is shortened until it reduces ‖F‖² enough, which stops the solver from 
+    /// overshooting on strongly nonlinear systems. Steps that don't decrease ‖F‖² 
+    /// at all give a `LineSearchError`.
+    pub fn line_search(mut self, line_search: bool) -> SolverOptions
+    {
+        self.line_search = line_search;
+        self
+    }
+
+    /// Sets whether every iteration is recorded in the `history` of the solver's report.
+    pub fn record_history(mut self, record_history: bool) -> SolverOptions
+    {
+        self.record_history = record_history;
+        self
+    }
+
+    /// Checks that the options can be used by a solver. This is called by
+    /// every solver before it starts.
+    pub fn validate(&self) -> anyhow::Result<()>
+    {
+        if !(self.abs_tol >= 0.0 && self.rel_tol >= 0.0 && self.step_tol >= 0.0 && self.rel_step_tol >= 0.0) 
+            || self.abs_tol + self.rel_tol == 0.0 
+            || self.step_tol + self.rel_step_tol == 0.0
+        {
+            return Err(SolverOptionsError::NonPositiveTolerance.into());
+        }
+        if !(self.damping > 0.0 && self.damping <= 1.0)
+        {
+            return Err(SolverOptionsError::InvalidDamping.into());
+        }
+        let step = self.finite_difference.size();
+        if step.is_nan() || step <= 0.0
+        {
+            return Err(SolverOptionsError::NonPositiveStep.into());
+        }
+        Ok(())
+    }
+
+    /// Checks that the chosen method is one of `methods`, the ones a solver can run.
+    pub (in crate) fn require_method(&self, methods: &[Method]) -> anyhow::Result<()>
+    {
+        if !methods.contains(&self.method)
+        {
+            return Err(SolverOptionsError::UnsupportedMethod.into());
+        }
+        Ok(())
+    }
+
+    /// Returns the largest residual accepted as a solution, given the residual at the initial guess.
+    pub (in crate) fn residual_tol(&self, initial_residual: f64) -> f64
+    {
+        self.abs_tol.max(self.rel_tol * initial_residual)
+    }
+
+    /// Returns the largest step accepted at a solution, given the size of the guess it is taken from.
+    pub (in crate) fn step_tol_at(&self, guess_size: f64) -> f64
+    {
+        self.step_tol.max(self.rel_step_tol * guess_size)
+    }
+}
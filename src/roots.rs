@@ -0,0 +1,252 @@
+use crate::errors::BracketingSolverError;
+
+/// Evaluates `f` at both ends of the bracket `[a, b]`, checking that the
+/// margin is legal and that `f` changes sign across the bracket.
+fn check_bracket<E>(f: &impl Fn(f64) -> Result<f64, E>, a: f64, b: f64, margin: f64) -> anyhow::Result<(f64, f64)>
+where anyhow::Error: From<E>
+{
+    if margin <= 0.0
+    {
+        return Err(BracketingSolverError::NegativeMargin.into());
+    }
+
+    let (fa, fb) = (f(a)?, f(b)?);
+    if fa.signum() == fb.signum() && fa != 0.0 && fb != 0.0
+    {
+        return Err(BracketingSolverError::NoSignChange.into());
+    }
+
+    Ok((fa, fb))
+}
+
+/// Finds a root of `f` on the interval `[a, b]` by bisection. `f(a)` and
+/// `f(b)` must have opposite signs.
+///
+/// Bisection halves the interval on every iteration, so it is slow but
+/// always converges for a continuous `f`. The returned value is within
+/// `margin` of the root, or gives `f(x)` within `margin` of `0.0`.
+///
+/// # Example
+/// ```
+/// use std::io::Error;
+/// use geqslib::roots::bisection;
+///
+/// fn f(x: f64) -> Result<f64, Error>
+/// {
+///     Ok(x * x - 2.0)
+/// }
+///
+/// let x = bisection(f, 0.0, 2.0, 0.0001, 100).unwrap();
+///
+/// assert!((x - 2f64.sqrt()).abs() < 0.0001);
+/// ```
+pub fn bisection<E>(f: impl Fn(f64) -> Result<f64, E>, a: f64, b: f64, margin: f64, limit: usize) -> anyhow::Result<f64>
+where anyhow::Error: From<E>
+{
+    let (mut fa, fb) = check_bracket(&f, a, b, margin)?;
+    if fa == 0.0 { return Ok(a) }
+    if fb == 0.0 { return Ok(b) }
+
+    let (mut a, mut b) = (a, b);
+    for _ in 0..limit
+    {
+        let mid = a + (b - a) / 2.0;
+        let f_mid = f(mid)?;
+
+        if f_mid.abs() <= margin || (b - a).abs() / 2.0 <= margin
+        {
+            return Ok(mid);
+        }
+
+        // Keep whichever half still has a sign change
+        if f_mid.signum() == fa.signum()
+        {
+            a = mid;
+            fa = f_mid;
+        }
+        else
+        {
+            b = mid;
+        }
+    }
+
+    Err(BracketingSolverError::ReachedIterationLimit.into())
+}
+
+/// Finds a root of `f` on the interval `[a, b]` using the Illinois variant
+/// of regula falsi. `f(a)` and `f(b)` must have opposite signs.
+///
+/// Each iteration steps to where the secant through the ends of the bracket
+/// crosses zero. Halving the value kept at an end that is not replaced stops
+/// that end from getting stuck, which plain regula falsi suffers from.
+/// The returned value is within `margin` of the root, or gives `f(x)`
+/// within `margin` of `0.0`.
+///
+/// # Example
+/// ```
+/// use std::io::Error;
+/// use geqslib::roots::illinois;
+///
+/// fn f(x: f64) -> Result<f64, Error>
+/// {
+///     Ok(x.cos() - x)
+/// }
+///
+/// let x = illinois(f, 0.0, 1.0, 0.0001, 100).unwrap();
+///
+/// assert!((x.cos() - x).abs() < 0.0001);
+/// ```
+pub fn illinois<E>(f: impl Fn(f64) -> Result<f64, E>, a: f64, b: f64, margin: f64, limit: usize) -> anyhow::Result<f64>
+where anyhow::Error: From<E>
+{
+    let (mut fa, mut fb) = check_bracket(&f, a, b, margin)?;
+    if fa == 0.0 { return Ok(a) }
+    if fb == 0.0 { return Ok(b) }
+
+    let (mut a, mut b) = (a, b);
+    for _ in 0..limit
+    {
+        let c = (a * fb - b * fa) / (fb - fa);
+        let fc = f(c)?;
+
+        if fc.abs() <= margin
+        {
+            return Ok(c);
+        }
+
+        if fc.signum() != fb.signum()
+        {
+            // The root is between b and c
+            a = b;
+            fa = fb;
+        }
+        else
+        {
+            // a is kept again, so shrink its value to pull the next step toward it
+            fa /= 2.0;
+        }
+        b = c;
+        fb = fc;
+
+        if (b - a).abs() <= margin
+        {
+            return Ok(b);
+        }
+    }
+
+    Err(BracketingSolverError::ReachedIterationLimit.into())
+}
+
+/// Finds a root of `f` on the interval `[a, b]` using Brent's method.
+/// `f(a)` and `f(b)` must have opposite signs.
+///
+/// Brent's method uses inverse quadratic interpolation and secant steps
+/// where they make good progress and falls back on bisection where they
+/// don't, so it converges about as reliably as bisection and usually far faster.
+/// The returned value is within `margin` of the root, or gives `f(x)`
+/// within `margin` of `0.0`.
+///
+/// # Example
+/// ```
+/// use std::io::Error;
+/// use geqslib::roots::brent;
+///
+/// fn f(x: f64) -> Result<f64, Error>
+/// {
+///     Ok(x.powi(3) - 2.0 * x - 5.0)
+/// }
+///
+/// let x = brent(f, 2.0, 3.0, 0.0001, 100).unwrap();
+///
+/// assert!((x - 2.0945515).abs() < 0.0001);
+/// ```
+pub fn brent<E>(f: impl Fn(f64) -> Result<f64, E>, a: f64, b: f64, margin: f64, limit: usize) -> anyhow::Result<f64>
+where anyhow::Error: From<E>
+{
+    let (mut fa, mut fb) = check_bracket(&f, a, b, margin)?;
+
+    // `b` is the best estimate of the root, and the root is between `b` and `c`.
+    // `a` is the previous value of `b`.
+    let (mut a, mut b) = (a, b);
+    let (mut c, mut fc) = (b, fb);
+    let mut d = b - a;
+    let mut e = d;
+
+    for _ in 0..limit
+    {
+        if fb.signum() == fc.signum() && fb != 0.0
+        {
+            c = a;
+            fc = fa;
+            d = b - a;
+            e = d;
+        }
+        if fc.abs() < fb.abs()
+        {
+            a = b;
+            b = c;
+            c = a;
+            fa = fb;
+            fb = fc;
+            fc = fa;
+        }
+
+        let tol = 2.0 * f64::EPSILON * b.abs() + 0.5 * margin;
+        let half_width = 0.5 * (c - b);
+        if fb.abs() <= margin || half_width.abs() <= tol
+        {
+            return Ok(b);
+        }
+
+        if e.abs() >= tol && fa.abs() > fb.abs()
+        {
+            // Try interpolating...
+            let s = fb / fa;
+            let (mut p, mut q) = if a == c
+            {
+                // ...linearly
+                (2.0 * half_width * s, 1.0 - s)
+            }
+            else
+            {
+                // ...with an inverse quadratic
+                let q = fa / fc;
+                let r = fb / fc;
+                (
+                    s * (2.0 * half_width * q * (q - r) - (b - a) * (r - 1.0)),
+                    (q - 1.0) * (r - 1.0) * (s - 1.0)
+                )
+            };
+            if p > 0.0
+            {
+                q = -q;
+            }
+            p = p.abs();
+
+            // ...but only accept the step if it stays in the bracket and converges quickly enough
+            if 2.0 * p < (3.0 * half_width * q - (tol * q).abs()).min((e * q).abs())
+            {
+                e = d;
+                d = p / q;
+            }
+            else
+            {
+                d = half_width;
+                e = d;
+            }
+        }
+        else
+        {
+            // Bisect
+            d = half_width;
+            e = d;
+        }
+
+        a = b;
+        fa = fb;
+        b += if d.abs() > tol { d } else { tol.copysign(half_width) };
+        fb = f(b)?;
+    }
+
+    Err(BracketingSolverError::ReachedIterationLimit.into())
+}
@@ -1,8 +1,13 @@
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
-use crate::{errors::{ShuntingYardError, ExpressionCompilationError, CompiledExpressionLookupError}, variable::Variable};
+use std::ops::Range;
+use crate::{errors::{ArityError, ParseError, ShuntingYardError, ExpressionCompilationError, CompiledExpressionLookupError}, variable::Variable};
+use crate::dual::Dual;
+pub use crate::bytecode::*;
 pub use crate::context::*;
+pub use crate::expr::*;
+pub use crate::lexer::*;
 use anyhow;
 
 use lazy_static::lazy_static;
@@ -13,14 +18,18 @@ use regex::Regex;
 /// 'Legal variables' follow Python's (and Rust's) definition of a legal variable.
 /// In other words, they must match the Regex pattern: `(?i)[a-z][a-z0-9_]*`
 /// 
+/// The exponents of numeric literals in scientific notation, such as the `e` 
+/// in `6.02e23`, are not reported as variables, and neither is the `if` keyword.
+/// 
 /// # Example
 /// ```
 /// use geqslib::shunting::get_legal_variables_iter;
 /// 
 /// let vars = Vec::from_iter(
-///     get_legal_variables_iter("x + y - snake_case_1 / CamelCase2")
+///     get_legal_variables_iter("x + y - snake_case_1 / CamelCase2 * 1e-3")
 /// );
 /// 
+/// assert_eq!(vars.len(), 4);
 /// assert!(vars.contains(&"x"));
 /// assert!(vars.contains(&"y"));
 /// assert!(vars.contains(&"snake_case_1"));
@@ -30,235 +39,376 @@ pub fn get_legal_variables_iter(text: &str) -> impl Iterator<Item = &str>
 {
     lazy_static! 
     {
-        static ref RE: Regex = Regex::new(r"(?i)[a-z][a-z0-9_]*").unwrap();
+        // Numbers are matched too so that their exponents aren't mistaken for variables
+        static ref RE: Regex = Regex::new(r"(?i)(?:[0-9]+\.?[0-9]*|\.[0-9]+)(?:e[+-]?[0-9]+)?|[a-z][a-z0-9_]*").unwrap();
     }
-    RE.find_iter(text).map(|i| i.as_str())
+    RE.find_iter(text)
+        .map(|i| i.as_str())
+        .filter(|s| s.starts_with(|c: char| c.is_ascii_alphabetic()) && *s != IF_KEYWORD)
 }
 
-const _OPERATORS_: &str = "()^*/+-";
+/// The keyword for conditional expressions, `if(condition, if_true, if_false)`.
+const IF_KEYWORD: &str = "if";
 
-/// Returns the precedence of a binary operator for a shunting yard algorithm
-fn precedence(op: &str) -> i32 
+/// Converts a lexeme to the `BinaryOp` it represents, if any.
+fn binary_op(kind: LexemeKind) -> Option<BinaryOp>
 {
-    match op 
+    match kind
     {
-        "^" => 4,
-        "/" => 3,
-        "*" => 3,
-        "-" => 2,
-        "+" => 2,
-         _  => 1,
+        LexemeKind::Caret => Some(BinaryOp::Pow),
+        LexemeKind::Slash => Some(BinaryOp::Div),
+        LexemeKind::Star  => Some(BinaryOp::Mul),
+        LexemeKind::Minus => Some(BinaryOp::Sub),
+        LexemeKind::Plus  => Some(BinaryOp::Add),
+        LexemeKind::Less         => Some(BinaryOp::Less),
+        LexemeKind::LessEqual    => Some(BinaryOp::LessEqual),
+        LexemeKind::Greater      => Some(BinaryOp::Greater),
+        LexemeKind::GreaterEqual => Some(BinaryOp::GreaterEqual),
+        LexemeKind::EqualEqual   => Some(BinaryOp::Equal),
+        LexemeKind::NotEqual     => Some(BinaryOp::NotEqual),
+        LexemeKind::AndAnd       => Some(BinaryOp::And),
+        LexemeKind::OrOr         => Some(BinaryOp::Or),
+         _  => None,
     }
 }
 
-/// Checks operator precedences for the shunting yard algorithm.
-fn prec_check(o1: &str, o2: &str) -> bool 
+/// An entry on the operator stack of the shunting yard algorithm. 
+/// Each entry holds the span of the source string it came from.
+enum StackOp
 {
-    let check1 = o2 != "(";
-    let check2 = precedence(o2) > precedence(o1);
-    let check3 = precedence(o2) == precedence(o1) && o1 != "^";
-    check1 && (check2 || check3)
+    /// A grouping parenthesis
+    Paren(Range<usize>),
+    /// The opening parenthesis of a function call. Holds the function name,
+    /// the size of the output stack when the call was opened, and the 
+    /// number of commas found in the call so far.
+    Call(String, Range<usize>, usize, usize),
+    Unary(UnaryOp, Range<usize>),
+    Binary(BinaryOp, Range<usize>),
 }
 
-/// Adds whitespace to help delimit tokens in an expression given as 
-/// a `&str`. 
-fn punctuate(expr: &str) -> String 
+/// Shorthand for creating a `ParseError` at the given span of `source`.
+fn parse_error(kind: ShuntingYardError, source: &str, span: Range<usize>) -> anyhow::Error
 {
-    let mut output = String::new();
-    for c in expr.chars() 
-    {
-        if _OPERATORS_.contains(c) || c == ','
-        {
-            output += &format!(" {c} ");
-        }
-        else 
+    ParseError::new(kind, source, span).into()
+}
+
+/// Returns the size the output stack had when the innermost open function call 
+/// was opened. Operators inside the call must not take operands from below it.
+fn call_floor(stack: &[StackOp]) -> usize
+{
+    stack.iter()
+        .rev()
+        .find_map(|op| match op
         {
-            output.push(c);
-        }
-    }
-    output.trim().to_owned()
+            StackOp::Call(_, _, start, _) => Some(*start),
+            _ => None,
+        })
+        .unwrap_or(0)
 }
 
-/// Converts a substring to a `Token` enum for use in 
-/// a postfix evaluator algorithm.
-fn tokenize(tok: &str) -> anyhow::Result<Token> 
+/// Pops the operands of an operator off of the output stack and pushes 
+/// the resulting expression back onto it. Operands below `floor` on the 
+/// output stack belong to an enclosing call and are never taken.
+fn reduce(op: StackOp, output: &mut Vec<Expr>, floor: usize, source: &str) -> anyhow::Result<()>
 {
-    let token = match tok 
+    let expr = match op
     {
-        "^" => Token::Exp,
-        "/" => Token::Div,
-        "*" => Token::Mul,
-        "-" => Token::Minus,
-        "+" => Token::Plus,
-        "," => Token::Comma,
-        "(" => Token::LeftParenthesis,
-        maybe_num => match maybe_num.parse::<f64>() 
+        StackOp::Unary(op, span) => match output.len() > floor
         {
-            Ok(num) => Token::Num(num),
-            Err(_) => return Err(ShuntingYardError::UnknownToken.into()),
-        }
+            true => Expr::Unary(op, Box::new(output.pop().unwrap())),
+            false => return Err(parse_error(ShuntingYardError::ExpectedArg, source, span)),
+        },
+        StackOp::Binary(op, span) => match output.len() >= floor + 2
+        {
+            true => {
+                let rhs = output.pop().unwrap();
+                let lhs = output.pop().unwrap();
+                Expr::Binary(op, Box::new(lhs), Box::new(rhs))
+            },
+            false => return Err(parse_error(ShuntingYardError::ExpectedArg, source, span)),
+        },
+        StackOp::Paren(span) | StackOp::Call(_, span, _, _) => {
+            return Err(parse_error(ShuntingYardError::UnclosedParenthesis, source, span))
+        },
     };
-    Ok(token)
+    output.push(expr);
+    Ok(())
 }
 
-/// Tokenizes a string, but checks `context` and
-/// creates tokens for values stored there.
-fn tokenize_with_context(tok: &str, context: &ContextHashMap) -> anyhow::Result<Token> 
-{  
-    if let Some(cnst_var_or_fn) = context.get(tok) 
+/// Pops and reduces operators until a parenthesis is found on top of the stack,
+/// without reaching past the start of the innermost open call.
+fn reduce_to_paren(stack: &mut Vec<StackOp>, output: &mut Vec<Expr>, source: &str) -> anyhow::Result<()>
+{
+    let floor = call_floor(stack);
+    while let Some(op) = stack.pop()
     {
-        let token = match cnst_var_or_fn 
+        if let StackOp::Paren(_) | StackOp::Call(..) = op
         {
-            Token::Func(args, func) => Token::Func(*args, *func),
-            Token::Var(val) => Token::Var(Rc::clone(val)),
-            Token::Num(num) => Token::Num(*num),
-            _ => return Err(ShuntingYardError::ContextMutation.into()),
-        };
-        Ok(token)
-    } 
-    else 
-    {
-        tokenize(tok)
+            stack.push(op); // put parenthesis back on stack
+            return Ok(());
+        }
+        reduce(op, output, floor, source)?;
     }
+    Ok(())
 }
 
+/// Parses a string into an `Expr`, resolving the names in it 
+/// using the given `ContextHashMap`.
+/// 
 /// See shunting yard implementation details at: 
 /// https://en.wikipedia.org/wiki/Shunting_yard_algorithm
-fn rpnify(expr: &str, context: &ContextHashMap) -> anyhow::Result<Vec<Token>> 
+/// 
+/// Errors found while parsing are returned as a `ParseError` that 
+/// points out the offending token. Calls with a number of arguments
+/// that the function's `Arity` does not allow give an `ArityError`.
+/// 
+/// # Example
+/// ```
+/// use geqslib::errors::{ParseError, ShuntingYardError};
+/// use geqslib::shunting::{parse_expr, new_context, ContextLike, Expr, BinaryOp};
+/// 
+/// let mut ctx = new_context();
+/// ctx.add_var_to_ctx("x", 1);
+/// 
+/// let expr = parse_expr("2 * x", &ctx).unwrap();
+/// 
+/// assert_eq!(expr, Expr::Binary(
+///     BinaryOp::Mul, 
+///     Box::new(Expr::Num(2.0)), 
+///     Box::new(Expr::Var("x".to_owned())),
+/// ));
+/// 
+/// let err = parse_expr("2 * (x + y)", &ctx).unwrap_err();
+/// let err = err.downcast_ref::<ParseError>().unwrap();
+/// 
+/// assert!(matches!(err.kind, ShuntingYardError::UnknownToken));
+/// assert_eq!(err.token, "y");
+/// assert_eq!(err.span, 9..10);
+/// assert_eq!(err.diagnostic, "2 * (x + y)\n         ^");
+/// ```
+pub fn parse_expr(expr: &str, context: &ContextHashMap) -> anyhow::Result<Expr> 
 {
-    let punctuated = punctuate(expr);
-    let words = punctuated.split(' ').filter(|c| !c.is_empty());
+    parse_lexemes(&lex(expr)?, expr, expr.len(), context)
+}
 
-    let mut stack: Vec<&str> = Vec::new();
-    let mut queue: Vec<Token> = Vec::new();
-    let mut unary_minus = true; // Indicator for whether the next '-' token is a unary operator
+/// Parses a slice of `lexemes` from `source` into an `Expr`. `end` is the 
+/// byte offset in `source` just past the end of the slice, which is used 
+/// to report errors about input ending unexpectedly.
+pub (in crate) fn parse_lexemes(lexemes: &[Lexeme], source: &str, end: usize, context: &ContextHashMap) -> anyhow::Result<Expr> 
+{
+    let mut stack: Vec<StackOp> = Vec::new();
+    let mut output: Vec<Expr> = Vec::new();
+    let mut expect_operand = true; // Indicator for whether the next '-' token is a unary operator
+    let mut pending_func: Option<&Lexeme> = None; // A function name waiting for its '('
 
-    for word in words 
+    for lexeme in lexemes 
     {
-        match word 
+        let span = lexeme.span.clone();
+
+        // A function name must be immediately followed by its argument list
+        if pending_func.is_some() && lexeme.kind != LexemeKind::LeftParenthesis
+        {
+            return Err(parse_error(ShuntingYardError::ExpectedArg, source, span));
+        }
+
+        match lexeme.kind 
         {
-            "," => {
-                while let Some(op) = stack.pop() 
+            LexemeKind::Comma => {
+                reduce_to_paren(&mut stack, &mut output, source)?;
+                match stack.last_mut()
                 {
-                    if op != "(" 
-                    {
-                        queue.push(tokenize_with_context(op, context)?); // ditto the comment for the previous branch
-                    } 
-                    else 
+                    Some(StackOp::Call(_, _, _, commas)) => *commas += 1,
+                    _ => return Err(parse_error(ShuntingYardError::LeftoverToken, source, span)),
+                }
+                expect_operand = true;
+            },
+
+            LexemeKind::LeftParenthesis => {
+                match pending_func.take()
+                {
+                    Some(name) => stack.push(StackOp::Call(name.text.to_owned(), name.span.start..span.end, output.len(), 0)),
+                    None => stack.push(StackOp::Paren(span)),
+                }
+                expect_operand = true;
+            },
+
+            LexemeKind::RightParenthesis => {
+                // A dangling operator or comma must not take its operand from outside 
+                // the parentheses. Only a call with no arguments may close right after its '('
+                if expect_operand
+                {
+                    match stack.last()
                     {
-                        stack.push(op); // put parenthesis back on stack
-                        break;
+                        Some(StackOp::Call(_, _, start, 0)) if *start == output.len() => (),
+                        Some(StackOp::Binary(_, op_span) | StackOp::Unary(_, op_span)) => {
+                            return Err(parse_error(ShuntingYardError::ExpectedArg, source, op_span.clone()));
+                        },
+                        _ => return Err(parse_error(ShuntingYardError::ExpectedArg, source, span)),
                     }
                 }
-                unary_minus = true;
+
+                reduce_to_paren(&mut stack, &mut output, source)?;
+                match stack.pop()
+                {
+                    Some(StackOp::Paren(_)) => (),
+                    Some(StackOp::Call(name, name_span, start, commas)) => {
+                        // Every operand was checked for above, so the arguments can be counted
+                        let mut args = output.split_off(start);
+                        if args.len() != commas + 1 && !(args.is_empty() && commas == 0)
+                        {
+                            return Err(parse_error(ShuntingYardError::ExpectedArg, source, span));
+                        }
+
+                        let call_span = name_span.start..span.end;
+                        if name == IF_KEYWORD && args.len() == 3
+                        {
+                            let if_false = args.pop().unwrap();
+                            let if_true = args.pop().unwrap();
+                            let cond = args.pop().unwrap();
+                            output.push(Expr::If(Box::new(cond), Box::new(if_true), Box::new(if_false)));
+                            expect_operand = false;
+                            continue;
+                        }
+
+                        // Any other form of `if` must be a function in the context
+                        match context.get(&name)
+                        {
+                            Some(Token::Func(arity, _, _)) if arity.accepts(args.len()) => output.push(Expr::Call(name, args)),
+                            Some(Token::Func(arity, _, _)) => {
+                                let expected = if name == IF_KEYWORD { Arity::Exactly(3) } else { *arity };
+                                return Err(ArityError::new(&name, expected, args.len(), source, call_span).into());
+                            },
+                            _ => {
+                                let name_span = name_span.start..name_span.start + name.len();
+                                return Err(parse_error(ShuntingYardError::UnknownToken, source, name_span));
+                            },
+                        }
+                    },
+                    _ => return Err(parse_error(ShuntingYardError::UnclosedParenthesis, source, span)),
+                }
+                expect_operand = false;
             },
 
-            "(" => {
-                stack.push(word);
-                unary_minus = true;
+            LexemeKind::Bang => {
+                if !expect_operand
+                {
+                    return Err(parse_error(ShuntingYardError::LeftoverToken, source, span));
+                }
+                stack.push(StackOp::Unary(UnaryOp::Not, span));
             },
 
-            ")" => {
-                while let Some(op) = stack.pop() 
+            LexemeKind::Caret | LexemeKind::Slash | LexemeKind::Star | LexemeKind::Plus | LexemeKind::Minus 
+            | LexemeKind::Less | LexemeKind::LessEqual | LexemeKind::Greater | LexemeKind::GreaterEqual 
+            | LexemeKind::EqualEqual | LexemeKind::NotEqual | LexemeKind::AndAnd | LexemeKind::OrOr => {
+                // if we find a minus and we're expecting a unary operator...
+                if expect_operand
                 {
-                    if op != "(" 
+                    if lexeme.kind != LexemeKind::Minus
                     {
-                        queue.push(tokenize_with_context(op, context)?);
-                    } 
-                    else if op == "(" 
+                        return Err(parse_error(ShuntingYardError::ExpectedArg, source, span));
+                    }
+                    stack.push(StackOp::Unary(UnaryOp::Neg, span));
+                    continue;
+                }
+
+                let o1 = binary_op(lexeme.kind).expect("operator lexemes are always binary operators");
+                let floor = call_floor(&stack);
+                while let Some(o2) = stack.pop() 
+                {
+                    let o2_prec = match &o2
                     {
-                        break;
+                        StackOp::Binary(op, _) => op.precedence(),
+                        StackOp::Unary(op, _) => op.precedence(),
+                        _ => 0,
+                    };
+
+                    let pops = o2_prec > o1.precedence()
+                        || (o2_prec == o1.precedence() && !o1.is_right_associative());
+
+                    if pops
+                    {
+                        reduce(o2, &mut output, floor, source)?;
                     } 
                     else 
                     {
-                        return Err(ShuntingYardError::UnclosedParenthesis.into())
+                        stack.push(o2); // put the prec-check-denied element back on the stack
+                        break;
                     }
                 }
-                unary_minus = false;
+                stack.push(StackOp::Binary(o1, span));
+                expect_operand = true;
             },
 
-            "^" | "/" | "*" | "+" | "-" => {
-                let o1 = word;
-
-                // if we find a minus and we're expecting a unary operator...
-                if unary_minus && o1 == "-" 
-                { 
-                    queue.push(Token::Num(-1.0));
-                    stack.push("*");
-                    unary_minus = true;
-                } 
-                else 
+            LexemeKind::Num(num) => {
+                if !expect_operand
                 {
-                    while let Some(o2) = stack.pop() 
-                    {
-                        if prec_check(o1, o2) 
-                        {
-                            queue.push(tokenize_with_context(o2, context)?);
-                        } 
-                        else 
-                        {
-                            stack.push(o2); // put the prec-check-denied element back on the stack
-                            break;
-                        }
-                    }
-                    stack.push(word);
-                    unary_minus = true;
+                    return Err(parse_error(ShuntingYardError::LeftoverToken, source, span));
                 }
+                output.push(Expr::Num(num));
+                expect_operand = false;
             },
 
-            other => {
-
-                if let Ok(num) = other.parse::<f64>() 
+            LexemeKind::Ident => {
+                if !expect_operand
                 {
-                    queue.push(Token::Num(num));
-                    unary_minus = false;
-                } 
-                
-                else if context.contains_key(other) 
+                    return Err(parse_error(ShuntingYardError::LeftoverToken, source, span));
+                }
+
+                let name = lexeme.text;
+                if name == IF_KEYWORD
                 {
-                    match &context[other] 
-                    {
-                        Token::Num(val) => {
-                            queue.push(Token::Num(*val));
-                            unary_minus = false;
-                        },
-                        Token::Var(val) => {
-                            queue.push(Token::Var(Rc::clone(val)));
-                            unary_minus = false;
-                        }
-                        Token::Func(_, _) => {
-                            stack.push(word);
-                            unary_minus = true;
-                        },
-                        _ => return Err(ShuntingYardError::ContextMutation.into())
-                    }
+                    pending_func = Some(lexeme);
+                    continue;
                 }
-                
-                else {
-                    return Err(ShuntingYardError::UnknownToken.into())
+
+                match context.get(name)
+                {
+                    Some(Token::Num(val)) => output.push(Expr::Const(name.to_owned(), *val)),
+                    Some(Token::Var(_)) => output.push(Expr::Var(name.to_owned())),
+                    Some(Token::Func(..)) => {
+                        pending_func = Some(lexeme);
+                        continue;
+                    },
+                    Some(_) => return Err(parse_error(ShuntingYardError::ContextMutation, source, span)),
+                    None => return Err(parse_error(ShuntingYardError::UnknownToken, source, span)),
                 }
+                expect_operand = false;
             },
+
+            LexemeKind::Equals => return Err(parse_error(ShuntingYardError::UnknownToken, source, span)),
         }   
     }
+
+    if pending_func.is_some()
+    {
+        return Err(parse_error(ShuntingYardError::ExpectedArg, source, end..end));
+    }
+
+    // Catch a trailing operator before it steals an operand from the one before it
+    if let (true, Some(StackOp::Binary(_, span) | StackOp::Unary(_, span))) = (expect_operand, stack.last())
+    {
+        return Err(parse_error(ShuntingYardError::ExpectedArg, source, span.clone()));
+    }
     
-    while let Some(tok) = stack.pop() 
+    while let Some(op) = stack.pop() 
     {
-        if "()".contains(tok) 
-        {
-            return Err(ShuntingYardError::LeftoverToken.into())
-        } 
-        queue.push(tokenize_with_context(tok, context)?);
+        reduce(op, &mut output, 0, source)?;
     }
 
-    Ok(queue)
+    match (output.pop(), output.is_empty())
+    {
+        (Some(expr), true) => Ok(expr),
+        (None, _) => Err(parse_error(ShuntingYardError::NoTokens, source, end..end)),
+        (Some(_), false) => Err(parse_error(ShuntingYardError::LeftoverToken, source, end..end)),
+    }
 }
 
 /// 'Compiles' a `&str` expression to a function that takes a hashmap as an argument.
 /// 
-/// Under the hood, this rearranges the string expression to a token stack **once** 
+/// Under the hood, this parses the string expression to an `Expr` **once** 
 /// prior to being moved to the returned closure value. The variables in the expression 
 /// are added to a `HashMap` that allows the function to quickly find and mutate the 
-/// values in the token stack to reduce the number of steps performed when the 
+/// values read by the `Expr` to reduce the number of steps performed when the 
 /// closure is called. 
 /// 
 /// In order for this process to work, mutable references are made to the contents of all
@@ -300,32 +450,90 @@ pub fn compile_to_fn_of_hashmap(expr: &str, context: &ContextHashMap) -> anyhow:
         }
     }
 
-    let rpn = rpnify(expr, context)?;
+    Ok(compile_expr_to_fn_of_hashmap(parse_expr(expr, context)?, context))
+}
 
+/// Identical to `compile_to_fn_of_hashmap`, but takes an already-parsed `Expr`.
+/// 
+/// # Example
+/// ```
+/// use std::collections::HashMap;
+/// use geqslib::shunting::{compile_expr_to_fn_of_hashmap, parse_expr, new_context, ContextLike};
+/// 
+/// let mut my_hm = new_context();
+/// my_hm.add_var_to_ctx("x", 4);
+/// 
+/// // differentiate the expression before compiling it
+/// let d_dx = parse_expr("x^2", &my_hm).unwrap()
+///     .derivative("x")
+///     .unwrap();
+/// let my_fn = compile_expr_to_fn_of_hashmap(d_dx, &my_hm);
+/// 
+/// let my_input = HashMap::from([("x".to_string(), 3.0)]);
+/// 
+/// assert_eq!(my_fn(&my_input).unwrap(), 6.0);
+/// ```
+pub fn compile_expr_to_fn_of_hashmap(expr: Expr, context: &ContextHashMap) -> impl Fn(&HashMap<String, f64>) -> anyhow::Result<f64>
+{
     // Clone the Rc's to a lookup table for closure function
-    let arg_lookup_table = context.clone();
+    compile_expr_to_fn_of_shared_context(expr, Rc::new(context.clone()))
+}
+
+/// Identical to `compile_expr_to_fn_of_hashmap`, but looks up names in a context 
+/// shared with other compiled functions instead of in a copy of its own.
+pub (in crate) fn compile_expr_to_fn_of_shared_context(expr: Expr, arg_lookup_table: Rc<ContextHashMap>) -> impl Fn(&HashMap<String, f64>) -> anyhow::Result<f64>
+{
+    move |x: &HashMap<String, f64>| {
+        set_vars_in_ctx(&arg_lookup_table, x)?;
+        expr.eval(&arg_lookup_table)
+    }
+}
 
-    Ok(move |x: &HashMap<String, f64>| {
-        for (var, value) in x 
+/// Sets the values of the variables in a compiled function's lookup table.
+fn set_vars_in_ctx(arg_lookup_table: &ContextHashMap, x: &HashMap<String, f64>) -> anyhow::Result<()>
+{
+    for (var, value) in x 
+    {
+        match arg_lookup_table.get(var)
         {
-            match arg_lookup_table.get(var)
-            {
-                Some(Token::Var(r)) => (*r.borrow_mut()).set(*value),
-                _ => return Err(CompiledExpressionLookupError.into()),
-            }
+            Some(Token::Var(r)) => (*r.borrow_mut()).set(*value),
+            _ => return Err(CompiledExpressionLookupError.into()),
         }
-        eval_rpn_expression(&rpn)
-    })
+    }
+    Ok(())
 }
 
+/// 'Compiles' a `&str` expression to `Bytecode` for fast repeated evaluation. 
+/// The value of each variable in `slots` is read from the same position in 
+/// the slice given to `Bytecode::eval`.
+/// 
+/// Unlike the closures from `compile_to_fn_of_hashmap`, evaluating `Bytecode` 
+/// does not look up variables by name or allocate.
+/// 
+/// # Example
+/// ```
+/// use geqslib::shunting::{compile_to_bytecode, new_context, ContextLike};
+/// 
+/// let mut ctx = new_context();
+/// ctx.add_var_to_ctx("x", 4);
+/// ctx.add_var_to_ctx("y", 2);
+/// 
+/// let mut my_fn = compile_to_bytecode("x + 4 * y", &ctx, &["x", "y"]).unwrap();
+/// 
+/// assert_eq!(my_fn.eval(&[8.0, 0.5]).unwrap(), 10.0);
+/// ```
+pub fn compile_to_bytecode(expr: &str, context: &ContextHashMap, slots: &[&str]) -> anyhow::Result<Bytecode>
+{
+    Bytecode::compile(&parse_expr(expr, context)?, context, slots)
+}
 
 /// Similar to `compile_to_fn_of_hashmap`, but produces a function that takes only 
 /// a single argument to mutate a single variable in the `&str` expression.
 /// 
-/// Under the hood, this rearranges the string expression to a token stack **once** 
+/// Under the hood, this parses the string expression to an `Expr` **once** 
 /// prior to being moved to the returned closure value. The variables in the expression 
 /// are added to a `HashMap` that allows the function to quickly find and mutate the 
-/// values in the token stack to reduce the number of steps performed when the 
+/// values read by the `Expr` to reduce the number of steps performed when the 
 /// closure is called. 
 /// 
 /// In order for this process to work, mutable references are made to the contents of all
@@ -360,6 +568,25 @@ pub fn compile_to_fn(expr: &str, context: &ContextHashMap) -> anyhow::Result<imp
         }
     }
 
+    compile_expr_to_fn(parse_expr(expr, context)?, context)
+}
+
+/// Identical to `compile_to_fn`, but takes an already-parsed `Expr`.
+/// 
+/// # Example
+/// ```
+/// use geqslib::shunting::{compile_expr_to_fn, parse_expr, new_context, ContextLike};
+/// 
+/// let mut my_hm = new_context();
+/// my_hm.add_var_to_ctx("x", 4);
+/// 
+/// let my_expr = parse_expr("x + 4", &my_hm).unwrap();
+/// let my_fn = compile_expr_to_fn(my_expr, &my_hm).unwrap();
+/// 
+/// assert_eq!(my_fn(8.0).unwrap(), 12.0);
+/// ```
+pub fn compile_expr_to_fn(expr: Expr, context: &ContextHashMap) -> anyhow::Result<impl Fn(f64) -> anyhow::Result<f64>> 
+{
     let is_var = |x: &(&String, &Token)| matches!(x.1, Token::Var(_));
 
     // Ensure that there is only one given variable to track
@@ -373,11 +600,11 @@ pub fn compile_to_fn(expr: &str, context: &ContextHashMap) -> anyhow::Result<imp
     if let Token::Var(r) = present_vars.first().unwrap().1
     {
         let var: Rc<RefCell<Variable>> = Rc::clone(r);
-        let rpn = rpnify(expr, context)?;
+        let lookup_table = context.clone();
     
         Ok(move |x: f64| {
             (*var.borrow_mut()).set(x);
-            eval_rpn_expression(&rpn)
+            expr.eval(&lookup_table)
         })
     }
     else 
@@ -386,111 +613,89 @@ pub fn compile_to_fn(expr: &str, context: &ContextHashMap) -> anyhow::Result<imp
     }
 }
 
-/// Evaluates a postfix token stack, returning an f64 value on success.
-fn eval_rpn_expression(expr: &Vec<Token>) -> anyhow::Result<f64> 
-{    
-    let mut stack: Vec<f64> = Vec::new();
-    
-    for token in expr 
-    {
-        match token 
-        {
-
-            Token::Num(num) => stack.push(*num),
-            
-            Token::Var(val) => stack.push((*val.borrow()).into()),
-
-            Token::Func(args, func) => {
-
-                let mut arguments: Vec<f64> = Vec::new();
-                for _ in 0..*args 
-                {
-                    if let Some(num) = stack.pop() 
-                    {
-                        arguments.push(num);
-                    } 
-                    else 
-                    {
-                        return Err(ShuntingYardError::ExpectedArg.into())
-                    }
-                }
-                stack.push(
-                    func(&arguments)
-                );
-            },
-
-            Token::Exp => {
-                if let (Some(arg2), Some(arg1)) = (stack.pop(), stack.pop()) 
-                {
-                    stack.push(arg1.powf(arg2));
-                } 
-                else 
-                {
-                    return Err(ShuntingYardError::ExpectedArg.into());
-                }
-            },
+/// Similar to `compile_to_fn`, but produces a function that evaluates the expression with 
+/// dual numbers, returning both its value and its exact derivative with respect to the 
+/// single variable in the context.
+/// 
+/// # Example
+/// ```
+/// use geqslib::shunting::{compile_to_dual_fn, new_context, ContextLike};
+/// 
+/// let mut my_hm = new_context();
+/// my_hm.add_var_to_ctx("x", 1);
+/// 
+/// let my_fn = compile_to_dual_fn("x * sin(x)", &my_hm).unwrap();
+/// let res = my_fn(2.0).unwrap();
+/// 
+/// assert_eq!(res.value, 2.0 * 2f64.sin());
+/// assert_eq!(res.deriv, 2f64.sin() + 2.0 * 2f64.cos());
+/// ```
+pub fn compile_to_dual_fn(expr: &str, context: &ContextHashMap) -> anyhow::Result<impl Fn(f64) -> anyhow::Result<Dual>> 
+{
+    compile_expr_to_dual_fn(parse_expr(expr, context)?, context)
+}
 
-            Token::Div => {
-                if let (Some(arg2), Some(arg1)) = (stack.pop(), stack.pop()) 
-                {
-                    if arg2 == 0.0 
-                    { 
-                        return Err(ShuntingYardError::DivisionByZero.into()) 
-                    }
-                    stack.push(arg1 / arg2);
-                } 
-                else 
-                {
-                    return Err(ShuntingYardError::ExpectedArg.into());
-                }
-            },
+/// Identical to `compile_to_dual_fn`, but takes an already-parsed `Expr`.
+pub fn compile_expr_to_dual_fn(expr: Expr, context: &ContextHashMap) -> anyhow::Result<impl Fn(f64) -> anyhow::Result<Dual>> 
+{
+    let is_var = |x: &(&String, &Token)| matches!(x.1, Token::Var(_));
 
-            Token::Mul => {
-                if let (Some(arg2), Some(arg1)) = (stack.pop(), stack.pop()) 
-                {
-                    stack.push(arg1 * arg2);
-                } 
-                else 
-                {
-                    return Err(ShuntingYardError::ExpectedArg.into());
-                }
-            },
+    // Ensure that there is only one given variable to track
+    let present_vars = Vec::from_iter(context.iter().filter(is_var));
+    if present_vars.len() != 1
+    {
+        return Err(ExpressionCompilationError::WrongVarCount.into());
+    }
 
-            Token::Minus => {
-                if let (Some(arg2), Some(arg1)) = (stack.pop(), stack.pop()) 
-                {
-                    stack.push(arg1 - arg2);
-                } 
-                else 
-                {
-                    return Err(ShuntingYardError::ExpectedArg.into());
-                }
-            },
+    let (name, var) = match present_vars[0]
+    {
+        (name, Token::Var(r)) => (name.clone(), Rc::clone(r)),
+        _ => return Err(ExpressionCompilationError::NoVarsFound.into()),
+    };
+    let lookup_table = context.clone();
 
-            Token::Plus => {
-                if let (Some(arg2), Some(arg1)) = (stack.pop(), stack.pop()) 
-                {
-                    stack.push(arg1 + arg2);
-                } 
-                else 
-                {
-                    return Err(ShuntingYardError::ExpectedArg.into());
-                }
-            },
+    Ok(move |x: f64| {
+        (*var.borrow_mut()).set(x);
+        expr.eval_dual(&lookup_table, &name)
+    })
+}
 
-            _ => {
-                return Err(ShuntingYardError::LeftoverToken.into())
-            },
-        }
-    
-    }
+/// Similar to `compile_expr_to_fn_of_hashmap`, but produces a function that evaluates 
+/// the expression with dual numbers, returning both its value and its exact partial
+/// derivative with respect to the named variable given on each call.
+/// 
+/// # Example
+/// ```
+/// use std::collections::HashMap;
+/// use geqslib::shunting::{compile_expr_to_dual_fn_of_hashmap, parse_expr, new_context, ContextLike};
+/// 
+/// let mut my_hm = new_context();
+/// my_hm.add_var_to_ctx("x", 1);
+/// my_hm.add_var_to_ctx("y", 1);
+/// 
+/// let my_expr = parse_expr("x * y^2", &my_hm).unwrap();
+/// let my_fn = compile_expr_to_dual_fn_of_hashmap(my_expr, &my_hm);
+/// 
+/// let my_input = HashMap::from([
+///     ("x".to_string(), 3.0),
+///     ("y".to_string(), 2.0),
+/// ]);
+/// 
+/// assert_eq!(my_fn(&my_input, "x").unwrap().deriv, 4.0);
+/// assert_eq!(my_fn(&my_input, "y").unwrap().deriv, 12.0);
+/// ```
+pub fn compile_expr_to_dual_fn_of_hashmap(expr: Expr, context: &ContextHashMap) -> impl Fn(&HashMap<String, f64>, &str) -> anyhow::Result<Dual>
+{
+    compile_expr_to_dual_fn_of_shared_context(expr, Rc::new(context.clone()))
+}
 
-    match stack.len() {
-        1 => Ok(stack[0]),
-        0 => Err(ShuntingYardError::NoTokens.into()),
-        _ => {
-            Err(ShuntingYardError::LeftoverToken.into())
-        }
+/// Identical to `compile_expr_to_dual_fn_of_hashmap`, but looks up names in a 
+/// context shared with other compiled functions instead of in a copy of its own.
+pub (in crate) fn compile_expr_to_dual_fn_of_shared_context(expr: Expr, arg_lookup_table: Rc<ContextHashMap>) -> impl Fn(&HashMap<String, f64>, &str) -> anyhow::Result<Dual>
+{
+    move |x: &HashMap<String, f64>, var: &str| {
+        set_vars_in_ctx(&arg_lookup_table, x)?;
+        expr.eval_dual(&arg_lookup_table, var)
     }
 }
 
@@ -508,7 +713,7 @@ fn eval_rpn_expression(expr: &Vec<Token>) -> anyhow::Result<f64>
 /// ```
 pub fn eval_str(expr: &str) -> anyhow::Result<f64> 
 {
-    eval_rpn_expression(&rpnify(expr, &new_context())?)
+    eval_str_with_context(expr, &new_context())
 }
 
 /// Evaluates a string as a mathematical expression using functions,
@@ -529,37 +734,167 @@ pub fn eval_str(expr: &str) -> anyhow::Result<f64>
 /// ```
 pub fn eval_str_with_context(expr: &str, context: &ContextHashMap) -> anyhow::Result<f64> 
 {
-    eval_rpn_expression(&rpnify(expr, context)?)
+    parse_expr(expr, context)?.eval(context)
 }
 
 #[test]
-fn test_punctuate() 
+fn test_lex() 
 {
-    let my_expr = "3+4";
-    let punctuated = punctuate(my_expr);
-    assert_eq!(punctuated, "3 + 4");
-
-    let tokens = Vec::from_iter(punctuated.split(' '));
+    let lexemes = lex("3+4").unwrap();
+    let texts = Vec::from_iter(lexemes.iter().map(|l| l.text));
     assert_eq!(
-        tokens,
+        texts,
         vec!["3", "+", "4"]
     )
 }
 
+#[test]
+fn test_lex_scientific_notation() 
+{
+    let texts = |s| Vec::from_iter(lex(s).unwrap().into_iter().map(|l| l.text));
+    assert_eq!(texts("1e-3+x"), vec!["1e-3", "+", "x"]);
+    assert_eq!(texts("6.02E+23*.5e2"), vec!["6.02E+23", "*", ".5e2"]);
+    assert_eq!(texts("xe-3"), vec!["xe", "-", "3"]);
+    assert_eq!(texts("2e-x"), vec!["2", "e", "-", "x"]);
+}
+
+#[test]
+fn test_parse_errors_point_at_offending_token()
+{
+    let ctx = new_context();
+    let span_of = |s| parse_expr(s, &ctx).unwrap_err().downcast::<ParseError>().unwrap().span;
+
+    assert_eq!(span_of("sin(1 + 2"), 0..4);
+    assert_eq!(span_of("1 + 2)"), 5..6);
+    assert_eq!(span_of("1 + # 2"), 4..5);
+    assert_eq!(span_of("1 + 2 *"), 6..7);
+    assert_eq!(span_of("1 2"), 2..3);
+    assert_eq!(span_of("(1 + )"), 3..4);
+    assert_eq!(span_of("()"), 1..2);
+    assert_eq!(span_of("sin + 1"), 4..5);
+    assert_eq!(span_of(""), 0..0);
+
+    let err = parse_expr("pi * (2 + tau)", &ctx).unwrap_err();
+    assert_eq!(
+        err.to_string(), 
+        "found an unexpected token while converting expression to reverse polish notation at 'tau' (bytes 10..13)\n\
+        pi * (2 + tau)\n          ^^^"
+    );
+}
+
+#[test]
+fn test_dangling_operators_stay_inside_calls()
+{
+    let mut ctx = new_context();
+    ctx.add_var_to_ctx("x", 3);
+    ctx.add_func_to_ctx("f", |_| 10.0, 0);
+
+    // the '*' used to take '2 * x' from outside the call, giving 2 * x * f()
+    for text in ["2 * f(x *)", "2 * sin(x *)"]
+    {
+        let err = parse_expr(text, &ctx).unwrap_err().downcast::<ParseError>().unwrap();
+        assert!(matches!(err.kind, ShuntingYardError::ExpectedArg));
+        assert_eq!(err.token, "*");
+    }
+    assert_eq!(eval_str_with_context("2 * f()", &ctx).unwrap(), 20.0);
+}
+
+#[test]
+fn test_arity_is_checked_while_parsing()
+{
+    let ctx = new_context();
+    let arity_error = |s| parse_expr(s, &ctx).unwrap_err().downcast::<ArityError>().unwrap();
+
+    let err = arity_error("1 + sin(1, 2)");
+    assert_eq!(err.func, "sin");
+    assert_eq!(err.expected, Arity::Exactly(1));
+    assert_eq!(err.found, 2);
+    assert_eq!(err.span, 4..13);
+    assert_eq!(
+        err.to_string(), 
+        "function 'sin' expects 1 argument but was called with 2 (bytes 4..13)\n\
+        1 + sin(1, 2)\n    ^^^^^^^^^"
+    );
+
+    assert_eq!(arity_error("log(3)").expected, Arity::Exactly(2));
+    assert_eq!(arity_error("max()").expected, Arity::AtLeast(1));
+    assert_eq!(arity_error("if(1, 2)").expected, Arity::Exactly(3));
+    assert!(parse_expr("max(1, 2, 3) + if(1, 2, 3) + if(1, 1, 1, 2, 3)", &ctx).is_ok());
+
+    // one argument was written, so the dangling operator is the error and not the arity
+    let err = parse_expr("2 * sin(1 *)", &ctx).unwrap_err();
+    assert!(err.downcast_ref::<ArityError>().is_none());
+    let err = err.downcast::<ParseError>().unwrap();
+    assert!(matches!(err.kind, ShuntingYardError::ExpectedArg));
+    assert_eq!(err.span, 10..11);
+}
+
 // Unit tests for private module functions:
 #[test]
-fn test_rpnify() 
+fn test_parse_expr() 
 {
     let ctx: ContextHashMap = HashMap::new();
-    let rpn = rpnify("3+4", &ctx).unwrap();
-    assert_eq!(rpn, vec![Token::Num(3.0), Token::Num(4.0), Token::Plus])
+    let expr = parse_expr("3+4", &ctx).unwrap();
+    assert_eq!(expr, Expr::Binary(BinaryOp::Add, Box::new(Expr::Num(3.0)), Box::new(Expr::Num(4.0))))
 }
 
 #[test]
 fn test_unary_minus() 
 {
     let ctx: ContextHashMap = new_context();
-    let rpn = rpnify("sin(-1 + 2 + 2 + 0.14)", &ctx).unwrap();
+    let expr = parse_expr("sin(-1 + 2 + 2 + 0.14)", &ctx).unwrap();
 
-    assert_eq!(rpn[0], Token::Num(-1.0));
-}
\ No newline at end of file
+    let mut first_leaf = &expr;
+    while let Some(child) = first_leaf.children().first()
+    {
+        first_leaf = child;
+    }
+    assert_eq!(*first_leaf, Expr::Num(1.0));
+
+    let mut negations = 0;
+    expr.walk(&mut |e| if let Expr::Unary(UnaryOp::Neg, _) = e { negations += 1 });
+    assert_eq!(negations, 1);
+}
+
+#[test]
+fn test_function_call_binds_to_its_arguments()
+{
+    let ctx: ContextHashMap = new_context();
+    let expr = parse_expr("sin(0) + 1", &ctx).unwrap();
+
+    assert!(matches!(expr, Expr::Binary(BinaryOp::Add, _, _)));
+    assert_eq!(expr.eval(&ctx).unwrap(), 1.0);
+}
+
+#[test]
+fn test_display_round_trips()
+{
+    let mut ctx: ContextHashMap = new_context();
+    ctx.add_var_to_ctx("x", 1.5);
+    ctx.add_var_to_ctx("y", -2);
+
+    for text in ["-x^2", "(x - y) * -(x * y)", "x - (y - 1)", "2^3^2", "(2^3)^2", "log(x, 2 + y) / (x / y)", 
+        "!(x < y) && -x != y", "(x || y) && x >= 1 == 1", "if(x > y, x, y) * 2"]
+    {
+        let expr = parse_expr(text, &ctx).unwrap();
+        let reparsed = parse_expr(&expr.to_string(), &ctx).unwrap();
+        assert_eq!(expr, reparsed);
+        assert_eq!(expr.eval(&ctx).unwrap(), eval_str_with_context(text, &ctx).unwrap());
+    }
+}
+
+#[test]
+fn test_logical_operator_precedence()
+{
+    let ctx: ContextHashMap = new_context();
+
+    assert_eq!(eval_str_with_context("1 + 1 == 2", &ctx).unwrap(), 1.0);
+    assert_eq!(eval_str_with_context("1 < 2 == 2 < 1", &ctx).unwrap(), 0.0);
+    assert_eq!(eval_str_with_context("0 && 1 || 1", &ctx).unwrap(), 1.0);
+    assert_eq!(eval_str_with_context("1 || 1 && 0", &ctx).unwrap(), 1.0);
+    assert_eq!(eval_str_with_context("!0 + 1", &ctx).unwrap(), 2.0);
+    assert_eq!(eval_str_with_context("2 >= 2 && 3 != 4 && !(5 <= 4)", &ctx).unwrap(), 1.0);
+
+    assert!(lex("1 & 2").is_err());
+    assert!(parse_expr("1 !", &ctx).is_err());
+}
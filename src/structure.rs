@@ -0,0 +1,287 @@
+/// Finds a maximum matching of equations to variables, where `incidence[i]` lists
+/// the indices of the variables that appear in equation `i`. The returned vector
+/// gives the variable matched to each equation, if any.
+///
+/// Each equation is matched by searching for an augmenting path through the
+/// equations that have already been matched (Kuhn's algorithm).
+pub (in crate) fn maximum_matching(incidence: &[Vec<usize>], var_count: usize) -> Vec<Option<usize>>
+{
+    let mut eqn_of_var: Vec<Option<usize>> = vec![None; var_count];
+    for eqn in 0..incidence.len()
+    {
+        let mut visited = vec![false; var_count];
+        augment(eqn, incidence, &mut eqn_of_var, &mut visited);
+    }
+
+    let mut var_of_eqn = vec![None; incidence.len()];
+    for (var, eqn) in eqn_of_var.iter().enumerate()
+    {
+        if let Some(eqn) = *eqn
+        {
+            var_of_eqn[eqn] = Some(var);
+        }
+    }
+    var_of_eqn
+}
+
+/// Tries to match `eqn` to a variable, re-matching the equations in its way.
+/// Returns whether a match was found.
+/// 
+/// The search is depth-first, with the path kept on a stack rather than in 
+/// recursive calls so that long chains of equations cannot overflow the call stack.
+fn augment(eqn: usize, incidence: &[Vec<usize>], eqn_of_var: &mut [Option<usize>], visited: &mut [bool]) -> bool
+{
+    // Each equation on the path, with the position of the next variable to try in it
+    let mut path = vec![(eqn, 0)];
+    // The variable that each equation on the path would take from the next one
+    let mut taking = vec![];
+
+    while let Some((eqn, next)) = path.last_mut()
+    {
+        let var = match incidence[*eqn].get(*next)
+        {
+            Some(&var) => var,
+            None => {
+                path.pop();
+                taking.pop();
+                continue;
+            },
+        };
+        *next += 1;
+
+        if visited[var]
+        {
+            continue;
+        }
+        visited[var] = true;
+        taking.push(var);
+
+        match eqn_of_var[var]
+        {
+            Some(other) => path.push((other, 0)),
+            None => {
+                // var is free, so every equation on the path takes the variable it wanted
+                for (&(eqn, _), &var) in path.iter().zip(&taking)
+                {
+                    eqn_of_var[var] = Some(eqn);
+                }
+                return true;
+            },
+        }
+    }
+    false
+}
+
+/// The coarse Dulmage-Mendelsohn decomposition of a set of equations, by index.
+pub (in crate) struct DulmageMendelsohn
+{
+    /// Variables left unmatched by the maximum matching
+    pub unmatched_vars: Vec<usize>,
+    /// Equations left unmatched by the maximum matching
+    pub unmatched_equations: Vec<usize>,
+    /// Variables that some maximum matching leaves unmatched
+    pub under_vars: Vec<usize>,
+    /// Equations that every maximum matching matches to one of `under_vars`
+    pub under_equations: Vec<usize>,
+    /// Equations that some maximum matching leaves unmatched
+    pub over_equations: Vec<usize>,
+    /// Variables that every maximum matching matches to one of `over_equations`
+    pub over_vars: Vec<usize>,
+}
+
+/// Finds the under- and over-constrained parts of a set of equations. Starting
+/// from a maximum matching, the under-constrained part is everything reachable 
+/// from an unmatched variable by alternately following an equation it appears 
+/// in and the variable matched to that equation. The over-constrained part is 
+/// found the same way from the unmatched equations.
+pub (in crate) fn dulmage_mendelsohn(incidence: &[Vec<usize>], var_count: usize) -> DulmageMendelsohn
+{
+    let var_of_eqn = maximum_matching(incidence, var_count);
+    let mut eqn_of_var = vec![None; var_count];
+    let mut appears_in = vec![vec![]; var_count];
+    for (eqn, vars) in incidence.iter().enumerate()
+    {
+        if let Some(var) = var_of_eqn[eqn]
+        {
+            eqn_of_var[var] = Some(eqn);
+        }
+        for &var in vars
+        {
+            appears_in[var].push(eqn);
+        }
+    }
+
+    let unmatched_vars: Vec<usize> = (0..var_count).filter(|&var| eqn_of_var[var].is_none()).collect();
+    let unmatched_equations: Vec<usize> = (0..incidence.len()).filter(|&eqn| var_of_eqn[eqn].is_none()).collect();
+
+    // var -> equation it appears in -> that equation's variable
+    let mut under = vec![false; var_count];
+    let mut stack = unmatched_vars.clone();
+    while let Some(var) = stack.pop()
+    {
+        if std::mem::replace(&mut under[var], true)
+        {
+            continue;
+        }
+        stack.extend(appears_in[var].iter().filter_map(|&eqn| var_of_eqn[eqn]));
+    }
+
+    // equation -> variable in it -> that variable's equation
+    let mut over = vec![false; incidence.len()];
+    let mut stack = unmatched_equations.clone();
+    while let Some(eqn) = stack.pop()
+    {
+        if std::mem::replace(&mut over[eqn], true)
+        {
+            continue;
+        }
+        stack.extend(incidence[eqn].iter().filter_map(|&var| eqn_of_var[var]));
+    }
+
+    let under_vars: Vec<usize> = (0..var_count).filter(|&var| under[var]).collect();
+    let over_equations: Vec<usize> = (0..incidence.len()).filter(|&eqn| over[eqn]).collect();
+    let mut under_equations: Vec<usize> = under_vars.iter().filter_map(|&var| eqn_of_var[var]).collect();
+    under_equations.sort();
+    let mut over_vars: Vec<usize> = over_equations.iter().filter_map(|&eqn| var_of_eqn[eqn]).collect();
+    over_vars.sort();
+
+    DulmageMendelsohn { unmatched_vars, unmatched_equations, under_vars, under_equations, over_equations, over_vars }
+}
+
+/// Splits a system into blocks of equations that must be solved simultaneously,
+/// given the variable matched to every equation. An equation depends on each
+/// equation matched to a variable that appears in it, and the strongly connected
+/// components of those dependencies (found by Tarjan's algorithm) are the blocks.
+///
+/// Tarjan's algorithm finishes a component only after every component it depends
+/// on, so the blocks are returned in the order they can be solved in.
+pub (in crate) fn block_triangularize(incidence: &[Vec<usize>], matching: &[usize]) -> Vec<Vec<usize>>
+{
+    let mut eqn_of_var = vec![usize::MAX; matching.len()];
+    for (eqn, &var) in matching.iter().enumerate()
+    {
+        eqn_of_var[var] = eqn;
+    }
+    let dependencies: Vec<Vec<usize>> = incidence.iter()
+        .map(|vars| vars.iter().map(|&var| eqn_of_var[var]).collect())
+        .collect();
+
+    let mut tarjan = Tarjan
+    {
+        dependencies: &dependencies,
+        index: vec![None; incidence.len()],
+        lowlink: vec![0; incidence.len()],
+        on_stack: vec![false; incidence.len()],
+        stack: vec![],
+        next_index: 0,
+        blocks: vec![],
+    };
+    for eqn in 0..incidence.len()
+    {
+        if tarjan.index[eqn].is_none()
+        {
+            tarjan.visit(eqn);
+        }
+    }
+    tarjan.blocks
+}
+
+/// The state of Tarjan's strongly connected components algorithm.
+struct Tarjan<'a>
+{
+    dependencies: &'a [Vec<usize>],
+    index: Vec<Option<usize>>,
+    lowlink: Vec<usize>,
+    on_stack: Vec<bool>,
+    stack: Vec<usize>,
+    next_index: usize,
+    blocks: Vec<Vec<usize>>,
+}
+
+impl Tarjan<'_>
+{
+    /// Visits every equation reachable from `root` that has not been visited yet. The 
+    /// depth-first search is kept on a stack rather than in recursive calls so that 
+    /// long chains of equations cannot overflow the call stack.
+    fn visit(&mut self, root: usize)
+    {
+        // Each equation being visited, with the position of the next dependency to follow
+        let mut frames = vec![(root, 0)];
+        self.open(root);
+
+        while let Some((eqn, next)) = frames.last_mut()
+        {
+            let eqn = *eqn;
+            if let Some(&dependency) = self.dependencies[eqn].get(*next)
+            {
+                *next += 1;
+                match self.index[dependency]
+                {
+                    None => {
+                        self.open(dependency);
+                        frames.push((dependency, 0));
+                    },
+                    Some(index) if self.on_stack[dependency] => {
+                        self.lowlink[eqn] = self.lowlink[eqn].min(index);
+                    },
+                    Some(_) => (),
+                }
+                continue;
+            }
+
+            frames.pop();
+            if let Some(&(parent, _)) = frames.last()
+            {
+                self.lowlink[parent] = self.lowlink[parent].min(self.lowlink[eqn]);
+            }
+
+            // eqn is the root of a component once nothing on the stack above it reaches further down
+            if Some(self.lowlink[eqn]) == self.index[eqn]
+            {
+                let mut block = vec![];
+                while let Some(member) = self.stack.pop()
+                {
+                    self.on_stack[member] = false;
+                    block.push(member);
+                    if member == eqn
+                    {
+                        break;
+                    }
+                }
+                block.sort();
+                self.blocks.push(block);
+            }
+        }
+    }
+
+    /// Gives `eqn` the next index and puts it on the stack.
+    fn open(&mut self, eqn: usize)
+    {
+        self.index[eqn] = Some(self.next_index);
+        self.lowlink[eqn] = self.next_index;
+        self.next_index += 1;
+        self.stack.push(eqn);
+        self.on_stack[eqn] = true;
+    }
+}
+
+#[test]
+fn test_long_chains_do_not_overflow_the_stack()
+{
+    // equation i holds variables i and i + 1, so each depends on the next
+    let n = 200_000;
+    let mut incidence: Vec<Vec<usize>> = (0..n - 1).map(|i| vec![i, i + 1]).collect();
+    incidence.push(vec![n - 1]);
+
+    let matching: Vec<usize> = (0..n).collect();
+    let blocks = block_triangularize(&incidence, &matching);
+    assert_eq!(blocks.len(), n);
+    assert_eq!(blocks[0], vec![n - 1]);
+    assert_eq!(blocks[n - 1], vec![0]);
+
+    // the last equation takes variable 0, re-matching every other equation along the chain
+    incidence[n - 1] = vec![0];
+    let var_of_eqn = maximum_matching(&incidence, n);
+    assert!(var_of_eqn.iter().all(Option::is_some));
+    assert_eq!(var_of_eqn[0], Some(1));
+}
@@ -1,7 +1,14 @@
 use std::collections::{HashMap, HashSet};
-use crate::newton::multivariate_newton_raphson;
-use crate::shunting::{get_legal_variables_iter, ContextHashMap, Token};
-use crate::compile_equation_to_fn_of_hashmap;
+use std::rc::Rc;
+use crate::errors::{SolverOptionsError, SystemStructureError};
+use crate::gauss_newton::{bounded_gauss_newton_with_jacobian, LeastSquaresReport};
+use crate::levenberg_marquardt::bounded_levenberg_marquardt_with_jacobian;
+use crate::minimum_norm::{minimum_norm_newton_with_jacobian, MinimumNormReport};
+use crate::newton::{bounded_multivariate_newton_raphson_with_jacobian_report, solve_in_domain, solve_with_margin, Bounds};
+use crate::options::{Method, SolverOptions};
+use crate::shunting::{compile_expr_to_dual_fn_of_shared_context, compile_expr_to_fn_of_shared_context, from_sync_context, get_legal_variables_iter, to_sync_context, ContextHashMap, Expr, SyncContextHashMap, SyncToken, Token};
+use crate::structure::{block_triangularize, dulmage_mendelsohn, maximum_matching};
+use crate::{derivative_in_context, parse_equation_with_unknowns};
 
 /// An enum for indicating why an equation could or could not be added
 /// to a system of equations in a `SystemBuilder`.
@@ -21,7 +28,72 @@ pub enum ConstrainResult
     WillOverConstrain,
 }
 
-/// Type alias for `System` and `SystemBuilder`
+/// A set of equations in a `System` that must be solved simultaneously,
+/// and the variables that they are solved for.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block
+{
+    /// The indices of the equations in the block, in the order they were added to the system.
+    pub equations: Vec<usize>,
+    /// The variables solved for by the block. Each is matched to the equation at the same position in `equations`.
+    pub vars: Vec<String>,
+}
+
+/// Explains which parts of a set of equations are under- or over-constrained,
+/// as found by the Dulmage-Mendelsohn decomposition of the set. Equations are
+/// given by their index in the order they were given or added.
+/// 
+/// A set of equations can be solved once every equation is matched to a different
+/// variable that appears in it, with no variable left over. Where there are more
+/// variables than equations can be matched to, some of them are under-constrained,
+/// and where there are more equations, some of them are redundant or conflicting.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Diagnosis
+{
+    /// The variables that cannot be found, because too few equations involve them.
+    pub under_constrained_vars: Vec<String>,
+    /// The equations that `under_constrained_vars` are found from.
+    pub under_constrained_equations: Vec<usize>,
+    /// The equations that are redundant or conflicting, because there are more 
+    /// of them than variables that appear in them.
+    pub over_constrained_equations: Vec<usize>,
+    /// The variables that appear in `over_constrained_equations`.
+    pub over_constrained_vars: Vec<String>,
+    /// Variables that could be given known values to remove the under-constrained
+    /// part. This is one choice among `under_constrained_vars`.
+    pub suggested_fixes: Vec<String>,
+    /// Equations that could be removed to remove the over-constrained part. This 
+    /// is one choice among `over_constrained_equations`.
+    pub redundant_equations: Vec<usize>,
+}
+impl Diagnosis
+{
+    /// Finds the diagnosis of `equations` in the unknowns `vars`.
+    fn of(equations: &[Expr], vars: &[String]) -> Diagnosis
+    {
+        let dm = dulmage_mendelsohn(&incidence(equations, vars), vars.len());
+        let names = |indices: Vec<usize>| indices.into_iter().map(|i| vars[i].clone()).collect();
+
+        Diagnosis
+        {
+            under_constrained_vars: names(dm.under_vars),
+            under_constrained_equations: dm.under_equations,
+            over_constrained_equations: dm.over_equations,
+            over_constrained_vars: names(dm.over_vars),
+            suggested_fixes: names(dm.unmatched_vars),
+            redundant_equations: dm.unmatched_equations,
+        }
+    }
+
+    /// Indicates whether the equations can be solved, i.e. every equation can be 
+    /// matched to a different variable in it with no variable left over.
+    pub fn is_well_constrained(&self) -> bool
+    {
+        self.under_constrained_vars.is_empty() && self.over_constrained_equations.is_empty()
+    }
+}
+
+/// Type alias for the compiled equations of a `System`
 type BoxedFnOfHashMapToResultF64 = Box<dyn Fn(&HashMap<String, f64>) -> anyhow::Result<f64>>;
 
 /// An object for building up a system of equations and ensuring that it is 
@@ -30,7 +102,7 @@ pub struct SystemBuilder
 {
     context: ContextHashMap,
     system_vars: Vec<String>,
-    system_equations: Vec<BoxedFnOfHashMapToResultF64>,
+    system_equations: Vec<Expr>,
 }
 impl SystemBuilder
 {
@@ -52,7 +124,7 @@ impl SystemBuilder
             .map(|x| x.to_owned())
             .collect();
 
-        let starting_eqn = Box::new(compile_equation_to_fn_of_hashmap(equation, &mut ctx)?);
+        let starting_eqn = parse_equation_with_unknowns(equation, &mut ctx)?;
 
         Ok(SystemBuilder
         {
@@ -62,6 +134,104 @@ impl SystemBuilder
         })
     }
 
+    /// Constructs a `SystemBuilder` from a whole set of equations at once. 
+    /// 
+    /// Unlike adding equations one at a time with `try_constrain_with`, the order 
+    /// of the equations doesn't matter, and any number of them may introduce more 
+    /// than one unknown. Instead, the set must have as many equations as unknowns, 
+    /// and it must be possible to match each equation to a different unknown that 
+    /// appears in it. Otherwise, some unknowns could never be found and a 
+    /// `SystemStructureError` is returned. `diagnose_equations` explains why.
+    /// 
+    /// # Example
+    /// ```
+    /// use geqslib::system::SystemBuilder;
+    /// use geqslib::shunting::new_context;
+    /// 
+    /// // No equation can be added to a system started from any other, because 
+    /// // each adds two new unknowns to it
+    /// let builder = SystemBuilder::from_equations(
+    ///     &["x + y = 3", "z + w = 7", "x - y = 1", "z - w = 1"], 
+    ///     new_context()
+    /// ).unwrap();
+    /// 
+    /// let soln = builder.build_system().unwrap().solve(0.0001, 100).unwrap();
+    /// 
+    /// assert!((soln["x"] - 2.0).abs() < 0.001);
+    /// assert!((soln["w"] - 3.0).abs() < 0.001);
+    /// 
+    /// // three equations for x and y leave only one for z and w
+    /// assert!(SystemBuilder::from_equations(&["x + y = 3", "z + w = 7", "x - y = 1", "x = 2"], new_context()).is_err());
+    /// ```
+    pub fn from_equations(equations: &[&str], mut ctx: ContextHashMap) -> anyhow::Result<SystemBuilder>
+    {
+        let (system_vars, system_equations) = parse_equation_set(equations, &mut ctx)?;
+
+        if system_equations.len() != system_vars.len()
+        {
+            return Err(SystemStructureError::NotSquare.into());
+        }
+
+        if maximum_matching(&incidence(&system_equations, &system_vars), system_vars.len()).contains(&None)
+        {
+            return Err(SystemStructureError::StructurallySingular.into());
+        }
+
+        Ok(SystemBuilder
+        {
+            context: ctx,
+            system_vars,
+            system_equations,
+        })
+    }
+
+    /// Constructs a `System` to be fit by least squares with `System::fit` from 
+    /// a set of equations with at least as many equations as unknowns. Every 
+    /// unknown must still be matched to a different equation that it appears 
+    /// in, or a `SystemStructureError` is returned.
+    /// 
+    /// # Example
+    /// ```
+    /// use geqslib::system::SystemBuilder;
+    /// use geqslib::shunting::new_context;
+    /// 
+    /// // Three measurements of x + y and one of x - y
+    /// let sys = SystemBuilder::least_squares_system(
+    ///     &["x + y = 3.1", "x + y = 2.9", "x + y = 3.0", "x - y = 1"], 
+    ///     new_context()
+    /// ).unwrap();
+    /// 
+    /// let fit = sys.fit(&Default::default()).unwrap();
+    /// 
+    /// assert!((fit.solution["x"] - 2.0).abs() < 0.001);
+    /// assert!((fit.solution["y"] - 1.0).abs() < 0.001);
+    /// ```
+    pub fn least_squares_system(equations: &[&str], mut ctx: ContextHashMap) -> anyhow::Result<System>
+    {
+        let (system_vars, system_equations) = parse_equation_set(equations, &mut ctx)?;
+
+        if system_equations.len() < system_vars.len()
+        {
+            return Err(SystemStructureError::TooFewEquations.into());
+        }
+
+        let matched = maximum_matching(&incidence(&system_equations, &system_vars), system_vars.len())
+            .iter()
+            .filter(|var| var.is_some())
+            .count();
+        if matched < system_vars.len()
+        {
+            return Err(SystemStructureError::StructurallySingular.into());
+        }
+
+        Ok(System
+        {
+            context: to_sync_context(&ctx),
+            system_vars,
+            system_equations,
+        })
+    }
+
     /// Gives a reference to the unknown variables in the system.
     /// 
     /// # Example
@@ -137,7 +307,7 @@ impl SystemBuilder
 
         // Add the equation to the system, updating the context with any newly-added variables
         self.system_equations.push(
-            Box::new(compile_equation_to_fn_of_hashmap(equation, &mut self.context)?) 
+            parse_equation_with_unknowns(equation, &mut self.context)?
         );
 
         // Add possible newly-found variable to the system
@@ -178,7 +348,9 @@ impl SystemBuilder
     }
 
     /// Attempts to fully constrain a system using a given `Vec`
-    /// of equations.
+    /// of equations. Equations are added one at a time as they become
+    /// useful, so this can stop short of a set of equations that is only
+    /// solvable as a whole. Use `SystemBuilder::from_equations` for those.
     /// 
     /// # Example
     /// ```
@@ -225,14 +397,40 @@ impl SystemBuilder
         Ok(self.is_fully_constrained())
     }
 
+    /// Explains which parts of the system are under- or over-constrained.
+    /// 
+    /// # Example
+    /// ```
+    /// use geqslib::system::SystemBuilder;
+    /// use geqslib::shunting::new_context;
+    /// 
+    /// let mut builder = SystemBuilder::new("x + y + z = 6", new_context()).unwrap();
+    /// builder.try_constrain_with("x - y = 1").unwrap();
+    /// 
+    /// // one more equation is needed to find x, y, and z
+    /// let diagnosis = builder.diagnose();
+    /// assert!(!diagnosis.is_well_constrained());
+    /// assert_eq!(diagnosis.under_constrained_vars.len(), 3);
+    /// assert_eq!(diagnosis.suggested_fixes.len(), 1);
+    /// 
+    /// // x + y = 3 and x - y = 1 find x and y, which leaves x + y + z = 6 to find z
+    /// builder.try_constrain_with("x + y = 3").unwrap();
+    /// assert!(builder.diagnose().is_well_constrained());
+    /// ```
+    pub fn diagnose(&self) -> Diagnosis
+    {
+        Diagnosis::of(&self.system_equations, &self.system_vars)
+    }
+
     /// Consumes `self` in order to produce a `System` object, representing 
-    /// a constrained system of equations.
+    /// a constrained system of equations. Returns `None` if the system is 
+    /// not fully constrained, in which case `diagnose` explains why.
     pub fn build_system(self) -> Option<System>
     {
         if self.is_fully_constrained()
         {
             return Some(System {
-                context: self.context,
+                context: to_sync_context(&self.context),
                 system_vars: self.system_vars,
                 system_equations: self.system_equations,
             });
@@ -240,6 +438,35 @@ impl SystemBuilder
         
         None
     }
+
+    /// Consumes `self` in order to produce a `System` object with fewer equations
+    /// than unknowns, to be solved with `System::solve_minimum_norm`. Returns `None`
+    /// if the system has more equations than unknowns, or if some equations are 
+    /// redundant or conflicting, as `diagnose` explains. Fully constrained systems 
+    /// are also accepted.
+    /// 
+    /// # Example
+    /// ```
+    /// use geqslib::system::SystemBuilder;
+    /// use geqslib::shunting::new_context;
+    /// 
+    /// let builder = SystemBuilder::new("x + y + z = 6", new_context()).unwrap();
+    /// 
+    /// assert!(builder.build_underdetermined_system().is_some());
+    /// ```
+    pub fn build_underdetermined_system(self) -> Option<System>
+    {
+        if self.diagnose().over_constrained_equations.is_empty()
+        {
+            return Some(System {
+                context: to_sync_context(&self.context),
+                system_vars: self.system_vars,
+                system_equations: self.system_equations,
+            });
+        }
+
+        None
+    }
 }
 
 impl std::fmt::Debug for SystemBuilder
@@ -253,11 +480,35 @@ impl std::fmt::Debug for SystemBuilder
 /// variables or just be solved after construction.
 /// 
 /// This object can only be built using a `SystemBuilder` object.
+/// 
+/// A `System` is `Send + Sync`, so many systems can be solved in parallel:
+/// 
+/// ```
+/// use geqslib::system::SystemBuilder;
+/// use geqslib::shunting::{new_context, ContextLike};
+/// 
+/// let handles: Vec<_> = (1..=4).map(|i| {
+///     let mut ctx = new_context();
+///     ctx.add_const_to_ctx("k", i);
+/// 
+///     let mut builder = SystemBuilder::new("x + y = 2 * k", ctx).unwrap();
+///     builder.try_constrain_with("x - y = k").unwrap();
+///     let sys = builder.build_system().unwrap();
+/// 
+///     std::thread::spawn(move || sys.solve(0.0001, 10).unwrap())
+/// }).collect();
+/// 
+/// for (i, handle) in handles.into_iter().enumerate()
+/// {
+///     let soln = handle.join().unwrap();
+///     assert!((soln["x"] - 1.5 * (i + 1) as f64).abs() < 0.001);
+/// }
+/// ```
 pub struct System
 {
-    context: ContextHashMap,
+    context: SyncContextHashMap,
     system_vars: Vec<String>,
-    system_equations: Vec<BoxedFnOfHashMapToResultF64>,
+    system_equations: Vec<Expr>,
 }
 impl System
 {
@@ -296,12 +547,12 @@ impl System
             return false;
         }
 
-        match &self.context[var]
+        match self.context.get_mut(var)
         {
-            Token::Var(value) => {
-                (value.borrow_mut()).min = min;
-                (value.borrow_mut()).max = max;
-                (value.borrow_mut()).set(guess);
+            Some(SyncToken::Var(value)) => {
+                value.min = min;
+                value.max = max;
+                value.set(guess);
             },
             _ => return false,
         };
@@ -309,9 +560,59 @@ impl System
         true
     }
 
+    /// Splits the system into the blocks of equations that it is solved in, in the 
+    /// order that they are solved. 
+    /// 
+    /// Each equation is matched to a variable to solve for. An equation then has to 
+    /// be solved after every other equation that is matched to a variable in it, or 
+    /// simultaneously with those that also have to be solved after it. If no equation 
+    /// can be matched to some variable, the whole system is returned as one block.
+    /// 
+    /// # Example
+    /// ```
+    /// use geqslib::system::SystemBuilder;
+    /// use geqslib::shunting::new_context;
+    /// 
+    /// let mut builder = SystemBuilder::new("x + y + z = 6", new_context()).unwrap();
+    /// builder.try_constrain_with("x * y = 2").unwrap();
+    /// builder.try_constrain_with("z = 3").unwrap();
+    /// 
+    /// let blocks = builder.build_system().unwrap().blocks();
+    /// 
+    /// // z is found first, then x and y together
+    /// assert_eq!(blocks.len(), 2);
+    /// assert_eq!(blocks[0].equations, vec![2]);
+    /// assert_eq!(blocks[0].vars, vec!["z"]);
+    /// assert_eq!(blocks[1].equations, vec![0, 1]);
+    /// ```
+    pub fn blocks(&self) -> Vec<Block>
+    {
+        let vars = self.unknowns();
+        let incidence = incidence(&self.system_equations, &vars);
+
+        let matching: Option<Vec<usize>> = maximum_matching(&incidence, vars.len())
+            .into_iter()
+            .collect();
+
+        match matching
+        {
+            Some(matching) if matching.len() == vars.len() => block_triangularize(&incidence, &matching)
+                .into_iter()
+                .map(|equations| Block {
+                    vars: equations.iter().map(|&eqn| vars[matching[eqn]].clone()).collect(),
+                    equations,
+                })
+                .collect(),
+            _ => vec![Block { equations: (0..self.system_equations.len()).collect(), vars }],
+        }
+    }
+
     /// Tries to solve the system of equations to within the radius `margin` 
     /// of the actual solution in `limit` iterations. 
     /// 
+    /// The jacobian of the system is found by symbolic differentiation where
+    /// possible, and by automatic differentiation where not.
+    /// 
     /// # Example
     /// ```
     /// use geqslib::system::{System, SystemBuilder};
@@ -336,25 +637,315 @@ impl System
     /// ```
     pub fn solve(self, margin: f64, limit: usize) -> anyhow::Result<HashMap<String, f64>>
     {
+        solve_with_margin(margin, limit, |options| self.solve_with_options(options))
+    }
+
+    /// Identical to `solve`, but configured by `options` instead of a margin
+    /// and iteration limit. Systems can be solved with `Method::Auto`, `Method::Newton`, 
+    /// `Method::Broyden`, or `Method::LevenbergMarquardt`.
+    /// 
+    /// The system is solved one block at a time, in the order given by `blocks`.
+    /// Blocks of a single equation are solved like single equations, so with 
+    /// `Method::Auto` they use Brent's method if their variable has a finite 
+    /// domain that brackets a root. Other blocks are solved with the given method.
+    /// 
+    /// Every step is shortened to keep each variable within the domain given to 
+    /// `specify_variable`, rather than clamping it afterwards.
+    /// 
+    /// # Example
+    /// ```
+    /// use geqslib::system::SystemBuilder;
+    /// use geqslib::options::SolverOptions;
+    /// use geqslib::shunting::new_context;
+    /// 
+    /// let mut builder = SystemBuilder::new("x * y = 6", new_context()).unwrap();
+    /// builder.try_constrain_with("x - y = 1").unwrap();
+    /// 
+    /// let mut sys = builder.build_system().unwrap();
+    /// sys.specify_variable("x", 4.0, f64::NEG_INFINITY, f64::INFINITY);
+    /// 
+    /// let options = SolverOptions::new()
+    ///     .abs_tol(1e-10)
+    ///     .step_tol(1e-10)
+    ///     .damping(0.8)
+    ///     .max_iterations(500);
+    /// 
+    /// let soln = sys.solve_with_options(&options).unwrap();
+    /// 
+    /// assert!((soln["x"] - 3.0).abs() < 1e-9);
+    /// assert!((soln["y"] - 2.0).abs() < 1e-9);
+    /// ```
+    pub fn solve_with_options(self, options: &SolverOptions) -> anyhow::Result<HashMap<String, f64>>
+    {
+        if !matches!(options.method, Method::Auto | Method::Newton | Method::Broyden | Method::LevenbergMarquardt)
+        {
+            return Err(SolverOptionsError::UnsupportedMethod.into());
+        }
+
+        // Variables are kept in cells while solving, local to this call and
+        // shared by every function compiled for it
+        let context = Rc::new(from_sync_context(&self.context));
+
+        let mut solution = HashMap::new();
+        for block in self.blocks()
+        {
+            // Later blocks are evaluated with the values found for earlier ones
+            for (var, value) in self.solve_block(&block, &context, options)?
+            {
+                if let Some(Token::Var(x)) = context.get(&var)
+                {
+                    x.borrow_mut().set(value);
+                }
+                solution.insert(var, value);
+            }
+        }
+
+        Ok(solution)
+    }
+
+    /// Fits the variables of the system to minimize the sum of the squares of the 
+    /// residuals of its equations with the Gauss-Newton method, as described by 
+    /// `gauss_newton`. This is how systems with more equations than unknowns, 
+    /// built with `SystemBuilder::least_squares_system`, are solved. The residual
+    /// of each equation is given in the order the equations were given.
+    /// 
+    /// The `method` in `options` does not apply. Every step keeps each variable 
+    /// within the domain given to `specify_variable`.
+    /// 
+    /// # Example
+    /// ```
+    /// use geqslib::system::SystemBuilder;
+    /// use geqslib::options::SolverOptions;
+    /// use geqslib::shunting::new_context;
+    /// 
+    /// // Fit y = a * e^(k * x) to four points
+    /// let sys = SystemBuilder::least_squares_system(&[
+    ///     "a * e ^ (k * 0) = 2.0",
+    ///     "a * e ^ (k * 1) = 2.7",
+    ///     "a * e ^ (k * 2) = 3.7",
+    ///     "a * e ^ (k * 3) = 4.9",
+    /// ], new_context()).unwrap();
+    /// 
+    /// let fit = sys.fit(&SolverOptions::new().step_tol(1e-9)).unwrap();
+    /// 
+    /// assert!((fit.solution["a"] - 2.0).abs() < 0.05);
+    /// assert!((fit.solution["k"] - 0.3).abs() < 0.01);
+    /// 
+    /// // the variance of each variable is on the diagonal of the covariance
+    /// let k = fit.vars.iter().position(|var| var == "k").unwrap();
+    /// assert!(fit.covariance[k][k] > 0.0);
+    /// ```
+    pub fn fit(self, options: &SolverOptions) -> anyhow::Result<LeastSquaresReport>
+    {
+        let context = Rc::new(from_sync_context(&self.context));
+        let vars = self.unknowns();
+
         let mut guess = HashMap::new();
-        for (key, var) in self.context
+        let mut bounds = Bounds::new();
+        for var in &vars
         {
-            match var
+            if let Some(SyncToken::Var(x)) = self.context.get(var)
             {
-                Token::Var(x) => guess.insert(key, (*x.borrow()).into()),
-                _ => continue,
+                guess.insert(var.clone(), (*x).into());
+                bounds.insert(var.clone(), (x.min, x.max));
+            }
+        }
+
+        let equations: Vec<BoxedFnOfHashMapToResultF64> = self.system_equations.iter()
+            .map(|eqn| Box::new(compile_expr_to_fn_of_shared_context(eqn.clone(), Rc::clone(&context))) as BoxedFnOfHashMapToResultF64)
+            .collect();
+        let all_equations: Vec<usize> = (0..self.system_equations.len()).collect();
+
+        bounded_gauss_newton_with_jacobian(equations, self.jacobian(&all_equations, &vars, &context), &mut guess, &bounds, options)
+    }
+
+    /// Solves a system with fewer equations than unknowns for the solution nearest 
+    /// to the guesses given to `specify_variable`, as described by `minimum_norm_newton`. 
+    /// This is how systems built with `SystemBuilder::build_underdetermined_system` 
+    /// are solved. The report gives the directions that the solution is still free
+    /// to move in.
+    /// 
+    /// The `method` and `line_search` options do not apply.
+    /// 
+    /// # Example
+    /// ```
+    /// use geqslib::system::SystemBuilder;
+    /// use geqslib::options::SolverOptions;
+    /// use geqslib::shunting::new_context;
+    /// 
+    /// // A rectangle with an area of 12, as near to 3 by 3 as possible
+    /// let builder = SystemBuilder::new("w * h = 12", new_context()).unwrap();
+    /// let mut sys = builder.build_underdetermined_system().unwrap();
+    /// sys.specify_variable("w", 3.0, f64::NEG_INFINITY, f64::INFINITY);
+    /// sys.specify_variable("h", 3.0, f64::NEG_INFINITY, f64::INFINITY);
+    /// 
+    /// let report = sys.solve_minimum_norm(&SolverOptions::new().step_tol(1e-9)).unwrap();
+    /// 
+    /// assert!((report.solution["w"] - 12f64.sqrt()).abs() < 0.001);
+    /// assert!((report.solution["h"] - 12f64.sqrt()).abs() < 0.001);
+    /// assert_eq!(report.free_directions.len(), 1);
+    /// ```
+    pub fn solve_minimum_norm(self, options: &SolverOptions) -> anyhow::Result<MinimumNormReport>
+    {
+        let context = Rc::new(from_sync_context(&self.context));
+        let vars = self.unknowns();
+
+        let mut guess = HashMap::new();
+        for var in &vars
+        {
+            if let Some(SyncToken::Var(x)) = self.context.get(var)
+            {
+                guess.insert(var.clone(), (*x).into());
+            }
+        }
+
+        let equations: Vec<BoxedFnOfHashMapToResultF64> = self.system_equations.iter()
+            .map(|eqn| Box::new(compile_expr_to_fn_of_shared_context(eqn.clone(), Rc::clone(&context))) as BoxedFnOfHashMapToResultF64)
+            .collect();
+        let all_equations: Vec<usize> = (0..self.system_equations.len()).collect();
+
+        minimum_norm_newton_with_jacobian(equations, self.jacobian(&all_equations, &vars, &context), &mut guess, options)
+    }
+
+    /// Solves a single block of the system, returning the values of its variables.
+    fn solve_block(&self, block: &Block, context: &Rc<ContextHashMap>, options: &SolverOptions) -> anyhow::Result<HashMap<String, f64>>
+    {
+        let mut guess = HashMap::new();
+        let mut bounds = Bounds::new();
+        for var in &block.vars
+        {
+            if let Some(SyncToken::Var(x)) = self.context.get(var)
+            {
+                guess.insert(var.clone(), (*x).into());
+                bounds.insert(var.clone(), (x.min, x.max));
+            }
+        }
+
+        let equations: Vec<BoxedFnOfHashMapToResultF64> = block.equations.iter()
+            .map(|&eqn| Box::new(compile_expr_to_fn_of_shared_context(self.system_equations[eqn].clone(), Rc::clone(context))) as BoxedFnOfHashMapToResultF64)
+            .collect();
+        let jacobian = self.jacobian(&block.equations, &block.vars, context);
+
+        // Single equations don't need the machinery of a system, unless they 
+        // are searched along with a line search
+        if block.vars.len() == 1 && !options.line_search && matches!(options.method, Method::Auto | Method::Newton)
+        {
+            let var = &block.vars[0];
+            let (min, max) = bounds[var];
+            let at = |x: f64| HashMap::from([(var.clone(), x)]);
+            let f = |x: f64| equations[0](&at(x));
+            let f_prime = |x: f64| match jacobian[0].get(var)
+            {
+                Some(df_dx) => df_dx(&at(x)),
+                None => Ok(0.0),
             };
+
+            return Ok(at(solve_in_domain(f, f_prime, guess[var], (min, max), options)?));
+        }
+
+        let report = match options.method
+        {
+            Method::LevenbergMarquardt => bounded_levenberg_marquardt_with_jacobian(equations, jacobian, &mut guess, &bounds, options)?,
+            _ => bounded_multivariate_newton_raphson_with_jacobian_report(equations, jacobian, &mut guess, &bounds, options)?,
+        };
+
+        Ok(report.solution)
+    }
+
+    /// Returns the unknowns of the system, in sorted order.
+    fn unknowns(&self) -> Vec<String>
+    {
+        let mut vars: Vec<String> = self.context.iter()
+            .filter(|(_, token)| matches!(token, SyncToken::Var(_)))
+            .map(|(name, _)| name.clone())
+            .collect();
+        vars.sort();
+        vars
+    }
+
+    /// Builds the partial derivatives of the given equations with respect to the given 
+    /// variables that appear in them. Symbolic derivatives are used where they are known, 
+    /// and automatic differentiation is used elsewhere. Every partial derivative shares
+    /// `context` rather than copying it.
+    fn jacobian(&self, equations: &[usize], vars: &[String], context: &Rc<ContextHashMap>) -> Vec<HashMap<String, BoxedFnOfHashMapToResultF64>>
+    {
+        let mut jacobian = vec![];
+        for &i in equations
+        {
+            let eqn = &self.system_equations[i];
+            let mut row = HashMap::new();
+            for var in vars.iter().filter(|var| eqn.depends_on(var))
+            {
+                let partial: BoxedFnOfHashMapToResultF64 = match derivative_in_context(eqn, var, context)
+                {
+                    Some(derivative) => Box::new(compile_expr_to_fn_of_shared_context(derivative, Rc::clone(context))),
+                    None => {
+                        let f = compile_expr_to_dual_fn_of_shared_context(eqn.clone(), Rc::clone(context));
+                        let var_name = var.to_owned();
+                        Box::new(move |x| Ok(f(x, &var_name)?.deriv))
+                    },
+                };
+                row.insert(var.to_owned(), partial);
+            }
+            jacobian.push(row);
         }
+        jacobian
+    }
+}
 
-        let res = multivariate_newton_raphson(
-            self.system_equations, 
-            &mut guess,
-            margin, 
-            limit
-        )?;
+/// Explains which parts of a set of equations are under- or over-constrained,
+/// in the same way as `SystemBuilder::diagnose`. The unknowns are the variables
+/// in the equations that are not in `ctx`.
+/// 
+/// # Example
+/// ```
+/// use geqslib::system::diagnose_equations;
+/// use geqslib::shunting::new_context;
+/// 
+/// let diagnosis = diagnose_equations(&["x + y = 3", "x - y = 1", "x = 2", "z + w = 7"], &new_context()).unwrap();
+/// 
+/// // three equations for x and y...
+/// assert_eq!(diagnosis.over_constrained_equations, vec![0, 1, 2]);
+/// assert_eq!(diagnosis.redundant_equations.len(), 1);
+/// 
+/// // ...and one for z and w
+/// assert_eq!(diagnosis.under_constrained_equations, vec![3]);
+/// assert_eq!(diagnosis.suggested_fixes.len(), 1);
+/// ```
+pub fn diagnose_equations(equations: &[&str], ctx: &ContextHashMap) -> anyhow::Result<Diagnosis>
+{
+    let (vars, equations) = parse_equation_set(equations, &mut ctx.clone())?;
+    Ok(Diagnosis::of(&equations, &vars))
+}
 
-        Ok(res.clone())
+/// Parses a set of equations, adding their unknowns to `ctx`. Returns the 
+/// unknowns in the order they were found, sorted within each equation, and 
+/// the parsed equations.
+fn parse_equation_set(equations: &[&str], ctx: &mut ContextHashMap) -> anyhow::Result<(Vec<String>, Vec<Expr>)>
+{
+    let mut vars: Vec<String> = vec![];
+    let mut exprs = vec![];
+    for equation in equations
+    {
+        let mut unknowns: Vec<String> = get_equation_unknowns(equation, ctx).map(|x| x.to_owned()).collect();
+        unknowns.sort();
+        vars.extend(unknowns);
+        exprs.push(parse_equation_with_unknowns(equation, ctx)?);
     }
+    Ok((vars, exprs))
+}
+
+/// Lists the indices of the variables in `vars` that appear in each equation.
+fn incidence(equations: &[Expr], vars: &[String]) -> Vec<Vec<usize>>
+{
+    equations.iter()
+        .map(|eqn| {
+            let appearing = eqn.variables();
+            (0..vars.len())
+                .filter(|&i| appearing.contains(vars[i].as_str()))
+                .collect()
+        })
+        .collect()
 }
 
 /// Returns an iterator with the unknown variables in a given equation or expression. 
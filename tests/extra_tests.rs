@@ -1,7 +1,21 @@
 use std::collections::HashMap;
-use geqslib::shunting::{new_context, ContextHashMap};
-use geqslib::shunting::{eval_str, eval_str_with_context, ContextLike};
-use geqslib::solve_equation_with_context;
+use geqslib::shunting::{new_context, Arity, ContextHashMap};
+use geqslib::shunting::{eval_str, eval_str_with_context, compile_expr_to_fn, compile_to_bytecode, compile_to_dual_fn, parse_expr, Bytecode, ContextLike};
+use geqslib::{solve_equation_from_str, solve_equation_with_context, solve_equation_with_options};
+use geqslib::roots::{bisection, brent, illinois};
+use geqslib::system::{diagnose_equations, Block, SystemBuilder};
+use geqslib::newton::{newton_raphson, newton_raphson_report, multivariate_newton_raphson_report};
+use geqslib::levenberg_marquardt::levenberg_marquardt;
+use geqslib::options::{FiniteDifference, Method, Norm, SolverOptions};
+use geqslib::errors::{BracketingSolverError, NewtonRaphsonSolverError, NotConvergedError, ParseError, ShuntingYardError, SingularJacobianError, SolverOptionsError, SystemStructureError};
+
+/// An expression in `x` using each built-in function, for checking derivatives
+const BUILT_IN_EXPRS: [&str; 24] = [
+    "sin(x)", "cos(x)", "tan(x)", "arcsin(x)", "arccos(x)", "arctan(x)",
+    "sinh(x)", "cosh(x)", "tanh(x)", "ln(x)", "log10(x)", "log(2, x)", "log(x, 2)", 
+    "abs(x - 1)", "x^x", "2^x", "x^3 / (1 + x)", "-x * e^-x", "if(x, 2, 1, x^2, 3*x)",
+    "min(x, 1)", "max(0.1, x^2, x)", "sum(x, x^2, 3)", "mean(x, 2*x)", "hypot(x, 2, x^2)",
+];
 
 #[test]
 fn test_eval_str() 
@@ -42,4 +56,864 @@ fn ensure_that_single_unknown_solver_can_solve_equation_with_if_statement()
 
     assert_eq!(soln.0, "i".to_owned());
     assert!(soln.1 - 1.0 < 0.001);
-}
\ No newline at end of file
+}
+
+#[test]
+fn symbolic_derivatives_of_built_in_functions_match_finite_differences()
+{
+    let mut ctx = new_context();
+    ctx.add_var_to_ctx("x", 0.3);
+
+    for text in BUILT_IN_EXPRS
+    {
+        let expr = parse_expr(text, &ctx).unwrap();
+        let d_dx = compile_expr_to_fn(expr.derivative("x").unwrap(), &ctx).unwrap();
+        let f = compile_expr_to_fn(expr, &ctx).unwrap();
+
+        let h = 1e-6;
+        let approx = (f(0.3 + h).unwrap() - f(0.3 - h).unwrap()) / (2.0 * h);
+        assert!((d_dx(0.3).unwrap() - approx).abs() < 1e-6, "d/dx {text}");
+    }
+}
+
+#[test]
+fn ensure_that_system_solves_variables_on_large_scales()
+{
+    let mut ctx = new_context();
+    ctx.add_const_to_ctx("t", 300);
+
+    let mut builder = SystemBuilder::new("p * v = 8314 * t", ctx).unwrap();
+    builder.try_constrain_with("v = 0.001").unwrap();
+
+    let mut sys = builder.build_system().unwrap();
+    sys.specify_variable("p", 1e6, 0.0, f64::INFINITY);
+
+    let soln = sys.solve(0.0001, 50).unwrap();
+
+    assert!((soln["p"] - 2.4942e9).abs() < 1.0);
+}
+
+#[test]
+fn automatic_derivatives_of_built_in_functions_match_symbolic_derivatives()
+{
+    let mut ctx = new_context();
+    ctx.add_var_to_ctx("x", 0.3);
+
+    for text in BUILT_IN_EXPRS
+    {
+        let expr = parse_expr(text, &ctx).unwrap();
+        let symbolic = expr.derivative("x").unwrap().eval(&ctx).unwrap();
+        let automatic = expr.eval_dual(&ctx, "x").unwrap();
+
+        assert_eq!(automatic.value, expr.eval(&ctx).unwrap());
+        assert!((automatic.deriv - symbolic).abs() < 1e-12, "d/dx {text}");
+    }
+}
+
+#[test]
+fn ensure_that_system_solves_with_custom_functions()
+{
+    fn cube(x: &[f64]) -> f64 { x[0].powi(3) }
+    fn square(x: &[f64]) -> f64 { x[0] * x[0] }
+    fn d_square(x: &[f64], _: usize) -> f64 { 2.0 * x[0] }
+
+    let mut ctx = new_context();
+    ctx.add_func_to_ctx("cube", cube, 1);
+    ctx.add_closure_to_ctx("square", Arity::Exactly(1), |x| Ok(square(x)))
+        .with_derivative(|x, i| Ok(d_square(x, i)));
+
+    // No symbolic derivative exists for either function
+    let expr = parse_expr("cube(2) + square(3)", &ctx).unwrap();
+    assert_eq!(expr.derivative("x").unwrap(), parse_expr("0", &ctx).unwrap());
+
+    let mut builder = SystemBuilder::new("cube(x) = y + 6", ctx).unwrap();
+    builder.try_constrain_with("square(y) = x").unwrap();
+
+    let mut sys = builder.build_system().unwrap();
+    sys.specify_variable("x", 3.0, f64::NEG_INFINITY, f64::INFINITY);
+    sys.specify_variable("y", 2.0, f64::NEG_INFINITY, f64::INFINITY);
+
+    let soln = sys.solve(0.0001, 50).unwrap();
+
+    assert!((soln["x"].powi(3) - soln["y"] - 6.0).abs() < 0.0001);
+    assert!((soln["y"] * soln["y"] - soln["x"]).abs() < 0.0001);
+}
+
+#[test]
+fn ensure_that_closures_can_capture_state_and_fail()
+{
+    let scale = 2.0;
+    let mut ctx = new_context();
+    ctx.add_closure_to_ctx("scaled", Arity::Exactly(1), move |x| Ok(scale * x[0]))
+        .with_derivative(move |_, _| Ok(scale));
+    ctx.add_closure_to_ctx("positive_sqrt", Arity::Exactly(1), |x| {
+        if x[0] < 0.0
+        {
+            anyhow::bail!("cannot take the square root of {}", x[0]);
+        }
+        Ok(x[0].sqrt())
+    });
+
+    let soln = solve_equation_with_context("scaled(x) = 9", &mut ctx.clone(), 1.0, f64::NEG_INFINITY, f64::INFINITY, 0.0001, 50).unwrap();
+    assert!((soln.1 - 4.5).abs() < 0.0001);
+
+    // a failing closure is reported instead of turning into NaN
+    let err = solve_equation_with_context("positive_sqrt(x - 10) = 1", &mut ctx, 1.0, f64::NEG_INFINITY, f64::INFINITY, 0.0001, 50).unwrap_err();
+    assert!(err.to_string().contains("cannot take the square root"));
+}
+
+#[test]
+fn ensure_that_variadic_functions_take_any_number_of_arguments()
+{
+    assert_eq!(eval_str("max(1, 3)").unwrap(), 3.0);
+    assert_eq!(eval_str("max(1, 3, 7, 2)").unwrap(), 7.0);
+    assert_eq!(eval_str("min(4)").unwrap(), 4.0);
+    assert_eq!(eval_str("min(4, -2, 0)").unwrap(), -2.0);
+    assert_eq!(eval_str("sum(1, 2, 3, 4)").unwrap(), 10.0);
+    assert_eq!(eval_str("mean(1, 2, 3, 4)").unwrap(), 2.5);
+    assert_eq!(eval_str("hypot(3, 4)").unwrap(), 5.0);
+    assert_eq!(eval_str("hypot(2, 3, 6)").unwrap(), 7.0);
+
+    assert!(eval_str("max()").is_err());
+
+    // variadic closures can carry their own derivative too
+    let mut ctx = new_context();
+    ctx.add_closure_to_ctx("sum_sq", Arity::AtLeast(1), |x| Ok(x.iter().map(|a| a * a).sum()))
+        .with_derivative(|x, i| Ok(2.0 * x[i]));
+    ctx.add_var_to_ctx("x", 1);
+
+    let f = compile_to_dual_fn("sum_sq(x, 2 * x, 3)", &ctx).unwrap();
+    assert_eq!(f(1.0).unwrap().deriv, 10.0);
+}
+
+#[test]
+fn ensure_that_multi_argument_functions_receive_arguments_in_call_order()
+{
+    // log(x, base)
+    assert!((eval_str("log(8, 2)").unwrap() - 3.0).abs() < 1e-12);
+    assert!((eval_str("log(100, 10)").unwrap() - 2.0).abs() < 1e-12);
+
+    // if(a, op_code, b, if_true, if_false) with op codes 1: ==, 2: <=, 3: >=, 4: <, 5: >, other: !=
+    assert_eq!(eval_str("if(1, 1, 1, 10, 20)").unwrap(), 10.0);
+    assert_eq!(eval_str("if(1, 2, 2, 10, 20)").unwrap(), 10.0);
+    assert_eq!(eval_str("if(1, 3, 2, 10, 20)").unwrap(), 20.0);
+    assert_eq!(eval_str("if(1, 4, 2, 10, 20)").unwrap(), 10.0);
+    assert_eq!(eval_str("if(1, 5, 2, 10, 20)").unwrap(), 20.0);
+    assert_eq!(eval_str("if(1, 6, 2, 10, 20)").unwrap(), 10.0);
+
+    // the variadic built-ins are symmetric, but pick the first of tied arguments
+    assert_eq!(eval_str("min(3, 1, 2)").unwrap(), 1.0);
+    assert_eq!(eval_str("max(3, 1, 2)").unwrap(), 3.0);
+    assert_eq!(eval_str("sum(3, 1, 2)").unwrap(), 6.0);
+    assert_eq!(eval_str("mean(3, 1, 2)").unwrap(), 2.0);
+    assert_eq!(eval_str("hypot(3, 4)").unwrap(), 5.0);
+
+    let mut ctx = new_context();
+    ctx.add_var_to_ctx("x", 2);
+    let d_dx = parse_expr("max(x, 2)", &ctx).unwrap().eval_dual(&ctx, "x").unwrap();
+    assert_eq!(d_dx.deriv, 1.0);
+
+    // custom functions get the same order
+    fn first(x: &[f64]) -> f64 { x[0] }
+    ctx.add_func_to_ctx("first", first, 3);
+    assert_eq!(eval_str_with_context("first(1, 2, 3)", &ctx).unwrap(), 1.0);
+}
+
+#[test]
+fn ensure_that_bytecode_matches_tree_evaluation()
+{
+    let mut ctx = new_context();
+    ctx.add_var_to_ctx("x", 0.7);
+    ctx.add_var_to_ctx("y", -2.5);
+    ctx.add_closure_to_ctx("checked_sqrt", Arity::Exactly(1), |x| {
+        anyhow::ensure!(x[0] >= 0.0, "negative square root");
+        Ok(x[0].sqrt())
+    });
+
+    let exprs = [
+        "x + 4 * y", "-x^2 / (1 + y)", "log(x + 10, 2) * e", "max(x, y, 0.5) - min(x, y)", 
+        "hypot(x, y, 1) + mean(x, y)", "if(x, 4, y, x, y) + 1", "x > y && y > 0 || !(x == 0.7)",
+        "if(x > 0, if(y > 0, 1, 2), 3) * (x != y)", "y > 0 && checked_sqrt(y) > 0 || checked_sqrt(x) > 0",
+    ];
+
+    for text in exprs
+    {
+        let expr = parse_expr(text, &ctx).unwrap();
+        let mut program = Bytecode::compile(&expr, &ctx, &["x", "y"]).unwrap();
+        assert_eq!(program.eval(&[0.7, -2.5]).unwrap(), expr.eval(&ctx).unwrap(), "{text}");
+    }
+
+    // errors from functions and division are reported, not turned into NaN
+    let mut program = compile_to_bytecode("checked_sqrt(x)", &ctx, &["x"]).unwrap();
+    assert!(program.eval(&[-1.0]).is_err());
+    assert_eq!(program.eval(&[4.0]).unwrap(), 2.0);
+    assert!(compile_to_bytecode("x / y", &ctx, &["x", "y"]).unwrap().eval(&[1.0, 0.0]).is_err());
+
+    // every variable needs a slot, and every slot needs a value
+    assert!(compile_to_bytecode("x + y", &ctx, &["x"]).is_err());
+    assert!(program.eval(&[1.0, 2.0]).is_err());
+}
+
+#[test]
+fn ensure_that_systems_and_bytecode_can_be_used_from_other_threads()
+{
+    fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+    let mut ctx = new_context();
+    ctx.add_closure_to_ctx("scaled", Arity::Exactly(1), |x| Ok(3.0 * x[0]));
+
+    let mut builder = SystemBuilder::new("scaled(x) + y = 9", ctx.clone()).unwrap();
+    builder.try_constrain_with("x - y = 1").unwrap();
+    let sys = builder.build_system().unwrap();
+    assert_send_sync(&sys);
+
+    ctx.add_var_to_ctx("x", 0);
+    let program = std::sync::Arc::new(compile_to_bytecode("scaled(x) + 1", &ctx, &["x"]).unwrap());
+    assert_send_sync(&program);
+
+    let shared = std::sync::Arc::clone(&program);
+    let from_thread = std::thread::spawn(move || shared.eval_with_stack(&[2.0], &mut vec![]).unwrap());
+    let soln = std::thread::spawn(move || sys.solve(0.0001, 20).unwrap()).join().unwrap();
+
+    assert_eq!(from_thread.join().unwrap(), 7.0);
+    assert!((soln["x"] - 2.5).abs() < 0.0001);
+    assert!((soln["y"] - 1.5).abs() < 0.0001);
+}
+
+#[test]
+fn ensure_that_bracketing_solvers_find_roots()
+{
+    type Case = (fn(f64) -> anyhow::Result<f64>, f64, f64, f64);
+
+    let cases: [Case; 4] = [
+        (|x| Ok(x.cos() - x), 0.0, 1.0, 0.7390851332),
+        (|x| Ok(x.powi(3) - 2.0 * x - 5.0), 2.0, 3.0, 2.0945514815),
+        // flat almost everywhere, which sends Newton-Raphson far away
+        (|x| Ok((10.0 * (x - 1.0)).tanh()), -20.0, 30.0, 1.0),
+        // a root at the end of the bracket
+        (|x| Ok(x * x - 4.0), 2.0, 5.0, 2.0),
+    ];
+
+    for (f, a, b, root) in cases
+    {
+        for solver in [bisection, brent, illinois]
+        {
+            let x = solver(f, a, b, 1e-9, 200).unwrap();
+            assert!((x - root).abs() < 1e-6, "expected {root}, found {x}");
+
+            // the bracket may be given in either order
+            let x = solver(f, b, a, 1e-9, 200).unwrap();
+            assert!((x - root).abs() < 1e-6, "expected {root}, found {x}");
+        }
+    }
+
+    for solver in [bisection, brent, illinois]
+    {
+        let err = solver(|x: f64| Ok::<f64, anyhow::Error>(x * x + 1.0), -1.0, 1.0, 1e-9, 200).unwrap_err();
+        assert!(matches!(err.downcast_ref::<BracketingSolverError>(), Some(BracketingSolverError::NoSignChange)));
+    }
+}
+
+#[test]
+fn ensure_that_single_unknown_solver_uses_finite_domains_as_brackets()
+{
+    let mut ctx = new_context();
+    let (_, soln) = solve_equation_with_context("tanh(10 * (x - 7)) = 0", &mut ctx, 1.0, 0.0, 10.0, 1e-9, 100).unwrap();
+    assert!((soln - 7.0).abs() < 1e-6);
+
+    // no sign change over the domain, so Newton-Raphson is used instead
+    let mut ctx = new_context();
+    let (_, soln) = solve_equation_with_context("(x - 2)^2 = 0", &mut ctx, 1.0, 0.0, 10.0, 1e-6, 100).unwrap();
+    assert!((soln - 2.0).abs() < 1e-2);
+
+    // blocks of one equation narrow their bracket down to the step tolerance, even where the residual is small everywhere...
+    let solve = |equation: &str, options: &SolverOptions| {
+        let mut sys = SystemBuilder::new(equation, new_context()).unwrap().build_system().unwrap();
+        sys.specify_variable("x", 1.0, 0.0, 10.0);
+        sys.solve_with_options(options).unwrap()["x"]
+    };
+    let soln = solve("0.001 * (x - 3.3) = 0", &SolverOptions::new().abs_tol(1.0).step_tol(1e-9));
+    assert!((soln - 3.3).abs() < 1e-6);
+
+    // ...and the residual must still meet its own tolerance when the bracket is wide
+    let soln = solve("1000 * (x - 3.3) = 0", &SolverOptions::new().abs_tol(1e-9).step_tol(0.1));
+    assert!((1000.0 * (soln - 3.3)).abs() <= 1e-9);
+
+    // as do single equations solved on their own
+    let options = SolverOptions::new().abs_tol(1.0).step_tol(1e-9);
+    let (_, soln) = solve_equation_with_options("0.001 * (x - 3.3) = 0", &mut new_context(), 1.0, 0.0, 10.0, &options).unwrap();
+    assert!((soln - 3.3).abs() < 1e-6);
+
+    let options = SolverOptions::new().abs_tol(1e-9).step_tol(0.1);
+    let (_, soln) = solve_equation_with_options("1000 * (x - 3.3) = 0", &mut new_context(), 1.0, 0.0, 10.0, &options).unwrap();
+    assert!((1000.0 * (soln - 3.3)).abs() <= 1e-9);
+
+    // a purely relative step tolerance still narrows the bracket from a guess of 0
+    let options = SolverOptions::new().step_tol(0.0).rel_step_tol(1e-6);
+    let (_, soln) = solve_equation_with_options("x - 3 = 0", &mut new_context(), 0.0, 0.0, 10.0, &options).unwrap();
+    assert!((soln - 3.0).abs() < 1e-4);
+
+    let mut sys = SystemBuilder::new("x - 3 = 0", new_context()).unwrap().build_system().unwrap();
+    sys.specify_variable("x", 0.0, 0.0, 10.0);
+    assert!((sys.solve_with_options(&options).unwrap()["x"] - 3.0).abs() < 1e-4);
+
+    // the bracketing methods take the same tolerances
+    for method in [Method::Bisection, Method::Illinois, Method::Brent]
+    {
+        let options = SolverOptions::new().abs_tol(1.0).step_tol(1e-9).method(method);
+        let (_, soln) = solve_equation_with_options("0.001 * (x - 3.3) = 0", &mut new_context(), 1.0, 0.0, 10.0, &options).unwrap();
+        assert!((soln - 3.3).abs() < 1e-6);
+
+        let options = SolverOptions::new().abs_tol(0.0).rel_tol(1e-6).method(method);
+        let (_, soln) = solve_equation_with_options("1000 * (x - 3.3) = 0", &mut new_context(), 1.0, 0.0, 10.0, &options).unwrap();
+        assert!((soln - 3.3).abs() < 1e-6);
+    }
+}
+
+#[test]
+fn ensure_that_scientific_notation_is_understood()
+{
+    assert_eq!(eval_str("1e-3 * 2").unwrap(), 0.002);
+    assert!((eval_str("6.02E23 / 2E+23").unwrap() - 3.01).abs() < 1e-12);
+    assert_eq!(eval_str("2.5e2-1e1").unwrap(), 240.0);
+
+    let (var, soln) = solve_equation_from_str("x * 1e3 = 6.02E23 * 1e-20", 0.0001, 50).unwrap();
+    assert_eq!(var, "x");
+    assert!((soln - 6.02).abs() < 0.0001);
+
+    let mut builder = SystemBuilder::new("x + y = 1e1", new_context()).unwrap();
+    assert_eq!(builder.get_vars().len(), 2);
+    builder.try_constrain_with("x - y = 4E0").unwrap();
+
+    let soln = builder.build_system().unwrap().solve(0.0001, 10).unwrap();
+    assert!((soln["x"] - 7.0).abs() < 0.0001);
+}
+
+#[test]
+fn ensure_that_equation_parse_errors_point_into_the_equation()
+{
+    let err = SystemBuilder::new("x + y = 3 * (z - 1", new_context()).unwrap_err();
+    let err = err.downcast_ref::<ParseError>().unwrap();
+
+    assert!(matches!(err.kind, ShuntingYardError::UnclosedParenthesis));
+    assert_eq!(err.token, "(");
+    assert_eq!(err.span, 12..13);
+    assert_eq!(err.diagnostic, "x + y = 3 * (z - 1\n            ^");
+
+    let err = solve_equation_from_str("x + 2 * = 4", 0.0001, 10).unwrap_err();
+    let err = err.downcast_ref::<ParseError>().unwrap();
+
+    assert!(matches!(err.kind, ShuntingYardError::ExpectedArg));
+    assert_eq!(err.span, 6..7);
+}
+
+#[test]
+fn ensure_that_conditionals_only_evaluate_the_branch_they_need()
+{
+    let mut ctx = new_context();
+    ctx.add_var_to_ctx("x", 0);
+
+    // neither of these should divide by zero
+    assert_eq!(eval_str_with_context("x != 0 && 1 / x > 2", &ctx).unwrap(), 0.0);
+    assert_eq!(eval_str_with_context("if(x == 0, 5, 1 / x)", &ctx).unwrap(), 5.0);
+    assert_eq!(eval_str_with_context("x == 0 || 1 / x > 2", &ctx).unwrap(), 1.0);
+}
+
+#[test]
+fn ensure_that_single_unknown_solver_can_solve_equation_with_comparisons()
+{
+    let mut ctx = new_context();
+    ctx.add_const_to_ctx("x", 6.5);
+    ctx.add_const_to_ctx("y", 2.5);
+
+    // same equation as the five-argument `if` test above
+    let soln = solve_equation_with_context(
+        "if(x >= y, i - 1, i + 1) = 0", 
+        &mut ctx, 
+        -1.0, 
+        f64::NEG_INFINITY, 
+        f64::INFINITY, 
+        0.0001, 
+        100
+    ).unwrap();
+
+    assert_eq!(soln.0, "i".to_owned());
+    assert!((soln.1 - 1.0).abs() < 0.001);
+
+    let d_di = parse_expr("if(x >= y, i^2, 0) + (i > 0)", &ctx).unwrap()
+        .derivative("i")
+        .unwrap();
+    assert_eq!(d_di.to_string(), "if(x >= y, 2 * i, 0)");
+}
+
+#[test]
+fn ensure_that_newton_raphson_reports_on_convergence_and_failure()
+{
+    let f = |x: f64| Ok::<f64, std::io::Error>(x * x + 1.0);
+
+    // a high limit would overflow the stack if the solver recursed
+    let err = newton_raphson_report(f, 1.0, &SolverOptions::new().max_iterations(100_000)).unwrap_err();
+    let err = err.downcast_ref::<NotConvergedError<f64>>().unwrap();
+    assert_eq!(err.iterations, 100_000);
+    assert!(err.history.is_empty());
+
+    let err = newton_raphson_report(f, 1.0, &SolverOptions::new().max_iterations(5).record_history(true)).unwrap_err();
+    let err = err.downcast_ref::<NotConvergedError<f64>>().unwrap();
+    assert_eq!(err.history.len(), 5);
+    assert_eq!(err.history[4].guess, err.last_iterate);
+    assert_eq!(err.history[4].residual, err.residual);
+
+    let g = |x: &HashMap<String, f64>| Ok::<f64, std::io::Error>(x["x"] * x["x"] - 4.0);
+    let mut guess = HashMap::from([("x".to_owned(), 1.0)]);
+    let report = multivariate_newton_raphson_report(vec![g], &mut guess, &SolverOptions::new().record_history(true)).unwrap();
+
+    assert!((report.solution["x"] - 2.0).abs() < 0.0001);
+    assert_eq!(report.solution, guess);
+    assert_eq!(report.history.len(), report.iterations);
+    assert!((report.history[0].guess["x"] - 1.0).abs() < 1e-12);
+    assert!(report.residual <= 0.0001 && report.step <= 0.0001);
+}
+
+#[test]
+fn ensure_that_solver_options_are_accepted_by_every_solver()
+{
+    // a relative step keeps the finite difference meaningful for large values
+    let f = |x: f64| Ok::<f64, std::io::Error>(x * x - 1e20);
+    let options = SolverOptions::new()
+        .abs_tol(1e6)
+        .step_tol(1.0)
+        .finite_difference(FiniteDifference::Relative(1e-8));
+    let report = newton_raphson_report(f, 1e9, &options).unwrap();
+    assert!((report.solution - 1e10).abs() < 1.0);
+
+    // relative tolerance is measured against the initial residual
+    let options = SolverOptions::new()
+        .abs_tol(0.0)
+        .rel_tol(0.5)
+        .step_tol(100.0)
+        .record_history(true);
+    let report = newton_raphson_report(|x: f64| Ok::<f64, std::io::Error>(x - 3.0), 1.0, &options).unwrap();
+    assert!(report.residual <= 1.0);
+
+    // damping halves every step of a linear solve
+    let options = SolverOptions::new().damping(0.5).record_history(true);
+    let report = newton_raphson_report(|x: f64| Ok::<f64, std::io::Error>(x - 3.0), 1.0, &options).unwrap();
+    assert!((report.history[1].guess - 2.0).abs() < 1e-9);
+
+    let mut ctx = new_context();
+    let options = SolverOptions::new().method(Method::Illinois);
+    let (_, soln) = solve_equation_with_options("cos(x) = x", &mut ctx, 0.5, 0.0, 1.0, &options).unwrap();
+    assert!((soln.cos() - soln).abs() < 0.0001);
+
+    let mut builder = SystemBuilder::new("x + y = 9", new_context()).unwrap();
+    builder.try_constrain_with("x - y = 4").unwrap();
+    let err = builder.build_system()
+        .unwrap()
+        .solve_with_options(&options)
+        .unwrap_err();
+    assert!(matches!(err.downcast_ref::<SolverOptionsError>(), Some(SolverOptionsError::UnsupportedMethod)));
+
+    // ...as do the solvers for a single method
+    let f = |x: f64| Ok::<f64, std::io::Error>(x - 3.0);
+    let err = newton_raphson_report(f, 1.0, &SolverOptions::new().method(Method::Brent)).unwrap_err();
+    assert!(matches!(err.downcast_ref::<SolverOptionsError>(), Some(SolverOptionsError::UnsupportedMethod)));
+
+    let g = |x: &HashMap<String, f64>| Ok::<f64, std::io::Error>(x["x"] - 3.0);
+    let mut guess = HashMap::from([("x".to_owned(), 1.0)]);
+    let err = multivariate_newton_raphson_report(vec![g], &mut guess, &SolverOptions::new().method(Method::LevenbergMarquardt)).unwrap_err();
+    assert!(matches!(err.downcast_ref::<SolverOptionsError>(), Some(SolverOptionsError::UnsupportedMethod)));
+    let err = levenberg_marquardt(vec![g], &mut guess, &SolverOptions::new().method(Method::Newton)).unwrap_err();
+    assert!(matches!(err.downcast_ref::<SolverOptionsError>(), Some(SolverOptionsError::UnsupportedMethod)));
+
+    let err = SolverOptions::new().abs_tol(-1.0).validate().unwrap_err();
+    assert!(matches!(err.downcast_ref::<SolverOptionsError>(), Some(SolverOptionsError::NonPositiveTolerance)));
+
+    // solvers taking a margin and limit still give the errors they always have
+    let f = |x: f64| Ok::<f64, std::io::Error>(x * x + 1.0);
+    let err = newton_raphson(f, 1.0, 0.0, 10).unwrap_err();
+    assert!(matches!(err.downcast_ref::<NewtonRaphsonSolverError>(), Some(NewtonRaphsonSolverError::NegativeMargin)));
+    let err = newton_raphson(f, 1.0, 0.0001, 0).unwrap_err();
+    assert!(matches!(err.downcast_ref::<NewtonRaphsonSolverError>(), Some(NewtonRaphsonSolverError::ReachedIterationLimit)));
+    let err = newton_raphson(f, 1.0, 0.0001, 10).unwrap_err();
+    assert!(matches!(err.downcast_ref::<NewtonRaphsonSolverError>(), Some(NewtonRaphsonSolverError::ReachedIterationLimit)));
+    assert!(err.downcast_ref::<NotConvergedError<f64>>().is_some());
+}
+
+#[test]
+fn ensure_that_line_search_keeps_systems_from_overshooting()
+{
+    let build = || {
+        let mut builder = SystemBuilder::new("y = 2 * x", new_context()).unwrap();
+        builder.try_constrain_with("arctan(x - 5) = 0").unwrap();
+        builder.build_system().unwrap()
+    };
+
+    // full Newton steps diverge from x = 1...
+    assert!(build().solve(0.0001, 100).is_err());
+
+    // ...but shortened steps do not
+    let soln = build().solve_with_options(&SolverOptions::new().line_search(true)).unwrap();
+    assert!((soln["x"] - 5.0).abs() < 0.001);
+    assert!((soln["y"] - 10.0).abs() < 0.001);
+
+    // full Newton steps bounce between the ends of the domain...
+    let build = || {
+        let mut builder = SystemBuilder::new("y = 2 * x", new_context()).unwrap();
+        builder.try_constrain_with("arctan(x - 5) = 0").unwrap();
+        let mut sys = builder.build_system().unwrap();
+        sys.specify_variable("x", 1.0, 0.0, 10.0);
+        sys
+    };
+    assert!(build().solve_with_options(&SolverOptions::with_margin(0.0001, 100).method(Method::Newton)).is_err());
+
+    // ...while a line search finds the root
+    let soln = build().solve_with_options(&SolverOptions::new().line_search(true)).unwrap();
+    assert!((soln["x"] - 5.0).abs() < 0.001);
+
+    // a finite domain also brackets the root of a single equation
+    let soln = build().solve(0.0001, 100).unwrap();
+    assert!((soln["x"] - 5.0).abs() < 0.001);
+
+    // without a line search, steps still stop at the edge of a one-sided domain. 
+    // From x = 1.9, a full step lands at x = -3.05, where the clamped value was 
+    // evaluated and found to be the root at x = 1 instead.
+    let mut sys = SystemBuilder::new("x^2 - 4 * x + 3 = 0", new_context()).unwrap().build_system().unwrap();
+    sys.specify_variable("x", 1.9, 1.0, f64::INFINITY);
+    let soln = sys.solve(0.0001, 100).unwrap();
+    assert!((soln["x"] - 1.0).abs() < 0.001);
+}
+
+#[test]
+fn ensure_that_levenberg_marquardt_solves_systems_with_singular_jacobians()
+{
+    let build = || {
+        let mut builder = SystemBuilder::new("x * y = 2", new_context()).unwrap();
+        builder.try_constrain_with("x + 2 * y = 5").unwrap();
+        let mut sys = builder.build_system().unwrap();
+
+        // the jacobian is singular wherever x = 2 * y
+        sys.specify_variable("x", 2.0, f64::NEG_INFINITY, f64::INFINITY);
+        sys
+    };
+
+    assert!(build().solve(0.0001, 100).is_err());
+
+    let options = SolverOptions::new().method(Method::LevenbergMarquardt);
+    let soln = build().solve_with_options(&options).unwrap();
+    assert!((soln["x"] * soln["y"] - 2.0).abs() < 0.001);
+    assert!((soln["x"] + 2.0 * soln["y"] - 5.0).abs() < 0.001);
+
+    // the same solver handles well-behaved systems too
+    let mut builder = SystemBuilder::new("x + y = 9", new_context()).unwrap();
+    builder.try_constrain_with("x - y = 4").unwrap();
+    let soln = builder.build_system().unwrap().solve_with_options(&options).unwrap();
+    assert!((soln["x"] - 6.5).abs() < 0.001);
+    assert!((soln["y"] - 2.5).abs() < 0.001);
+}
+
+#[test]
+fn ensure_that_broyden_updates_save_function_evaluations()
+{
+    type Equation<'a> = dyn Fn(&HashMap<String, f64>) -> Result<f64, std::io::Error> + 'a;
+
+    let calls = std::cell::Cell::new(0);
+    let f1 = |x: &HashMap<String, f64>| {
+        calls.set(calls.get() + 1);
+        Ok::<f64, std::io::Error>(x["x"].powi(2) + x["y"].powi(2) - 5.0)
+    };
+    let f2 = |x: &HashMap<String, f64>| {
+        calls.set(calls.get() + 1);
+        Ok::<f64, std::io::Error>(x["x"] * x["y"] - 2.0)
+    };
+    let solve = |method| {
+        calls.set(0);
+        let mut guess = HashMap::from([("x".to_owned(), 1.2), ("y".to_owned(), 1.8)]);
+        let f: Vec<&Equation> = vec![&f1, &f2];
+        let report = multivariate_newton_raphson_report(f, &mut guess, &SolverOptions::new().method(method)).unwrap();
+        (report.solution, calls.get())
+    };
+
+    let (newton, newton_calls) = solve(Method::Newton);
+    let (broyden, broyden_calls) = solve(Method::Broyden);
+
+    for soln in [newton, broyden]
+    {
+        assert!((soln["x"] - 1.0).abs() < 0.001);
+        assert!((soln["y"] - 2.0).abs() < 0.001);
+    }
+    assert!(broyden_calls < newton_calls);
+}
+
+#[test]
+fn ensure_that_newton_steps_are_found_by_factorization()
+{
+    // inverting the jacobian of this system used to fail
+    let mut builder = SystemBuilder::new("x + y + z = 6", new_context()).unwrap();
+    builder.try_constrain_with("2 * x - y + 3 * z = 9").unwrap();
+    builder.try_constrain_with("x * y - z = -1").unwrap();
+    let soln = builder.build_system().unwrap().solve(0.0001, 100).unwrap();
+    assert!((soln["x"] + soln["y"] + soln["z"] - 6.0).abs() < 0.001);
+    assert!((2.0 * soln["x"] - soln["y"] + 3.0 * soln["z"] - 9.0).abs() < 0.001);
+    assert!((soln["x"] * soln["y"] - soln["z"] + 1.0).abs() < 0.001);
+
+    // x and y only ever appear as x + y
+    let mut builder = SystemBuilder::new("x + y + z = 3", new_context()).unwrap();
+    builder.try_constrain_with("2 * x + 2 * y = 4").unwrap();
+    builder.try_constrain_with("z = 1").unwrap();
+    let err = builder.build_system().unwrap().solve(0.0001, 100).unwrap_err();
+    let singular = err.downcast_ref::<SingularJacobianError>().unwrap();
+    assert_eq!(singular.rank, 1);
+    assert_eq!(singular.size, 2);
+    assert_eq!(singular.vars, vec!["x", "y"]);
+
+    // the jacobian is singular wherever x = 2 * y
+    let mut builder = SystemBuilder::new("x * y = 2", new_context()).unwrap();
+    builder.try_constrain_with("x + 2 * y = 5").unwrap();
+    let mut sys = builder.build_system().unwrap();
+    sys.specify_variable("x", 2.0, f64::NEG_INFINITY, f64::INFINITY);
+    let err = sys.solve(0.0001, 100).unwrap_err();
+    assert_eq!(err.downcast_ref::<SingularJacobianError>().unwrap().vars, vec!["x", "y"]);
+}
+
+#[test]
+fn ensure_that_convergence_rules_are_applied_as_documented()
+{
+    // a small residual does not excuse a large step in either direction
+    let f = |x: f64| Ok::<f64, std::io::Error>(1e-5 * (x - 10.0));
+    let report = newton_raphson_report(f, 0.0, &SolverOptions::new().abs_tol(0.001)).unwrap();
+    assert!((report.solution - 10.0).abs() < 1e-6);
+    assert_eq!(report.iterations, 2);
+
+    // the step tolerance can be relative to the size of the guess
+    let f = |x: f64| Ok::<f64, std::io::Error>(x - 1e6);
+    let options = SolverOptions::new().abs_tol(1.0).step_tol(0.0);
+    assert!(newton_raphson_report(f, 1e6 + 0.5, &options).is_err());
+    let report = newton_raphson_report(f, 1e6 + 0.5, &options.rel_step_tol(1e-6)).unwrap();
+    assert_eq!(report.iterations, 1);
+
+    // residuals and steps are both (3, 4) at the initial guess
+    let f1 = |x: &HashMap<String, f64>| Ok::<f64, std::io::Error>(x["x"] - 3.0);
+    let f2 = |x: &HashMap<String, f64>| Ok::<f64, std::io::Error>(x["y"] + 4.0);
+    let solve = |norm, abs_tol, step_tol| {
+        let mut guess = HashMap::from([("x".to_owned(), 0.0), ("y".to_owned(), 0.0)]);
+        let options = SolverOptions::new()
+            .norm(norm)
+            .abs_tol(abs_tol)
+            .step_tol(step_tol)
+            .max_iterations(1);
+        multivariate_newton_raphson_report(vec![f1, f2], &mut guess, &options)
+    };
+
+    for (abs_tol, step_tol) in [(4.5, 10.0), (10.0, 4.5)]
+    {
+        let l1 = solve(Norm::L1, abs_tol, step_tol).unwrap_err();
+        let l1 = l1.downcast_ref::<NotConvergedError<HashMap<String, f64>>>().unwrap();
+        assert_eq!(l1.residual, 7.0);
+        assert!((l1.step - 7.0).abs() < 1e-9);
+
+        let l2 = solve(Norm::L2, abs_tol, step_tol).unwrap_err();
+        let l2 = l2.downcast_ref::<NotConvergedError<HashMap<String, f64>>>().unwrap();
+        assert_eq!(l2.residual, 5.0);
+        assert!((l2.step - 5.0).abs() < 1e-9);
+
+        let linf = solve(Norm::LInf, abs_tol, step_tol).unwrap();
+        assert_eq!(linf.residual, 4.0);
+        assert!((linf.step - 4.0).abs() < 1e-9);
+    }
+
+    // at least one step tolerance must be positive
+    assert!(SolverOptions::new().step_tol(0.0).rel_step_tol(1e-6).validate().is_ok());
+    assert!(SolverOptions::new().step_tol(0.0).validate().is_err());
+}
+
+#[test]
+fn ensure_that_systems_are_solved_in_blocks()
+{
+    let mut builder = SystemBuilder::new("c + d = b", new_context()).unwrap();
+    builder.try_fully_constrain_with(vec!["c - d = a", "a = 2", "b = a ^ 2"]).unwrap();
+    let sys = builder.build_system().unwrap();
+
+    let blocks: Vec<Vec<usize>> = sys.blocks().into_iter().map(|block| block.equations).collect();
+    assert_eq!(blocks, vec![vec![2], vec![3], vec![0, 1]]);
+    assert_eq!(sys.blocks()[2], Block { equations: vec![0, 1], vars: vec!["c".to_owned(), "d".to_owned()] });
+
+    let soln = sys.solve(0.0001, 100).unwrap();
+    assert!((soln["a"] - 2.0).abs() < 0.001);
+    assert!((soln["b"] - 4.0).abs() < 0.001);
+    assert!((soln["c"] - 3.0).abs() < 0.001);
+    assert!((soln["d"] - 1.0).abs() < 0.001);
+}
+
+#[test]
+fn ensure_that_systems_can_be_built_from_equations_in_any_order()
+{
+    let equations = ["x + y = 3", "z + w = 7", "x - y = 1", "z - w = 1"];
+
+    // adding equations one at a time stops at the first pair of unknowns, leaving out z and w
+    let mut builder = SystemBuilder::new(equations[0], new_context()).unwrap();
+    assert!(builder.try_fully_constrain_with(equations[1..].to_vec()).unwrap());
+    assert_eq!(builder.get_vars().len(), 2);
+
+    for first in 0..equations.len()
+    {
+        let mut reordered = equations.to_vec();
+        reordered.rotate_left(first);
+        reordered.swap(1, 2);
+
+        let soln = SystemBuilder::from_equations(&reordered, new_context()).unwrap()
+            .build_system().unwrap()
+            .solve(0.0001, 100).unwrap();
+        assert!((soln["x"] - 2.0).abs() < 0.001);
+        assert!((soln["y"] - 1.0).abs() < 0.001);
+        assert!((soln["z"] - 4.0).abs() < 0.001);
+        assert!((soln["w"] - 3.0).abs() < 0.001);
+    }
+
+    let err = SystemBuilder::from_equations(&["x + y = 3", "x - y = 1", "x = 2"], new_context()).unwrap_err();
+    assert!(matches!(err.downcast_ref::<SystemStructureError>(), Some(SystemStructureError::NotSquare)));
+
+    // x = 2 and x - 2 * y = 0 leave nothing to find z or w with
+    let err = SystemBuilder::from_equations(&["x = 2", "x - 2 * y = 0", "y = 1", "z + w = 7"], new_context()).unwrap_err();
+    assert!(matches!(err.downcast_ref::<SystemStructureError>(), Some(SystemStructureError::StructurallySingular)));
+}
+
+#[test]
+fn ensure_that_structural_diagnostics_explain_unsolvable_systems()
+{
+    // four equations for a, b, and c, and one equation for p and q
+    let equations = ["a = 1", "b = 2", "c = a + b", "c = 2 * a", "p + q = b"];
+    let diagnosis = diagnose_equations(&equations, &new_context()).unwrap();
+
+    assert!(!diagnosis.is_well_constrained());
+    assert_eq!(diagnosis.over_constrained_equations, vec![0, 1, 2, 3]);
+    assert_eq!(diagnosis.over_constrained_vars, vec!["a", "b", "c"]);
+    assert_eq!(diagnosis.redundant_equations.len(), 1);
+    assert!(diagnosis.over_constrained_equations.contains(&diagnosis.redundant_equations[0]));
+
+    assert_eq!(diagnosis.under_constrained_vars, vec!["p", "q"]);
+    assert_eq!(diagnosis.under_constrained_equations, vec![4]);
+    assert_eq!(diagnosis.suggested_fixes.len(), 1);
+    assert!(diagnosis.under_constrained_vars.contains(&diagnosis.suggested_fixes[0]));
+
+    // removing the suggested equation and fixing the suggested variable repairs the system
+    let fix = diagnosis.suggested_fixes[0].clone();
+    let mut ctx = new_context();
+    ctx.add_var_to_ctx(&fix, 1.0);
+    let repaired: Vec<&str> = equations.iter()
+        .enumerate()
+        .filter(|(i, _)| !diagnosis.redundant_equations.contains(i))
+        .map(|(_, eqn)| *eqn)
+        .collect();
+    assert!(diagnose_equations(&repaired, &ctx).unwrap().is_well_constrained());
+    assert!(SystemBuilder::from_equations(&repaired, ctx).is_ok());
+
+    // builders explain why they can't build a system
+    let builder = SystemBuilder::new("x + y = 3", new_context()).unwrap();
+    assert_eq!(builder.diagnose().under_constrained_vars.len(), 2);
+    assert!(builder.build_system().is_none());
+}
+
+#[test]
+fn ensure_that_over_determined_systems_are_fit_by_least_squares()
+{
+    let sys = SystemBuilder::least_squares_system(
+        &["m * 0 + b = 1", "m * 1 + b = 3", "m * 2 + b = 4", "m * 3 + b = 7"], 
+        new_context()
+    ).unwrap();
+    let fit = sys.fit(&SolverOptions::new().step_tol(1e-9)).unwrap();
+
+    assert!((fit.solution["m"] - 1.9).abs() < 1e-6);
+    assert!((fit.solution["b"] - 0.9).abs() < 1e-6);
+    for (residual, expected) in fit.residuals.iter().zip([-0.1, -0.2, 0.7, -0.4])
+    {
+        assert!((residual - expected).abs() < 1e-6);
+    }
+
+    // s² = 0.7 / (4 - 2), and (JᵀJ)⁻¹ = [[0.7, -0.3], [-0.3, 0.2]] for b and m
+    assert_eq!(fit.vars, vec!["b", "m"]);
+    let expected = [[0.245, -0.105], [-0.105, 0.07]];
+    for (row, expected_row) in fit.covariance.iter().zip(expected)
+    {
+        for (cov, expected_cov) in row.iter().zip(expected_row)
+        {
+            assert!((cov - expected_cov).abs() < 1e-6);
+        }
+    }
+
+    // square systems fit exactly, leaving nothing to estimate the covariance from
+    let sys = SystemBuilder::least_squares_system(&["x + y = 9", "x - y = 4"], new_context()).unwrap();
+    let fit = sys.fit(&SolverOptions::new()).unwrap();
+    assert!((fit.solution["x"] - 6.5).abs() < 0.001);
+    assert!(fit.covariance[0][0].is_nan());
+
+    let err = SystemBuilder::least_squares_system(&["x + y = 9"], new_context()).err().unwrap();
+    assert!(matches!(err.downcast_ref::<SystemStructureError>(), Some(SystemStructureError::TooFewEquations)));
+
+    let err = SystemBuilder::least_squares_system(&["x = 1", "x = 2", "y + z = 3"], new_context()).err().unwrap();
+    assert!(matches!(err.downcast_ref::<SystemStructureError>(), Some(SystemStructureError::StructurallySingular)));
+}
+
+#[test]
+fn ensure_that_under_determined_systems_are_solved_nearest_the_guess()
+{
+    let mut builder = SystemBuilder::new("x + y + z = 6", new_context()).unwrap();
+    builder.try_constrain_with("x - y = 0").unwrap();
+    assert!(!builder.is_fully_constrained());
+
+    let mut sys = builder.build_underdetermined_system().unwrap();
+    sys.specify_variable("x", 0.0, f64::NEG_INFINITY, f64::INFINITY);
+    sys.specify_variable("y", 0.0, f64::NEG_INFINITY, f64::INFINITY);
+    sys.specify_variable("z", 0.0, f64::NEG_INFINITY, f64::INFINITY);
+    let report = sys.solve_minimum_norm(&SolverOptions::new()).unwrap();
+
+    // the line x = y, z = 6 - 2x comes nearest to the origin at (2, 2, 2)
+    for var in ["x", "y", "z"]
+    {
+        assert!((report.solution[var] - 2.0).abs() < 0.001);
+    }
+
+    // ...and leaves the solution free to move along (1, 1, -2)
+    assert_eq!(report.free_directions.len(), 1);
+    let free = &report.free_directions[0];
+    assert!((free["x"] + free["y"] + free["z"]).abs() < 0.001);
+    assert!((free["x"] - free["y"]).abs() < 0.001);
+    assert!((free["x"] * free["x"] + free["y"] * free["y"] + free["z"] * free["z"] - 1.0).abs() < 0.001);
+
+    // a redundant equation leaves two free directions instead of one
+    let build = |redundant: &str| {
+        let mut builder = SystemBuilder::new("x + y + z = 2", new_context()).unwrap();
+        builder.try_constrain_with(redundant).unwrap();
+        let mut sys = builder.build_underdetermined_system().unwrap();
+        sys.specify_variable("x", 0.0, f64::NEG_INFINITY, f64::INFINITY);
+        sys.specify_variable("y", 0.0, f64::NEG_INFINITY, f64::INFINITY);
+        sys.specify_variable("z", 3.0, f64::NEG_INFINITY, f64::INFINITY);
+        sys
+    };
+    let report = build("2*x + 2*y + 2*z = 4").solve_minimum_norm(&SolverOptions::new()).unwrap();
+    assert!((report.solution["x"] + 1.0 / 3.0).abs() < 0.001);
+    assert!((report.solution["z"] - 8.0 / 3.0).abs() < 0.001);
+    assert_eq!(report.free_directions.len(), 2);
+    for free in &report.free_directions
+    {
+        assert!((free["x"] + free["y"] + free["z"]).abs() < 0.001);
+    }
+
+    // ...but cannot be solved if it disagrees with the others
+    let err = build("2*x + 2*y + 2*z = 5").solve_minimum_norm(&SolverOptions::new()).unwrap_err();
+    assert!(err.downcast_ref::<NotConvergedError<HashMap<String, f64>>>().is_some());
+
+    // no step can be found where no equation changes to first order
+    let mut sys = SystemBuilder::new("x * y = 1", new_context()).unwrap().build_underdetermined_system().unwrap();
+    sys.specify_variable("x", 0.0, f64::NEG_INFINITY, f64::INFINITY);
+    sys.specify_variable("y", 0.0, f64::NEG_INFINITY, f64::INFINITY);
+    let err = sys.solve_minimum_norm(&SolverOptions::new()).unwrap_err();
+    let err = err.downcast_ref::<SingularJacobianError>().unwrap();
+    assert_eq!((err.rank, err.size), (0, 2));
+    assert_eq!(err.vars, ["x", "y"]);
+
+    // z cannot satisfy both of these however x and y move
+    let mut builder = SystemBuilder::new("x + y + z = 6", new_context()).unwrap();
+    builder.try_constrain_with("z = 1").unwrap();
+    builder.try_constrain_with("z = 2").unwrap();
+    assert!(builder.build_underdetermined_system().is_none());
+}